@@ -2,11 +2,11 @@ use client::communication::{FromUiCommunication, ToUICommunication};
 use client::ui::{ClientState, Ui, UiState};
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use eframe::egui;
-use message::ChatResponse;
+use message::{ChatResponse, FileInfo, Profile, ServerType};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 fn main() -> eframe::Result {
     env_logger::init();
@@ -51,21 +51,85 @@ fn simulate_worker(
             response: ChatResponse::MessageFrom {
                 from: 1,
                 message: "Ciao".to_string().into_bytes(),
+                id: 0,
             },
         })
         .unwrap();
+    // the simulated servers: 7 relays the chats, 8 serves documents and 9 stores files
+    let kinds = [
+        (7, ServerType::ChatServer),
+        (8, ServerType::TextServer),
+        (9, ServerType::MediaServer),
+    ];
+    for (server, kind) in kinds {
+        tx_to_ui
+            .send(ToUICommunication::ServerType { server, kind })
+            .unwrap();
+    }
+    // files stored by the simulated media server 9
+    let mut files: HashMap<String, Vec<u8>> = HashMap::new();
+    files.insert("notes.txt".to_string(), b"Appunti".to_vec());
+    let file_list = |files: &HashMap<String, Vec<u8>>| ToUICommunication::MediaResponse {
+        server: 9,
+        response: ChatResponse::FileList(
+            files
+                .iter()
+                .map(|(name, data)| FileInfo {
+                    name: name.clone(),
+                    size: data.len() as u64,
+                    owner: 1,
+                })
+                .collect(),
+        ),
+    };
+    tx_to_ui.send(file_list(&files)).unwrap();
+    // documents served by the simulated text server 8
+    let documents: HashMap<String, String> = [
+        ("benvenuto.txt", "Benvenuti nel server di testo."),
+        ("regole.txt", "1. Siate gentili\n2. Niente spam"),
+    ]
+    .into_iter()
+    .map(|(name, text)| (name.to_string(), text.to_string()))
+    .collect();
+    let mut names: Vec<_> = documents.keys().cloned().collect();
+    names.sort();
+    tx_to_ui
+        .send(ToUICommunication::TextResponse {
+            server: 8,
+            response: ChatResponse::DocumentList(names.clone()),
+        })
+        .unwrap();
     loop {
         let message = rx_from_ui.recv().unwrap();
         match message {
             FromUiCommunication::SendChatMessage {
                 to_client: _,
                 message: _,
+                id,
             } => {
+                tx_to_ui
+                    .send(ToUICommunication::MessageDeliveredToServer(id))
+                    .unwrap();
                 tx_to_ui
                     .send(ToUICommunication::ChatResponse {
                         response: ChatResponse::MessageFrom {
                             from: 1,
                             message: "Messaggio ricevuto".to_string().into_bytes(),
+                            id: 0,
+                        },
+                    })
+                    .unwrap();
+            }
+            FromUiCommunication::MarkRead { .. }
+            | FromUiCommunication::EditMessage { .. }
+            | FromUiCommunication::DeleteMessage { .. } => {}
+            FromUiCommunication::GetHistory { with, before: _ } => {
+                tx_to_ui
+                    .send(ToUICommunication::ChatResponse {
+                        response: ChatResponse::History {
+                            with,
+                            entries: vec![],
+                            more: false,
                         },
                     })
                     .unwrap();
@@ -76,6 +140,7 @@ fn simulate_worker(
                         response: ChatResponse::MessageFrom {
                             from: 1,
                             message: "Ricaricare Client List".to_string().into_bytes(),
+                            id: 0,
                         },
                     })
                     .unwrap();
@@ -86,10 +151,152 @@ fn simulate_worker(
                         response: ChatResponse::MessageFrom {
                             from: 1,
                             message: "Ricaricare la topologia".to_string().into_bytes(),
+                            id: 0,
                         },
                     })
                     .unwrap();
             }
+            FromUiCommunication::Logout => {
+                tx_to_ui
+                    .send(ToUICommunication::ChatResponse {
+                        response: ChatResponse::ClientLeft(1),
+                    })
+                    .unwrap();
+            }
+            FromUiCommunication::Login => {
+                tx_to_ui
+                    .send(ToUICommunication::ChatResponse {
+                        response: ChatResponse::ClientList(vec![1, 2, 3]),
+                    })
+                    .unwrap();
+            }
+            FromUiCommunication::CreateRoom(room) | FromUiCommunication::JoinRoom(room) => {
+                tx_to_ui
+                    .send(ToUICommunication::ChatResponse {
+                        response: ChatResponse::RoomList(vec![(room, vec![0, 1])]),
+                    })
+                    .unwrap();
+            }
+            FromUiCommunication::LeaveRoom(_) => {
+                tx_to_ui
+                    .send(ToUICommunication::ChatResponse {
+                        response: ChatResponse::RoomList(vec![]),
+                    })
+                    .unwrap();
+            }
+            FromUiCommunication::SendBroadcast { message: _ } => {
+                tx_to_ui
+                    .send(ToUICommunication::ChatResponse {
+                        response: ChatResponse::BroadcastReport {
+                            reached: vec![1, 2],
+                            unreachable: vec![3],
+                        },
+                    })
+                    .unwrap();
+            }
+            FromUiCommunication::SendRoomMessage { room, message: _ } => {
+                tx_to_ui
+                    .send(ToUICommunication::ChatResponse {
+                        response: ChatResponse::MessageFromRoom {
+                            room,
+                            from: 1,
+                            message: "Messaggio ricevuto nella stanza".to_string().into_bytes(),
+                        },
+                    })
+                    .unwrap();
+            }
+            FromUiCommunication::SetPresence(presence) => {
+                tx_to_ui
+                    .send(ToUICommunication::Presence {
+                        client: 1,
+                        presence,
+                        last_seen: SystemTime::now(),
+                    })
+                    .unwrap();
+            }
+            FromUiCommunication::Typing(_) => {
+                // the other client answers while we type
+                tx_to_ui.send(ToUICommunication::Typing(1)).unwrap();
+            }
+            FromUiCommunication::SetProfile(profile) => {
+                tx_to_ui
+                    .send(ToUICommunication::ChatResponse {
+                        response: ChatResponse::Directory(vec![
+                            (0, profile),
+                            (
+                                1,
+                                Profile {
+                                    name: "Bob".to_string(),
+                                    status: "Disponibile".to_string(),
+                                },
+                            ),
+                        ]),
+                    })
+                    .unwrap();
+            }
+            FromUiCommunication::ListFiles => {
+                tx_to_ui.send(file_list(&files)).unwrap();
+            }
+            FromUiCommunication::UploadFile { server, name, data } => {
+                let total = 3;
+                for done in 1..=total {
+                    thread::sleep(Duration::from_millis(500));
+                    tx_to_ui
+                        .send(ToUICommunication::Transfer {
+                            server,
+                            session: 0,
+                            name: Some(name.clone()),
+                            upload: true,
+                            done,
+                            total,
+                        })
+                        .unwrap();
+                }
+                let info = FileInfo {
+                    name: name.clone(),
+                    size: data.len() as u64,
+                    owner: 0,
+                };
+                files.insert(name, data);
+                tx_to_ui
+                    .send(ToUICommunication::MediaResponse {
+                        server,
+                        response: ChatResponse::FileStored(info),
+                    })
+                    .unwrap();
+            }
+            FromUiCommunication::ListDocuments => {
+                tx_to_ui
+                    .send(ToUICommunication::TextResponse {
+                        server: 8,
+                        response: ChatResponse::DocumentList(names.clone()),
+                    })
+                    .unwrap();
+            }
+            FromUiCommunication::GetDocument { server, name } => {
+                let response = match documents.get(&name) {
+                    Some(text) => ChatResponse::Document {
+                        name,
+                        text: text.clone(),
+                    },
+                    None => ChatResponse::ErrorWrongDocument(name),
+                };
+                tx_to_ui
+                    .send(ToUICommunication::TextResponse { server, response })
+                    .unwrap();
+            }
+            FromUiCommunication::DownloadFile { server, name } => {
+                let response = match files.get(&name) {
+                    Some(data) => ChatResponse::File {
+                        name,
+                        data: data.clone(),
+                    },
+                    None => ChatResponse::ErrorWrongFile(name),
+                };
+                tx_to_ui
+                    .send(ToUICommunication::MediaResponse { server, response })
+                    .unwrap();
+            }
         }
     }
 }
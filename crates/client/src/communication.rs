@@ -1,24 +1,99 @@
-use message::ChatResponse;
+use message::{ChatResponse, FileName, MessageId, Presence, Profile, RoomName};
+use std::time::SystemTime;
 use wg_2024::network::NodeId;
 
-#[allow(unused)]
-#[derive(Debug, Clone)]
-pub enum ServerType {
-    ChatServer,
-    MediaServer,
-}
+pub use message::ServerType;
 
 #[allow(unused)]
 #[derive(Debug, Clone)]
 pub enum FromUiCommunication {
-    SendChatMessage { to_client: NodeId, message: String },
+    SendChatMessage {
+        to_client: NodeId,
+        message: String,
+        id: MessageId,
+    },
+    MarkRead {
+        to_client: NodeId,
+        ids: Vec<MessageId>,
+    },
+    GetHistory {
+        with: NodeId,
+        before: Option<u64>,
+    },
     RefreshTopology,
     AskClientList,
+    Logout,
+    Login,
+    CreateRoom(RoomName),
+    JoinRoom(RoomName),
+    LeaveRoom(RoomName),
+    SendRoomMessage {
+        room: RoomName,
+        message: String,
+    },
+    SendBroadcast {
+        message: String,
+    },
+    SetProfile(Profile),   // sent to every server, again at each registration
+    SetPresence(Presence), // Online or Away, like the profile
+    Typing(NodeId),        // the input of the direct chat with the client changed
+    ListFiles,             // asked to every media server
+    UploadFile {
+        server: NodeId,
+        name: FileName,
+        data: Vec<u8>,
+    },
+    DownloadFile {
+        server: NodeId,
+        name: FileName,
+    },
+    ListDocuments, // asked to every text server
+    GetDocument {
+        server: NodeId,
+        name: FileName,
+    },
+    EditMessage {
+        to_client: NodeId,
+        id: MessageId, // of a direct message sent to `to_client`
+        message: String,
+    },
+    DeleteMessage {
+        to_client: NodeId,
+        id: MessageId,
+    },
 }
 
 #[allow(unused)]
 #[derive(Debug, Clone)]
 pub enum ToUICommunication {
-    ChatResponse { response: ChatResponse },
-    MessageDeliveredToServer(u64),
+    ChatResponse {
+        response: ChatResponse,
+    },
+    MessageDeliveredToServer(MessageId), // accepted by the server, the other receipts are ChatResponse
+    Presence {
+        client: NodeId,
+        presence: Presence,
+        last_seen: SystemTime,
+    },
+    Typing(NodeId), // the client is writing to us
+    MediaResponse {
+        server: NodeId,
+        response: ChatResponse, // FileList, FileStored, File or ErrorWrongFile
+    },
+    ServerType {
+        server: NodeId,
+        kind: ServerType, // sent when the server answers the ServerType request
+    },
+    TextResponse {
+        server: NodeId,
+        response: ChatResponse, // DocumentList, Document or ErrorWrongDocument
+    },
+    Transfer {
+        server: NodeId,
+        session: u64,
+        name: Option<FileName>, // known only for uploads
+        upload: bool,
+        done: u64, // fragments acked by the server, or received from it
+        total: u64,
+    },
 }
@@ -0,0 +1,36 @@
+use crypto_box::aead::{Aead, AeadCore, OsRng};
+use crypto_box::{SalsaBox, SecretKey};
+use message::{Nonce, PublicKey};
+
+/// Key pair of a client, messages are sealed with `crypto_box` (X25519 and XSalsa20-Poly1305):
+/// only the recipient can open them and it knows they come from the owner of the sender key
+pub struct KeyPair {
+    secret: SecretKey,
+}
+
+impl KeyPair {
+    pub fn generate() -> Self {
+        Self {
+            secret: SecretKey::generate(&mut OsRng),
+        }
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.secret.public_key().to_bytes()
+    }
+
+    pub fn encrypt(&self, recipient: &PublicKey, plaintext: &[u8]) -> (Nonce, Vec<u8>) {
+        let sealer = SalsaBox::new(&(*recipient).into(), &self.secret);
+        let nonce = SalsaBox::generate_nonce(&mut OsRng);
+        let ciphertext = sealer
+            .encrypt(&nonce, plaintext)
+            .expect("Failed to encrypt message");
+        (nonce.into(), ciphertext)
+    }
+
+    /// `None` if the message was not sealed by `sender` for us or it was modified
+    pub fn decrypt(&self, sender: &PublicKey, nonce: &Nonce, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        let opener = SalsaBox::new(&(*sender).into(), &self.secret);
+        opener.decrypt(&(*nonce).into(), ciphertext).ok()
+    }
+}
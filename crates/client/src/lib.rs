@@ -1,5 +1,6 @@
 mod channel;
 pub mod communication;
+mod crypto;
 mod message;
 mod network;
 mod test;
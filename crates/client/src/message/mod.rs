@@ -1,30 +1,75 @@
 mod tests;
 
 use crate::channel::ChannelManager;
-use crate::communication::ToUICommunication::ChatResponse;
+use crate::communication::ToUICommunication;
+use crate::communication::ToUICommunication::{ChatResponse, MessageDeliveredToServer};
+use crate::crypto::KeyPair;
 use hashbrown::{HashMap, HashSet};
 use log::{debug, error, info, warn};
-use message::ChatResponse::{ClientList, ErrorWrongClientId};
-use message::NodeEvent::{CreateMessage, MessageRecv};
-use message::{ChatRequest, RecvMessageWrapper, SentMessageWrapper};
+use message::expiry::stale_keys;
+use message::ChatResponse::{
+    BroadcastReport, ClientKeys, ClientLeft, ClientList, Document, DocumentList, EncryptedFrom,
+    ErrorIncompatible, ErrorWrongClientId, ErrorWrongDocument, ErrorWrongFile, ErrorWrongRoom,
+    ErrorWrongSender, File, FileList, FileStored, History, MessageEdited, MessageFrom,
+    MessageQueued, Presence, Receipt, RoomList, Typing, Welcome, WithReceipt,
+};
+use message::NodeEvent::{
+    CreateMessage, MessageCorrupted, MessageRecv, MessageRecvAbandoned, MessageSentAbandoned,
+};
+use message::{
+    AbandonedSessions, BufferLimits, Capabilities, ChatRequest, Features, FileName,
+    FragmentationOptions, HistoryEntry, MessageId, Nonce, PublicKey, ReceiptStatus,
+    RecvMessageWrapper, RoomName, SentMessageWrapper, ServerType,
+};
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::time::SystemTime;
 use wg_2024::network::NodeId;
 use wg_2024::packet::{Ack, Fragment, Nack, NackType, Packet};
 
 type Session = u64;
 
+/// What happened to a received fragment, tells the worker how to answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FragmentStatus {
+    /// New fragment, these indices must be acked: the fragment itself and the data
+    /// fragments rebuilt from parity
+    Stored(Vec<u64>),
+    /// Already received (or its message is already complete), nothing to do
+    Duplicate,
+    /// Last fragment of a message that failed the integrity check, the whole message
+    /// (this many fragments) must be requested again
+    Corrupted(u64),
+}
+
 pub struct MessagerManager {
     my_id: NodeId,
 
     channels: Rc<RefCell<ChannelManager>>,
 
     pub clients: HashMap<NodeId, HashSet<NodeId>>, // client -> server
+    pub departed: HashMap<NodeId, HashSet<NodeId>>, // client that left -> servers that queue messages for it
+    pub public_keys: HashMap<NodeId, PublicKey>,    // client -> key, messages to it are encrypted
+    keys: KeyPair,
+    pub rooms: HashMap<RoomName, NodeId>, // room -> server hosting it
+    pub media_servers: HashSet<NodeId>,   // servers that answered with a FileList
+    uploads: HashMap<Session, FileName>,  // sessions of the files being uploaded
 
     buffer: HashMap<NodeId, Vec<Packet>>, // server -> buffer
     msg_wrapper: HashMap<Session, SentMessageWrapper>,
     rcv_wrapper: HashMap<(Session, NodeId), RecvMessageWrapper>,
+    completed: HashMap<(Session, NodeId), SystemTime>, // late fragments (e.g. parity) of these are ignored
+    pub outbox: Vec<(NodeId, ChatRequest)>, // server -> request to send because of a received message
+    pub discovered: Vec<(NodeId, ServerType)>, // servers that answered the ServerType request
     last_session: Session,
+
+    pub capabilities: Capabilities,
+    pub servers: HashMap<NodeId, Capabilities>, // capabilities agreed with each server
+    pub incompatible: HashSet<NodeId>,          // servers without a common protocol version
+    pub registered_on: HashSet<NodeId>, // chat servers whose ClientList listed us since the registration
+    pub fragmentation: FragmentationOptions,
+    pub limits: BufferLimits,
+    pub abandoned: AbandonedSessions,
 }
 
 impl MessagerManager {
@@ -33,19 +78,254 @@ impl MessagerManager {
             my_id,
             channels,
             clients: HashMap::new(),
+            departed: HashMap::new(),
+            public_keys: HashMap::new(),
+            keys: KeyPair::generate(),
+            rooms: HashMap::new(),
+            media_servers: HashSet::new(),
+            uploads: HashMap::new(),
             buffer: HashMap::new(),
             msg_wrapper: HashMap::new(),
             rcv_wrapper: HashMap::new(),
+            completed: HashMap::new(),
+            outbox: Vec::new(),
+            discovered: Vec::new(),
             last_session: 0,
+            capabilities: Capabilities::default(),
+            servers: HashMap::new(),
+            incompatible: HashSet::new(),
+            registered_on: HashSet::new(),
+            fragmentation: FragmentationOptions::default(),
+            limits: BufferLimits::default(),
+            abandoned: AbandonedSessions::default(),
+        }
+    }
+
+    /// Drop the messages that are stuck for too long or above the `limits` caps,
+    /// every abandoned session is reported to the controller
+    pub fn collect_garbage(&mut self) {
+        let now = SystemTime::now();
+
+        let stale = stale_keys(
+            &self.rcv_wrapper,
+            self.limits.recv_timeout,
+            self.limits.max_recv,
+            now,
+        );
+        for key in stale {
+            if let Some(wrapper) = self.rcv_wrapper.remove(&key) {
+                warn!(
+                    "{}: abandoned partial message with session {} from {}, {}/{} fragments arrived",
+                    self.my_id,
+                    key.0,
+                    key.1,
+                    wrapper.arrived.len(),
+                    wrapper.total_n_fragments
+                );
+                self.abandoned.recv += 1;
+                self.channels
+                    .borrow()
+                    .tx_ctrl
+                    .send(MessageRecvAbandoned(wrapper))
+                    .expect("Failed to transmit to CONTROLLER");
+            }
+        }
+
+        let stale = stale_keys(
+            &self.msg_wrapper,
+            self.limits.sent_timeout,
+            self.limits.max_sent,
+            now,
+        );
+        for session in stale {
+            self.uploads.remove(&session);
+            if let Some(wrapper) = self.msg_wrapper.remove(&session) {
+                warn!(
+                    "{}: abandoned message with session {} to {}, {}/{} fragments acked",
+                    self.my_id,
+                    session,
+                    wrapper.destination,
+                    wrapper.acked.len(),
+                    wrapper.total_n_fragments
+                );
+                self.abandoned.sent += 1;
+                self.channels
+                    .borrow()
+                    .tx_ctrl
+                    .send(MessageSentAbandoned(wrapper))
+                    .expect("Failed to transmit to CONTROLLER");
+            }
+        }
+
+        for key in stale_keys(&self.completed, self.limits.recv_timeout, usize::MAX, now) {
+            self.completed.remove(&key);
+        }
+    }
+    pub fn public_key(&self) -> PublicKey {
+        self.keys.public_key()
+    }
+
+    /// `true` if `server` agreed on `feature`, servers that didn't answer Hello yet are assumed to
+    pub fn server_supports(&self, server: &NodeId, feature: Features) -> bool {
+        !self.incompatible.contains(server)
+            && self
+                .servers
+                .get(server)
+                .is_none_or(|agreed| agreed.supports(feature))
+    }
+
+    /// `true` only once `server` answered Hello agreeing on `feature`
+    pub fn server_agreed(&self, server: &NodeId, feature: Features) -> bool {
+        self.servers
+            .get(server)
+            .is_some_and(|agreed| agreed.supports(feature))
+    }
+
+    /// Server to send a message to `client` through, one that queues it if `client` left
+    pub fn server_for(&self, client: &NodeId) -> Option<NodeId> {
+        self.clients
+            .get(client)
+            .or_else(|| self.departed.get(client))
+            .and_then(|servers| servers.iter().next().copied())
+    }
+
+    /// Request to send `message` with `id` to `to`, encrypted if its public key is known
+    pub fn chat_request(&self, to: NodeId, id: MessageId, message: String) -> ChatRequest {
+        match self.public_keys.get(&to) {
+            Some(public_key) => {
+                let (nonce, message) = self.keys.encrypt(public_key, message.as_bytes());
+                ChatRequest::SendEncrypted {
+                    from: self.my_id,
+                    to,
+                    nonce,
+                    message,
+                    id,
+                }
+            }
+            None => ChatRequest::SendMessage {
+                from: self.my_id,
+                to,
+                message,
+                id,
+            },
+        }
+    }
+
+    /// Request to replace the text of message `id` sent to `to`, encrypted like `chat_request`
+    pub fn edit_request(&self, to: NodeId, id: MessageId, message: String) -> ChatRequest {
+        let (nonce, message) = match self.public_keys.get(&to) {
+            Some(public_key) => {
+                let (nonce, message) = self.keys.encrypt(public_key, message.as_bytes());
+                (Some(nonce), message)
+            }
+            None => (None, message.into_bytes()),
+        };
+        ChatRequest::EditMessage {
+            from: self.my_id,
+            to,
+            id,
+            nonce,
+            message,
+        }
+    }
+
+    /// `msg` as shown to the UI, encrypted messages are opened
+    fn plaintext(
+        &self,
+        msg: message::ChatResponse,
+        source: NodeId,
+    ) -> Option<message::ChatResponse> {
+        match msg {
+            EncryptedFrom {
+                from,
+                nonce,
+                message,
+                id,
+            } => self
+                .open(from, &nonce, &message, source)
+                .map(|message| MessageFrom { from, message, id }),
+            MessageEdited {
+                from,
+                id,
+                nonce: Some(nonce),
+                message,
+            } => self
+                .open(from, &nonce, &message, source)
+                .map(|message| MessageEdited {
+                    from,
+                    id,
+                    nonce: None,
+                    message,
+                }),
+            History {
+                with,
+                entries,
+                more,
+            } => {
+                let entries = entries
+                    .into_iter()
+                    .filter_map(|entry| {
+                        let message = match entry.message {
+                            EncryptedFrom {
+                                from,
+                                nonce,
+                                message,
+                                id,
+                            } => {
+                                // sent messages are opened with the key of the recipient
+                                let peer = if from == self.my_id { entry.to } else { from };
+                                let message = self.open(peer, &nonce, &message, source)?;
+                                MessageFrom { from, message, id }
+                            }
+                            message => message,
+                        };
+                        Some(HistoryEntry { message, ..entry })
+                    })
+                    .collect();
+                Some(History {
+                    with,
+                    entries,
+                    more,
+                })
+            }
+            msg => Some(msg),
+        }
+    }
+
+    /// Body of an encrypted message exchanged with `peer`
+    fn open(&self, peer: NodeId, nonce: &Nonce, message: &[u8], source: NodeId) -> Option<Vec<u8>> {
+        let opened = self
+            .public_keys
+            .get(&peer)
+            .and_then(|key| self.keys.decrypt(key, nonce, message));
+        if opened.is_none() {
+            error!(
+                "{}: unable to decrypt message exchanged with {} relayed by {}",
+                self.my_id, peer, source
+            );
         }
+        opened
     }
+
     pub fn create_and_store_wrapper(
         &mut self,
         destination: &NodeId,
         msg: ChatRequest,
     ) -> &SentMessageWrapper {
         self.last_session += 1;
-        let wrapper = SentMessageWrapper::from_message(self.last_session, *destination, &msg);
+        let options = match self.servers.get(destination) {
+            Some(capabilities) => capabilities.restrict(&self.fragmentation),
+            None => self.fragmentation,
+        };
+        let wrapper = SentMessageWrapper::from_message_with_options(
+            self.last_session,
+            *destination,
+            &msg,
+            &options,
+        );
+        if let ChatRequest::UploadFile { name, .. } = &msg {
+            self.uploads.insert(self.last_session, name.clone());
+        }
         self.channels
             .borrow()
             .tx_ctrl
@@ -73,7 +353,16 @@ impl MessagerManager {
         }
     }
 
-    pub fn get_dropped_fragment(&mut self, nack: &Nack, session: Session) -> Option<&Fragment> {
+    /// Fragment to send again after `nack`, coming from `from`
+    ///
+    /// Fragments dropped by a drone are not sent again while the destination can rebuild
+    /// them from parity, explicit requests of the destination are always satisfied.
+    pub fn get_dropped_fragment(
+        &mut self,
+        nack: &Nack,
+        session: Session,
+        from: &NodeId,
+    ) -> Option<&Fragment> {
         match nack.nack_type {
             NackType::UnexpectedRecipient(_) => {
                 if let Some(wrapper) = self.msg_wrapper.get(&session) {
@@ -85,10 +374,17 @@ impl MessagerManager {
                 self.msg_wrapper.remove(&session);
                 None
             }
-            _ => self
-                .msg_wrapper
-                .get(&session)
-                .and_then(|wrapper| wrapper.fragments.get(nack.fragment_index as usize)),
+            _ => {
+                let wrapper = self.msg_wrapper.get_mut(&session)?;
+                if *from != wrapper.destination && !wrapper.fragment_lost(nack.fragment_index) {
+                    debug!(
+                        "{}: fragment {} of session {} can be rebuilt from parity, not sent again",
+                        self.my_id, nack.fragment_index, session
+                    );
+                    return None;
+                }
+                wrapper.fragments.get(nack.fragment_index as usize)
+            }
         }
     }
 
@@ -100,8 +396,23 @@ impl MessagerManager {
 
     pub fn ack_and_build_message(&mut self, ack: &Ack, session: Session) {
         if let Some(wrapper) = self.msg_wrapper.get_mut(&session) {
-            wrapper.acked.insert(ack.fragment_index);
+            wrapper.add_acked(ack.fragment_index);
+            if let Some(name) = self.uploads.get(&session) {
+                self.channels
+                    .borrow()
+                    .tx_ui
+                    .send(ToUICommunication::Transfer {
+                        server: wrapper.destination,
+                        session,
+                        name: Some(name.clone()),
+                        upload: true,
+                        done: wrapper.acked.len() as u64,
+                        total: wrapper.total_n_fragments,
+                    })
+                    .expect("Failed to transmit to UI");
+            }
             if wrapper.is_all_fragment_acked() {
+                self.uploads.remove(&session);
                 info!(
                     "{}: All fragments acknowledged for message {:?}, removing wrapper",
                     self.my_id, wrapper
@@ -121,15 +432,21 @@ impl MessagerManager {
         fragment: Fragment,
         session: Session,
         source: NodeId,
-    ) -> bool {
+    ) -> FragmentStatus {
         let session_key = (session, source);
-        let mut is_not_duplicate = true;
+        if self.completed.contains_key(&session_key) {
+            return FragmentStatus::Duplicate;
+        }
+
+        let mut status = FragmentStatus::Stored(vec![fragment.fragment_index]);
         if let Some(wrapper) = self.rcv_wrapper.get_mut(&session_key) {
             debug!(
                 "{}: fragment from {}, session: {}",
                 self.my_id, source, session
             );
-            is_not_duplicate = wrapper.add_fragment(fragment.clone());
+            if !wrapper.add_fragment(fragment.clone()) {
+                status = FragmentStatus::Duplicate;
+            }
         } else {
             debug!(
                 "{}: new fragment received with session {} and index {} from {}",
@@ -141,51 +458,293 @@ impl MessagerManager {
             );
         }
 
+        if status == FragmentStatus::Duplicate {
+            return status;
+        }
+
         if let Some(wrapper) = self.rcv_wrapper.get_mut(&session_key) {
+            if let FragmentStatus::Stored(to_ack) = &mut status {
+                to_ack.extend(wrapper.take_recovered());
+            }
+            // files are the only messages long enough to show their progress
+            if self.media_servers.contains(&source) && wrapper.total_n_fragments > 1 {
+                self.channels
+                    .borrow()
+                    .tx_ui
+                    .send(ToUICommunication::Transfer {
+                        server: source,
+                        session,
+                        name: None,
+                        upload: false,
+                        done: wrapper.arrived.len() as u64,
+                        total: wrapper.total_n_fragments,
+                    })
+                    .expect("Failed to transmit to UI");
+            }
             if wrapper.is_all_fragments_arrived() {
-                if let Some(msg) = wrapper.try_deserialize() {
-                    if let ClientList(list) = &msg {
-                        debug!(
-                            "{}: Received ClientList from {}. ClientList {:?}",
-                            self.my_id, source, list
-                        );
-                        for client in list {
-                            self.clients
-                                .entry(*client)
-                                .or_insert_with(HashSet::new)
-                                .insert(source);
+                match wrapper.deserialize::<message::ChatResponse>() {
+                    Ok(msg) => {
+                        if let ClientList(list) = &msg {
+                            debug!(
+                                "{}: Received ClientList from {}. ClientList {:?}",
+                                self.my_id, source, list
+                            );
+                            for client in list {
+                                self.departed.remove(client);
+                                self.clients
+                                    .entry(*client)
+                                    .or_insert_with(HashSet::new)
+                                    .insert(source);
+                            }
+                            // the rooms are asked once the server both registered us and agreed
+                            // on them, a RoomList sent with Hello could arrive first and be refused
+                            if list.contains(&self.my_id)
+                                && self.registered_on.insert(source)
+                                && self
+                                    .servers
+                                    .get(&source)
+                                    .is_some_and(|agreed| agreed.supports(Features::GROUPS))
+                            {
+                                self.outbox.push((source, ChatRequest::RoomList));
+                            }
+                        } else if let ErrorWrongClientId(client) = &msg {
+                            debug!(
+                                "{}: Received ErrorWrongClientId from {}. ClientId {}",
+                                self.my_id, source, client
+                            );
+                            if let Some(servers) = self.clients.get_mut(client) {
+                                servers.remove(&source);
+                                if servers.is_empty() {
+                                    self.clients.remove(client);
+                                }
+                            }
+                        } else if let ErrorWrongSender(claimed) = &msg {
+                            error!(
+                                "{}: Server {} refused a message sent as {}",
+                                self.my_id, source, claimed
+                            );
+                        } else if let ClientLeft(client) = &msg {
+                            debug!(
+                                "{}: Received ClientLeft from {}. ClientId {}",
+                                self.my_id, source, client
+                            );
+                            // the key is kept, messages queued for it are still encrypted
+                            if let Some(servers) = self.clients.get_mut(client) {
+                                servers.remove(&source);
+                                if servers.is_empty() {
+                                    self.clients.remove(client);
+                                }
+                            }
+                            self.departed.entry(*client).or_default().insert(source);
+                        } else if let MessageQueued { to: client, .. } = &msg {
+                            info!(
+                                "{}: Client {} is away, server {} queued the message",
+                                self.my_id, client, source
+                            );
+                        } else if let RoomList(rooms) = &msg {
+                            debug!(
+                                "{}: Received RoomList from {}. Rooms {:?}",
+                                self.my_id, source, rooms
+                            );
+                            self.rooms.retain(|_, server| *server != source);
+                            for (room, _) in rooms {
+                                self.rooms.insert(room.clone(), source);
+                            }
+                        } else if let BroadcastReport {
+                            reached,
+                            unreachable,
+                        } = &msg
+                        {
+                            info!(
+                                "{}: Broadcast through {} reached {:?}, unreachable {:?}",
+                                self.my_id, source, reached, unreachable
+                            );
+                        } else if let ErrorWrongRoom(room) = &msg {
+                            warn!(
+                                "{}: Server {} refused a request for room {}",
+                                self.my_id, source, room
+                            );
+                        } else if let ClientKeys(keys) = &msg {
+                            debug!(
+                                "{}: Received ClientKeys from {} for {} clients",
+                                self.my_id,
+                                source,
+                                keys.len()
+                            );
+                            self.public_keys.extend(keys.iter().copied());
+                        } else if let Welcome(agreed) = &msg {
+                            info!(
+                                "{}: Server {} speaks protocol version {}, features {:?}",
+                                self.my_id, source, agreed.version, agreed.features
+                            );
+                            self.incompatible.remove(&source);
+                            self.servers.insert(source, *agreed);
+                            if self.registered_on.contains(&source)
+                                && agreed.supports(Features::GROUPS)
+                            {
+                                self.outbox.push((source, ChatRequest::RoomList));
+                            }
+                        } else if let FileList(files) = &msg {
+                            debug!(
+                                "{}: Media server {} stores {} files",
+                                self.my_id,
+                                source,
+                                files.len()
+                            );
+                            self.media_servers.insert(source);
+                        } else if let ErrorIncompatible(server) = &msg {
+                            if self.capabilities.negotiate(server).is_some() {
+                                // a request needing a feature not agreed, e.g. rooms
+                                warn!(
+                                    "{}: Server {} refused a request needing a feature not agreed",
+                                    self.my_id, source
+                                );
+                                let agreed = self.servers.get(&source);
+                                if agreed.is_some_and(|agreed| !agreed.supports(Features::GROUPS)) {
+                                    self.rooms.retain(|_, server| *server != source);
+                                }
+                            } else {
+                                error!(
+                                    "{}: Server {} is incompatible, its versions are {}..={}",
+                                    self.my_id, source, server.min_version, server.version
+                                );
+                                self.servers.remove(&source);
+                                self.incompatible.insert(source);
+                                self.clients.retain(|_, servers| {
+                                    servers.remove(&source);
+                                    !servers.is_empty()
+                                });
+                                self.rooms.retain(|_, server| *server != source);
+                            }
                         }
-                    } else if let ErrorWrongClientId(client) = &msg {
-                        debug!(
-                            "{}: Received ErrorWrongClientId from {}. ClientId {}",
-                            self.my_id, source, client
-                        );
-                        if let Some(servers) = self.clients.get_mut(client) {
-                            servers.remove(&source);
-                            if servers.is_empty() {
-                                self.clients.remove(client);
+                        self.channels
+                            .borrow()
+                            .tx_ctrl
+                            .send(MessageRecv(wrapper.clone()))
+                            .expect("Failed to transmit to CONTROLLER");
+                        // the UI only sees plaintext
+                        let response = match msg {
+                            WithReceipt { id, response } => {
+                                let response = self.plaintext(*response, source);
+                                if let Some(MessageFrom { from, .. }) = &response {
+                                    let receipt = ChatRequest::Receipt {
+                                        from: self.my_id,
+                                        to: *from,
+                                        id,
+                                        status: ReceiptStatus::Delivered,
+                                    };
+                                    self.outbox.push((source, receipt));
+                                }
+                                response.map(|response| WithReceipt {
+                                    id,
+                                    response: Box::new(response),
+                                })
+                            }
+                            Receipt {
+                                id,
+                                status: ReceiptStatus::Accepted,
+                                ..
+                            } => {
+                                self.channels
+                                    .borrow()
+                                    .tx_ui
+                                    .send(MessageDeliveredToServer(id))
+                                    .expect("Failed to transmit to UI");
+                                None
+                            }
+                            // still reachable through another server
+                            ClientLeft(client) if self.clients.contains_key(&client) => None,
+                            Presence(presences) => {
+                                for (client, presence, last_seen) in presences {
+                                    if presence == message::Presence::Offline
+                                        && self.clients.contains_key(&client)
+                                    {
+                                        continue;
+                                    }
+                                    self.channels
+                                        .borrow()
+                                        .tx_ui
+                                        .send(ToUICommunication::Presence {
+                                            client,
+                                            presence,
+                                            last_seen,
+                                        })
+                                        .expect("Failed to transmit to UI");
+                                }
+                                None
+                            }
+                            Typing(from) => {
+                                self.channels
+                                    .borrow()
+                                    .tx_ui
+                                    .send(ToUICommunication::Typing(from))
+                                    .expect("Failed to transmit to UI");
+                                None
                             }
+                            msg @ (FileList(_)
+                            | FileStored(_)
+                            | File { .. }
+                            | ErrorWrongFile(_)) => {
+                                self.channels
+                                    .borrow()
+                                    .tx_ui
+                                    .send(ToUICommunication::MediaResponse {
+                                        server: source,
+                                        response: msg,
+                                    })
+                                    .expect("Failed to transmit to UI");
+                                None
+                            }
+                            msg @ (DocumentList(_) | Document { .. } | ErrorWrongDocument(_)) => {
+                                self.channels
+                                    .borrow()
+                                    .tx_ui
+                                    .send(ToUICommunication::TextResponse {
+                                        server: source,
+                                        response: msg,
+                                    })
+                                    .expect("Failed to transmit to UI");
+                                None
+                            }
+                            message::ChatResponse::ServerType(kind) => {
+                                info!("{}: Server {} is a {:?}", self.my_id, source, kind);
+                                self.discovered.push((source, kind));
+                                None
+                            }
+                            msg => self.plaintext(msg, source),
+                        };
+                        if let Some(response) = response {
+                            self.channels
+                                .borrow()
+                                .tx_ui
+                                .send(ChatResponse { response })
+                                .expect("Failed to transmit to UI");
                         }
+                        self.rcv_wrapper.remove(&session_key);
+                        self.completed.insert(session_key, SystemTime::now());
+                    }
+                    Err(e) if e.is_corruption() => {
+                        warn!(
+                            "{}: message with session {} from {} is corrupted ({:?}), asking it again",
+                            self.my_id, session, source, e
+                        );
+                        self.channels
+                            .borrow()
+                            .tx_ctrl
+                            .send(MessageCorrupted(wrapper.clone()))
+                            .expect("Failed to transmit to CONTROLLER");
+                        wrapper.reset();
+                        status = FragmentStatus::Corrupted(wrapper.total_n_fragments);
+                    }
+                    Err(e) => {
+                        error!(
+                            "{}: unable to read message with session {} from {}: {:?}",
+                            self.my_id, session, source, e
+                        );
                     }
-                    self.channels
-                        .borrow()
-                        .tx_ctrl
-                        .send(MessageRecv(wrapper.clone()))
-                        .expect("Failed to transmit to CONTROLLER");
-                    self.channels
-                        .borrow()
-                        .tx_ui
-                        .send(ChatResponse { response: msg })
-                        .expect("Failed to transmit to UI");
-                    self.rcv_wrapper.remove(&session_key);
-                } else {
-                    debug!(
-                        "{}: another fragment received with session {} and index {} from {}",
-                        self.my_id, session, fragment.fragment_index, source
-                    );
                 }
             }
         }
-        is_not_duplicate
+        status
     }
 }
@@ -2,11 +2,16 @@
 mod tests {
     use crate::channel::ChannelManager;
     use crate::communication::ToUICommunication;
-    use crate::message::MessagerManager;
+    use crate::message::{FragmentStatus, MessagerManager};
     use crossbeam_channel::{unbounded, Receiver};
-    use message::{NodeEvent, RecvMessageWrapper};
+    use message::{
+        Capabilities, ChatRequest, ChatResponse, DroneSend, Features, FragmentationOptions,
+        HistoryEntry, NodeEvent, Presence, ReceiptStatus, RecvMessageWrapper, SentMessageWrapper,
+        ServerType,
+    };
     use std::cell::RefCell;
     use std::rc::Rc;
+    use std::time::{Duration, SystemTime};
     use wg_2024::packet::{Fragment, Packet};
 
     fn setup_manager() -> (
@@ -42,13 +47,652 @@ mod tests {
     #[test]
     fn test_receive_multi_fragments_same_session() {
         let (mut manager, _, _, _) = setup_manager();
-        let fragment = Fragment::from_string(1, 1, "TEST".to_string());
+        let fragment = Fragment::from_string(1, 1, "\"TEST\"".to_string());
         let session = 1;
         let sid_a = 1;
         let sid_b = 2;
-        assert!(manager.save_received_message(fragment.clone(), session, sid_a));
+        assert!(matches!(
+            manager.save_received_message(fragment.clone(), session, sid_a),
+            FragmentStatus::Stored(_)
+        ));
         assert!(manager.rcv_wrapper.contains_key(&(session, sid_a)));
-        assert!(manager.save_received_message(fragment.clone(), session, sid_b));
+        assert!(matches!(
+            manager.save_received_message(fragment.clone(), session, sid_b),
+            FragmentStatus::Stored(_)
+        ));
         assert!(manager.rcv_wrapper.contains_key(&(session, sid_b)));
     }
+
+    #[test]
+    fn test_corrupted_message_is_asked_again() {
+        let (mut manager, rx_ctrl, rx_ui, _) = setup_manager();
+        let session = 1;
+        let sid = 1;
+        let wrapper =
+            SentMessageWrapper::from_message(session, 0, &ChatResponse::ClientList(vec![2, 3]));
+        let mut fragment = wrapper.fragments[0].clone();
+        fragment.data[message::frame::FRAME_HEADER_LEN] ^= 0xFF;
+
+        assert_eq!(
+            manager.save_received_message(fragment, session, sid),
+            FragmentStatus::Corrupted(wrapper.total_n_fragments)
+        );
+        assert!(matches!(
+            rx_ctrl.try_recv(),
+            Ok(NodeEvent::MessageCorrupted(_))
+        ));
+        assert!(rx_ui.try_recv().is_err());
+        assert!(!manager.clients.contains_key(&2));
+
+        // the wrapper is empty again, the resent fragment completes the message
+        assert_eq!(
+            manager.save_received_message(wrapper.fragments[0].clone(), session, sid),
+            FragmentStatus::Stored(vec![0])
+        );
+        assert!(!manager.rcv_wrapper.contains_key(&(session, sid)));
+        assert!(manager.clients.contains_key(&2));
+    }
+
+    #[test]
+    fn test_dropped_fragment_rebuilt_from_parity() {
+        let (mut manager, _rx_ctrl, rx_ui, _) = setup_manager();
+        let session = 4;
+        let sid = 1;
+        let msg = ChatResponse::ClientList((0..=255).collect());
+        let options = FragmentationOptions {
+            compress: false,
+            parity_group: 2,
+            ..Default::default()
+        };
+        let wrapper = SentMessageWrapper::new_with_options(
+            session,
+            0,
+            msg.encode(message::Codec::Binary),
+            &options,
+        );
+        let n = wrapper.total_n_fragments as usize;
+        assert!(n > 2);
+
+        // fragment 0 is dropped, it is rebuilt when its group partner and parity arrive
+        let mut acked = Vec::new();
+        for fragment in wrapper.fragments[1..n]
+            .iter()
+            .chain([&wrapper.fragments[n]])
+        {
+            match manager.save_received_message(fragment.clone(), session, sid) {
+                FragmentStatus::Stored(to_ack) => acked.extend(to_ack),
+                other => panic!("Unexpected status {:?}", other),
+            }
+        }
+        acked.sort();
+        assert_eq!(acked, (0..=n as u64).collect::<Vec<_>>());
+        assert!(rx_ui.try_recv().is_ok());
+        assert!(manager.clients.contains_key(&255));
+
+        // the remaining parity arrives late
+        assert_eq!(
+            manager.save_received_message(wrapper.fragments[n + 1].clone(), session, sid),
+            FragmentStatus::Duplicate
+        );
+        assert!(!manager.rcv_wrapper.contains_key(&(session, sid)));
+    }
+
+    #[test]
+    fn test_stale_messages_are_abandoned() {
+        let (mut manager, rx_ctrl, _, _) = setup_manager();
+        let wrapper = SentMessageWrapper::from_message(7, 0, &ChatResponse::ClientList(vec![2]));
+        let mut fragment = wrapper.fragments[0].clone();
+        fragment.total_n_fragments = 2;
+        manager.save_received_message(fragment, 7, 1);
+        manager.create_and_store_wrapper(&1, message::ChatRequest::ClientList);
+        while rx_ctrl.try_recv().is_ok() {}
+
+        manager.collect_garbage();
+        assert!(rx_ctrl.try_recv().is_err());
+
+        manager.limits.recv_timeout = Duration::ZERO;
+        manager.limits.max_sent = 0;
+        manager.collect_garbage();
+        assert!(manager.rcv_wrapper.is_empty());
+        assert!(manager.msg_wrapper.is_empty());
+        assert_eq!(manager.abandoned.recv, 1);
+        assert_eq!(manager.abandoned.sent, 1);
+
+        let events: Vec<_> = rx_ctrl.try_iter().collect();
+        assert!(matches!(
+            events.as_slice(),
+            [
+                NodeEvent::MessageRecvAbandoned(_),
+                NodeEvent::MessageSentAbandoned(_)
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_handshake_responses() {
+        let (mut manager, _rx_ctrl, rx_ui, _) = setup_manager();
+        let sid = 1;
+        let deliver = |manager: &mut MessagerManager, session, msg: &ChatResponse| {
+            let wrapper = SentMessageWrapper::from_message(session, 0, msg);
+            for fragment in &wrapper.fragments {
+                manager.save_received_message(fragment.clone(), session, sid);
+            }
+        };
+
+        deliver(&mut manager, 1, &ChatResponse::ClientList(vec![2, 3]));
+        let plain = Capabilities::new(Features::NONE);
+        deliver(&mut manager, 2, &ChatResponse::Welcome(plain));
+        assert_eq!(manager.servers.get(&sid), Some(&plain));
+
+        // features not agreed are not used with that server
+        manager.fragmentation.compress = true;
+        let wrapper = manager.create_and_store_wrapper(
+            &sid,
+            message::ChatRequest::SendMessage {
+                from: 0,
+                to: 2,
+                message: "again and again ".repeat(40),
+                id: 0,
+            },
+        );
+        assert_eq!(
+            wrapper.fragments[0].data[2] & message::frame::FLAG_COMPRESSED,
+            0
+        );
+
+        // a request for a feature not agreed is refused, the server is still used
+        deliver(&mut manager, 3, &ChatResponse::ErrorIncompatible(plain));
+        assert!(!manager.incompatible.contains(&sid));
+        assert_eq!(manager.servers.get(&sid), Some(&plain));
+
+        let future = Capabilities {
+            version: message::PROTOCOL_VERSION + 2,
+            min_version: message::PROTOCOL_VERSION + 1,
+            ..plain
+        };
+        deliver(&mut manager, 4, &ChatResponse::ErrorIncompatible(future));
+        assert!(manager.incompatible.contains(&sid));
+        assert!(!manager.servers.contains_key(&sid));
+        assert!(manager.clients.is_empty());
+        // the errors reach the UI like every other response
+        assert_eq!(rx_ui.try_iter().count(), 4);
+    }
+
+    #[test]
+    fn test_end_to_end_encrypted_message() {
+        let (mut alice, _rx_ctrl_a, _rx_ui_a, _) = setup_manager();
+        let (mut bob, _rx_ctrl_b, rx_ui_b, _) = setup_manager();
+        let (alice_id, bob_id, server) = (0, 2, 1);
+
+        // without the key of the recipient the message is sent in plaintext
+        assert!(matches!(
+            alice.chat_request(bob_id, 0, "hi".to_string()),
+            ChatRequest::SendMessage { .. }
+        ));
+
+        let keys = ChatResponse::ClientKeys(vec![
+            (alice_id, alice.public_key()),
+            (bob_id, bob.public_key()),
+        ]);
+        let wrapper = SentMessageWrapper::from_message(1, server, &keys);
+        alice.save_received_message(wrapper.fragments[0].clone(), 1, server);
+        bob.save_received_message(wrapper.fragments[0].clone(), 1, server);
+        assert_eq!(bob.public_keys.get(&alice_id), Some(&alice.public_key()));
+        while rx_ui_b.try_recv().is_ok() {}
+
+        let ChatRequest::SendEncrypted {
+            from,
+            to,
+            nonce,
+            message,
+            id,
+        } = alice.chat_request(bob_id, 1, "secret".to_string())
+        else {
+            panic!("The message is not encrypted");
+        };
+        assert_eq!((from, to, id), (alice_id, bob_id, 1));
+        assert!(!message.windows(6).any(|w| w == b"secret"));
+
+        // the server relays the sealed body as it is
+        let mut relayed = |session, message: Vec<u8>| {
+            let response = ChatResponse::EncryptedFrom {
+                from,
+                nonce,
+                message,
+                id,
+            };
+            let wrapper = SentMessageWrapper::from_message(session, bob_id, &response);
+            for fragment in &wrapper.fragments {
+                bob.save_received_message(fragment.clone(), session, server);
+            }
+        };
+        relayed(2, message.clone());
+        match rx_ui_b.try_recv() {
+            Ok(ToUICommunication::ChatResponse {
+                response: ChatResponse::MessageFrom { from, message, id },
+            }) => {
+                assert_eq!((from, id), (alice_id, 1));
+                assert_eq!(message, b"secret");
+            }
+            other => panic!("Unexpected message to UI {:?}", other),
+        }
+
+        // a tampered body is not shown
+        let mut tampered = message;
+        tampered[0] ^= 0x01;
+        relayed(3, tampered);
+        assert!(rx_ui_b.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_history_is_decrypted_with_the_key_of_the_peer() {
+        let (mut alice, _rx_ctrl_a, rx_ui_a, _) = setup_manager();
+        let (bob, _rx_ctrl_b, _rx_ui_b, _) = setup_manager();
+        let (alice_id, bob_id, server) = (0, 2, 1);
+
+        let keys = ChatResponse::ClientKeys(vec![(bob_id, bob.public_key())]);
+        let wrapper = SentMessageWrapper::from_message(1, server, &keys);
+        alice.save_received_message(wrapper.fragments[0].clone(), 1, server);
+        while rx_ui_a.try_recv().is_ok() {}
+
+        let ChatRequest::SendEncrypted { nonce, message, .. } =
+            alice.chat_request(bob_id, 4, "secret".to_string())
+        else {
+            panic!("The message is not encrypted");
+        };
+        // the message alice sent comes back in her history
+        let history = ChatResponse::History {
+            with: bob_id,
+            entries: vec![HistoryEntry {
+                seq: 1,
+                timestamp: SystemTime::now(),
+                to: bob_id,
+                id: 4,
+                message: ChatResponse::EncryptedFrom {
+                    from: alice_id,
+                    nonce,
+                    message,
+                    id: 4,
+                },
+                edited: false,
+                deleted: false,
+            }],
+            more: false,
+        };
+        let wrapper = SentMessageWrapper::from_message(2, alice_id, &history);
+        for fragment in &wrapper.fragments {
+            alice.save_received_message(fragment.clone(), 2, server);
+        }
+        match rx_ui_a.try_recv() {
+            Ok(ToUICommunication::ChatResponse {
+                response:
+                    ChatResponse::History {
+                        with,
+                        entries,
+                        more,
+                    },
+            }) => {
+                assert_eq!((with, more), (bob_id, false));
+                assert!(matches!(
+                    &entries[..],
+                    [HistoryEntry { seq: 1, message: ChatResponse::MessageFrom { from, message, id: 4 }, .. }]
+                        if *from == alice_id && message == b"secret"
+                ));
+            }
+            other => panic!("Unexpected message to UI {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_edits_are_encrypted_like_messages() {
+        let (mut alice, _rx_ctrl_a, _rx_ui_a, _) = setup_manager();
+        let (mut bob, _rx_ctrl_b, rx_ui_b, _) = setup_manager();
+        let (alice_id, bob_id, server) = (0, 2, 1);
+
+        // without the key of the recipient the new text is sent in plaintext
+        assert!(matches!(
+            alice.edit_request(bob_id, 4, "hi".to_string()),
+            ChatRequest::EditMessage { id: 4, nonce: None, message, .. } if message == b"hi"
+        ));
+
+        let keys = ChatResponse::ClientKeys(vec![
+            (alice_id, alice.public_key()),
+            (bob_id, bob.public_key()),
+        ]);
+        let wrapper = SentMessageWrapper::from_message(1, server, &keys);
+        alice.save_received_message(wrapper.fragments[0].clone(), 1, server);
+        bob.save_received_message(wrapper.fragments[0].clone(), 1, server);
+        while rx_ui_b.try_recv().is_ok() {}
+
+        let ChatRequest::EditMessage {
+            from,
+            id,
+            nonce: Some(nonce),
+            message,
+            ..
+        } = alice.edit_request(bob_id, 4, "secret".to_string())
+        else {
+            panic!("The edit is not encrypted");
+        };
+        assert!(!message.windows(6).any(|w| w == b"secret"));
+
+        // bob's UI gets the opened text
+        let edited = ChatResponse::MessageEdited {
+            from,
+            id,
+            nonce: Some(nonce),
+            message,
+        };
+        let wrapper = SentMessageWrapper::from_message(2, bob_id, &edited);
+        for fragment in &wrapper.fragments {
+            bob.save_received_message(fragment.clone(), 2, server);
+        }
+        match rx_ui_b.try_recv() {
+            Ok(ToUICommunication::ChatResponse {
+                response:
+                    ChatResponse::MessageEdited {
+                        from,
+                        id: 4,
+                        nonce: None,
+                        message,
+                    },
+            }) => {
+                assert_eq!(from, alice_id);
+                assert_eq!(message, b"secret");
+            }
+            other => panic!("Unexpected message to UI {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_client_left_is_shown_when_no_server_knows_it() {
+        let (mut manager, _rx_ctrl, rx_ui, _) = setup_manager();
+        let deliver = |manager: &mut MessagerManager, session, server, msg: &ChatResponse| {
+            let wrapper = SentMessageWrapper::from_message(session, 0, msg);
+            manager.save_received_message(wrapper.fragments[0].clone(), session, server);
+        };
+        deliver(&mut manager, 1, 1, &ChatResponse::ClientList(vec![2]));
+        deliver(&mut manager, 1, 3, &ChatResponse::ClientList(vec![2]));
+        while rx_ui.try_recv().is_ok() {}
+
+        deliver(&mut manager, 2, 1, &ChatResponse::ClientLeft(2));
+        assert!(rx_ui.try_recv().is_err());
+        assert!(manager.clients.contains_key(&2));
+
+        deliver(&mut manager, 2, 3, &ChatResponse::ClientLeft(2));
+        assert!(matches!(
+            rx_ui.try_recv(),
+            Ok(ToUICommunication::ChatResponse {
+                response: ChatResponse::ClientLeft(2)
+            })
+        ));
+        assert!(!manager.clients.contains_key(&2));
+
+        // messages to it go through a server that queues them
+        assert!(manager.server_for(&2).is_some());
+        deliver(&mut manager, 3, 1, &ChatResponse::ClientList(vec![2]));
+        assert_eq!(manager.server_for(&2), Some(1));
+        assert!(!manager.departed.contains_key(&2));
+    }
+
+    #[test]
+    fn test_presence_and_typing_are_sent_to_ui() {
+        let (mut manager, _rx_ctrl, rx_ui, _) = setup_manager();
+        let deliver = |manager: &mut MessagerManager, session, server, msg: &ChatResponse| {
+            let wrapper = SentMessageWrapper::from_message(session, 0, msg);
+            manager.save_received_message(wrapper.fragments[0].clone(), session, server);
+        };
+        deliver(&mut manager, 1, 1, &ChatResponse::ClientList(vec![2, 4]));
+        deliver(&mut manager, 1, 3, &ChatResponse::ClientList(vec![2]));
+        while rx_ui.try_recv().is_ok() {}
+
+        let now = SystemTime::now();
+        let presences = vec![(2, Presence::Offline, now), (4, Presence::Away, now)];
+        deliver(&mut manager, 2, 1, &ChatResponse::Presence(presences));
+        // client 2 is still registered on server 3
+        assert!(matches!(
+            rx_ui.try_recv(),
+            Ok(ToUICommunication::Presence {
+                client: 4,
+                presence: Presence::Away,
+                ..
+            })
+        ));
+        assert!(rx_ui.try_recv().is_err());
+
+        deliver(&mut manager, 3, 3, &ChatResponse::Typing(2));
+        assert!(matches!(rx_ui.try_recv(), Ok(ToUICommunication::Typing(2))));
+    }
+
+    #[test]
+    fn test_rooms_are_tracked_per_server() {
+        let (mut manager, _rx_ctrl, rx_ui, _) = setup_manager();
+        let deliver = |manager: &mut MessagerManager, session, server, msg: &ChatResponse| {
+            let wrapper = SentMessageWrapper::from_message(session, 0, msg);
+            manager.save_received_message(wrapper.fragments[0].clone(), session, server);
+        };
+        let rooms = vec![
+            ("demo".to_string(), vec![0, 2]),
+            ("other".to_string(), vec![2]),
+        ];
+        deliver(&mut manager, 1, 1, &ChatResponse::RoomList(rooms));
+        assert_eq!(manager.rooms.get("demo"), Some(&1));
+        assert_eq!(manager.rooms.get("other"), Some(&1));
+
+        // a new list from the same server replaces the old one
+        deliver(&mut manager, 2, 1, &ChatResponse::RoomList(vec![]));
+        assert!(manager.rooms.is_empty());
+
+        let room_message = ChatResponse::MessageFromRoom {
+            room: "demo".to_string(),
+            from: 2,
+            message: b"hi".to_vec(),
+        };
+        while rx_ui.try_recv().is_ok() {}
+        deliver(&mut manager, 3, 1, &room_message);
+        assert!(matches!(
+            rx_ui.try_recv(),
+            Ok(ToUICommunication::ChatResponse {
+                response: ChatResponse::MessageFromRoom { from: 2, .. }
+            })
+        ));
+
+        // rooms are asked only to servers that agreed on them
+        assert!(manager.server_supports(&1, Features::GROUPS));
+        let plain = Capabilities::new(Features::NONE);
+        deliver(&mut manager, 4, 1, &ChatResponse::Welcome(plain));
+        assert!(!manager.server_supports(&1, Features::GROUPS));
+    }
+
+    #[test]
+    fn test_rooms_asked_after_welcome_and_registration() {
+        let (mut manager, _rx_ctrl, _rx_ui, _) = setup_manager();
+        let deliver = |manager: &mut MessagerManager, session, server, msg: &ChatResponse| {
+            let wrapper = SentMessageWrapper::from_message(session, 0, msg);
+            manager.save_received_message(wrapper.fragments[0].clone(), session, server);
+        };
+        let room_lists = |manager: &mut MessagerManager| {
+            manager
+                .outbox
+                .drain(..)
+                .filter(|(_, request)| matches!(request, ChatRequest::RoomList))
+                .map(|(server, _)| server)
+                .collect::<Vec<_>>()
+        };
+
+        // the registration is seen first, the rooms wait for the agreement
+        assert!(!manager.server_agreed(&1, Features::GROUPS));
+        deliver(&mut manager, 1, 1, &ChatResponse::ClientList(vec![0, 2]));
+        assert!(room_lists(&mut manager).is_empty());
+        deliver(
+            &mut manager,
+            2,
+            1,
+            &ChatResponse::Welcome(Capabilities::default()),
+        );
+        assert_eq!(room_lists(&mut manager), vec![1]);
+
+        // the other way around, and never to a server without rooms
+        deliver(
+            &mut manager,
+            3,
+            2,
+            &ChatResponse::Welcome(Capabilities::default()),
+        );
+        assert!(room_lists(&mut manager).is_empty());
+        deliver(&mut manager, 4, 2, &ChatResponse::ClientList(vec![0]));
+        assert_eq!(room_lists(&mut manager), vec![2]);
+        let plain = Capabilities::new(Features::NONE);
+        deliver(&mut manager, 5, 3, &ChatResponse::ClientList(vec![0]));
+        deliver(&mut manager, 6, 3, &ChatResponse::Welcome(plain));
+        assert!(room_lists(&mut manager).is_empty());
+    }
+
+    #[test]
+    fn test_files_of_media_servers_are_sent_to_ui() {
+        let (mut manager, _rx_ctrl, rx_ui, _) = setup_manager();
+        let deliver = |manager: &mut MessagerManager, session, server, msg: &ChatResponse| {
+            let wrapper = SentMessageWrapper::from_message(session, 0, msg);
+            for fragment in wrapper.fragments {
+                manager.save_received_message(fragment, session, server);
+            }
+        };
+        deliver(&mut manager, 1, 1, &ChatResponse::FileList(vec![]));
+        assert!(manager.media_servers.contains(&1));
+        assert!(matches!(
+            rx_ui.try_recv(),
+            Ok(ToUICommunication::MediaResponse {
+                server: 1,
+                response: ChatResponse::FileList(_)
+            })
+        ));
+
+        // a file of many fragments shows its progress before it is complete,
+        // its content doesn't compress to a single fragment
+        let data = (0..500u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+            .collect();
+        let file = ChatResponse::File {
+            name: "notes.txt".to_string(),
+            data,
+        };
+        deliver(&mut manager, 2, 1, &file);
+        let updates: Vec<_> = rx_ui.try_iter().collect();
+        let (last, progress) = updates.split_last().unwrap();
+        assert!(progress.len() > 1);
+        assert!(progress.iter().all(|update| matches!(
+            update,
+            ToUICommunication::Transfer {
+                server: 1,
+                upload: false,
+                ..
+            }
+        )));
+        assert!(matches!(
+            progress.last(),
+            Some(ToUICommunication::Transfer { done, total, .. }) if done == total
+        ));
+        assert!(matches!(
+            last,
+            ToUICommunication::MediaResponse {
+                server: 1,
+                response: ChatResponse::File { .. }
+            }
+        ));
+    }
+
+    #[test]
+    fn test_documents_of_text_servers_are_sent_to_ui() {
+        let (mut manager, _rx_ctrl, rx_ui, _) = setup_manager();
+        let deliver = |manager: &mut MessagerManager, session, server, msg: &ChatResponse| {
+            let wrapper = SentMessageWrapper::from_message(session, 0, msg);
+            manager.save_received_message(wrapper.fragments[0].clone(), session, server);
+        };
+        let names = vec!["readme.txt".to_string()];
+        deliver(&mut manager, 1, 3, &ChatResponse::DocumentList(names));
+        assert!(!manager.media_servers.contains(&3));
+        assert!(matches!(
+            rx_ui.try_recv(),
+            Ok(ToUICommunication::TextResponse {
+                server: 3,
+                response: ChatResponse::DocumentList(_)
+            })
+        ));
+
+        let document = ChatResponse::Document {
+            name: "readme.txt".to_string(),
+            text: "Benvenuti".to_string(),
+        };
+        deliver(&mut manager, 2, 3, &document);
+        assert!(matches!(
+            rx_ui.try_recv(),
+            Ok(ToUICommunication::TextResponse {
+                server: 3,
+                response: ChatResponse::Document { .. }
+            })
+        ));
+    }
+
+    #[test]
+    fn test_server_type_is_kept_for_the_worker() {
+        let (mut manager, _rx_ctrl, rx_ui, _) = setup_manager();
+        let kind = ChatResponse::ServerType(ServerType::MediaServer);
+        let wrapper = SentMessageWrapper::from_message(1, 0, &kind);
+        manager.save_received_message(wrapper.fragments[0].clone(), 1, 4);
+        assert!(matches!(
+            manager.discovered[..],
+            [(4, ServerType::MediaServer)]
+        ));
+        // the worker tells the UI once it saved it
+        assert!(rx_ui.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_delivered_receipt_for_message_with_id() {
+        let (mut manager, _rx_ctrl, rx_ui, _) = setup_manager();
+        let server = 1;
+        let response = ChatResponse::WithReceipt {
+            id: 7,
+            response: Box::new(ChatResponse::MessageFrom {
+                from: 2,
+                message: b"hi".to_vec(),
+                id: 7,
+            }),
+        };
+        let wrapper = SentMessageWrapper::from_message(1, 0, &response);
+        manager.save_received_message(wrapper.fragments[0].clone(), 1, server);
+
+        // the worker sends the receipt back through the same server
+        assert!(matches!(
+            manager.outbox[..],
+            [(
+                1,
+                ChatRequest::Receipt {
+                    from: 0,
+                    to: 2,
+                    id: 7,
+                    status: ReceiptStatus::Delivered
+                }
+            )]
+        ));
+        assert!(matches!(
+            rx_ui.try_recv(),
+            Ok(ToUICommunication::ChatResponse {
+                response: ChatResponse::WithReceipt { id: 7, .. }
+            })
+        ));
+
+        // the server accepting a sent message is told to the UI by id
+        let accepted = ChatResponse::Receipt {
+            from: 2,
+            id: 3,
+            status: ReceiptStatus::Accepted,
+        };
+        let wrapper = SentMessageWrapper::from_message(2, 0, &accepted);
+        manager.save_received_message(wrapper.fragments[0].clone(), 2, server);
+        assert!(matches!(
+            rx_ui.try_recv(),
+            Ok(ToUICommunication::MessageDeliveredToServer(3))
+        ));
+    }
 }
@@ -4,6 +4,7 @@ mod tests_network_state;
 use crate::channel::ChannelManager;
 use log::{debug, error, info, warn};
 use message::NodeEvent::PacketSent;
+use message::ServerType;
 use petgraph::algo::dijkstra;
 use petgraph::graph::{Graph, NodeIndex};
 use std::cell::RefCell;
@@ -28,7 +29,8 @@ pub struct NetworkState {
     start_idx: NodeIndex,
     start_id: NodeId,
     pub server_list: HashSet<NodeId>,
-    routing_table: HashMap<NodeId, Vec<NodeId>>, // destination -> path
+    pub server_types: HashMap<NodeId, ServerType>, // answered by each server, kept if it leaves
+    routing_table: HashMap<NodeId, Vec<NodeId>>,   // destination -> path
 
     creation_time: SystemTime,
     flood_interval: Duration, // default 10 seconds,
@@ -55,6 +57,7 @@ impl NetworkState {
             start_idx: idx,
             start_id,
             server_list: HashSet::new(),
+            server_types: HashMap::new(),
             routing_table: HashMap::new(),
             creation_time: SystemTime::now(),
             flood_interval,
@@ -190,6 +193,18 @@ impl NetworkState {
         }
     }
 
+    /// Servers in `server_list` that answered they provide `kind`, sorted
+    pub fn servers_of(&self, kind: ServerType) -> Vec<NodeId> {
+        let mut servers: Vec<_> = self
+            .server_list
+            .iter()
+            .filter(|sid| self.server_types.get(sid) == Some(&kind))
+            .cloned()
+            .collect();
+        servers.sort();
+        servers
+    }
+
     pub fn increment_weight_around_node(&mut self, nid: &NodeId, increment: i32) {
         if let Some(&node_idx) = self.id_to_idx.get(nid) {
             let outgoing_neighbors: Vec<_> = self.topology.neighbors(node_idx).collect();
@@ -411,7 +426,7 @@ impl NetworkManager {
     }
 
     /// # Returns
-    /// - Option(NewServers)
+    /// - Option(NewServers), with the known servers that had lost their route
     pub fn update_network_from_flood_response(
         &mut self,
         flood_response: &FloodResponse,
@@ -428,7 +443,10 @@ impl NetworkManager {
             .path_trace
             .iter()
             .filter_map(|&(nid, ntype)| {
-                if ntype == NodeType::Server && !self.state.server_list.contains(&nid) {
+                if ntype == NodeType::Server
+                    && (!self.state.server_list.contains(&nid)
+                        || !self.state.routing_table.contains_key(&nid))
+                {
                     Some(nid)
                 } else {
                     None
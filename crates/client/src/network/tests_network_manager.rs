@@ -96,6 +96,35 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_flood_response_reports_server_reached_again() {
+        let (mut manager, _, _, _) = setup_manager();
+        let mut flood_request = FloodRequest::initialize(1, 0, NodeType::Client);
+        flood_request.increment(1, NodeType::Drone);
+        flood_request.increment(2, NodeType::Server);
+        let response = flood_request.generate_response(1);
+        let PacketType::FloodResponse(flood_response) = response.pack_type else {
+            panic!()
+        };
+
+        assert_eq!(
+            manager.update_network_from_flood_response(&flood_response),
+            Some(vec![2])
+        );
+        // already known and routed
+        assert_eq!(
+            manager.update_network_from_flood_response(&flood_response),
+            None
+        );
+
+        // the route is lost, the next flood reaching it reports it again
+        manager.state.routing_table.remove(&2);
+        assert_eq!(
+            manager.update_network_from_flood_response(&flood_response),
+            Some(vec![2])
+        );
+    }
+
     #[test]
     fn test_send_packet_cached_path() {
         let (mut manager, rx_ctrl, rx_ui, rx_drone) = setup_manager();
@@ -1,6 +1,7 @@
 #[cfg(test)]
 mod tests {
     use crate::network::{NetworkState, NEW_STATE_GRACE_PERIOD};
+    use message::ServerType;
     use petgraph::algo::dijkstra;
     use std::time::Duration;
     use wg_2024::packet::NodeType;
@@ -68,6 +69,23 @@ mod tests {
         assert!(state.id_to_idx.contains_key(&sid));
     }
 
+    #[test]
+    fn test_servers_of_kind() {
+        let mut state = setup_state();
+        for sid in [4, 2, 3, 5] {
+            state.add_node(sid, NodeType::Server);
+        }
+        state.server_types.insert(4, ServerType::ChatServer);
+        state.server_types.insert(2, ServerType::ChatServer);
+        state.server_types.insert(3, ServerType::MediaServer);
+        // a server that left keeps its kind but is not listed
+        state.server_types.insert(6, ServerType::ChatServer);
+
+        assert_eq!(state.servers_of(ServerType::ChatServer), vec![2, 4]);
+        assert_eq!(state.servers_of(ServerType::MediaServer), vec![3]);
+        assert!(state.servers_of(ServerType::TextServer).is_empty());
+    }
+
     #[test]
     fn test_add_link_creates_nodes() {
         let mut state = setup_state();
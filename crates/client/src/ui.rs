@@ -1,17 +1,115 @@
-use crate::communication::{FromUiCommunication, ToUICommunication};
+mod chat_store;
+mod documents;
+mod files;
+
+use crate::communication::{FromUiCommunication, ServerType, ToUICommunication};
+use chat_store::SavedChats;
 use crossbeam_channel::{Receiver, Sender};
+use documents::OpenDocument;
 use egui::RichText;
+use files::Transfer;
 use hashbrown::HashSet;
-use message::ChatResponse;
+use log::warn;
+use message::{
+    ChatResponse, FileInfo, FileName, HistoryEntry, MessageId, Presence, Profile, ReceiptStatus,
+    RoomName,
+};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 use wg_2024::network::NodeId;
 
+/// How long a typing notice is shown
+const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+/// Least time between two typing notices sent to the same chat
+const TYPING_INTERVAL: Duration = Duration::from_secs(3);
+
+/// A message shown in a chat
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ChatLine {
+    from: NodeId,
+    text: String,
+    id: Option<MessageId>, // direct messages only, set by the sender
+    edited: bool,
+    deleted: bool, // only a tombstone is shown
+    queued: bool, // sent while the recipient was away, kept by the server
+}
+
+impl ChatLine {
+    fn new(from: NodeId, text: String, id: Option<MessageId>) -> Self {
+        Self {
+            from,
+            text,
+            id,
+            edited: false,
+            deleted: false,
+            queued: false,
+        }
+    }
+}
+
+/// A direct chat with a client, a room or the broadcasts
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Chat {
+    Broadcast,
+    Direct(NodeId),
+    Room(RoomName),
+}
+
+/// What the right column of a client shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    Chat,
+    Files,
+    Documents,
+}
+
+impl Chat {
+    fn label(&self, state: &ClientState) -> String {
+        match self {
+            Chat::Broadcast => "Broadcast".to_string(),
+            Chat::Direct(node_id) => state.display_name(*node_id),
+            Chat::Room(room) => format!("Room {room}"),
+        }
+    }
+}
+
 pub struct ClientState {
     my_id: NodeId,
-    current_chat: Option<NodeId>,
-    unread_chat: HashSet<NodeId>,
+    current_chat: Option<Chat>,
+    unread_chat: HashSet<Chat>,
+    encrypted_chat: HashSet<NodeId>, // clients with a known public key
+    offline_chat: HashSet<NodeId>,   // clients that left, messages to them are queued by the server
+    logged_out: bool,
+    room_input: String,
 
-    chat_message: HashMap<NodeId, Vec<(NodeId, String)>>,
+    chat_message: HashMap<Chat, Vec<ChatLine>>,
+    next_message_id: MessageId,
+    message_status: HashMap<MessageId, ReceiptStatus>, // of the sent direct messages
+    to_mark_read: HashMap<NodeId, Vec<MessageId>>, // received messages, read receipt not sent yet
+    history_page: HashMap<NodeId, (Option<u64>, bool)>, // oldest history entry loaded, older ones left
+    room_members: HashMap<RoomName, Vec<NodeId>>,
+    profile: Profile,                   // own, as sent to the servers
+    profile_input: Profile,             // own, being edited
+    profiles: HashMap<NodeId, Profile>, // of the other clients, kept after they leave
+    away: bool,
+    presence: HashMap<NodeId, (Presence, SystemTime)>, // of the other clients, with their last request
+    typing: HashMap<NodeId, Instant>, // clients writing to us, when they were last seen typing
+    typing_sent: Option<(NodeId, Instant)>, // last typing notice sent
+    editing: Option<(NodeId, MessageId)>, // own direct message being edited in the input
+    history_file: Option<PathBuf>,    // where the chats are saved, not saved if None
+    dirty: bool,                      // chats changed since they were saved
+    view: View,
+    files: HashMap<NodeId, Vec<FileInfo>>, // stored by each media server
+    upload_server: Option<NodeId>,
+    file_path_input: String,
+    transfers: HashMap<(NodeId, u64, bool), Transfer>, // by server, session and upload
+    file_notices: Vec<String>,
+    documents: HashMap<NodeId, Vec<FileName>>, // served by each text server
+    open_document: Option<OpenDocument>,
+    document_notice: Option<String>,
+    server_types: HashMap<NodeId, ServerType>, // of the servers found by the worker
     rx_from_worker: Receiver<ToUICommunication>,
     tx_to_worker: Sender<FromUiCommunication>,
 }
@@ -26,17 +124,179 @@ impl ClientState {
             my_id,
             current_chat: None,
             unread_chat: HashSet::default(),
+            encrypted_chat: HashSet::default(),
+            offline_chat: HashSet::default(),
+            logged_out: false,
+            room_input: String::new(),
             chat_message: HashMap::new(),
+            next_message_id: 0,
+            message_status: HashMap::new(),
+            to_mark_read: HashMap::new(),
+            history_page: HashMap::new(),
+            room_members: HashMap::new(),
+            profile: Profile::default(),
+            profile_input: Profile::default(),
+            profiles: HashMap::new(),
+            away: false,
+            presence: HashMap::new(),
+            typing: HashMap::new(),
+            typing_sent: None,
+            editing: None,
+            history_file: None,
+            dirty: false,
+            view: View::Chat,
+            files: HashMap::new(),
+            upload_server: None,
+            file_path_input: String::new(),
+            transfers: HashMap::new(),
+            file_notices: Vec::new(),
+            documents: HashMap::new(),
+            open_document: None,
+            document_notice: None,
+            server_types: HashMap::new(),
             rx_from_worker,
             tx_to_worker,
         }
     }
+
+    /// Save the chats in `dir` and restore the ones saved by a previous run
+    pub fn with_history_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.history_file = Some(chat_store::history_file(dir.as_ref(), self.my_id));
+        self.load();
+        if self.profile != Profile::default() {
+            self.tx_to_worker
+                .send(FromUiCommunication::SetProfile(self.profile.clone()))
+                .expect("Failed to transmit to Worker");
+        }
+        self
+    }
+
+    /// Name shown for `nid`, its id if it didn't set one
+    fn display_name(&self, nid: NodeId) -> String {
+        match self.profiles.get(&nid) {
+            Some(profile) if !profile.name.trim().is_empty() => profile.name.clone(),
+            _ => format!("Node {nid}"),
+        }
+    }
+
+    /// Replace the text of message `id` sent by `from` in the direct chat with `with`,
+    /// `None` deletes it. Deleted messages can't be changed again
+    fn amend(&mut self, with: NodeId, from: NodeId, id: MessageId, text: Option<String>) {
+        let Some(line) = self
+            .chat_message
+            .get_mut(&Chat::Direct(with))
+            .and_then(|lines| {
+                lines
+                    .iter_mut()
+                    .find(|line| line.from == from && line.id == Some(id))
+            })
+            .filter(|line| !line.deleted)
+        else {
+            warn!(
+                "{}: message {} of {} not found, it can't be changed",
+                self.my_id, id, from
+            );
+            return;
+        };
+        match text {
+            Some(text) => {
+                line.text = text;
+                line.edited = true;
+            }
+            None => {
+                line.text.clear();
+                line.deleted = true;
+            }
+        }
+        self.dirty = true;
+    }
+
+    /// Add a page of the history with `with` before the lines shown. A message already shown,
+    /// sent in this run or saved by a previous one, is only updated with its edits
+    fn merge_history(&mut self, with: NodeId, entries: &[HistoryEntry]) {
+        let my_id = self.my_id;
+        let messages = self.chat_message.entry(Chat::Direct(with)).or_default();
+        let mut lines = Vec::new();
+        for entry in entries {
+            let ChatResponse::MessageFrom { from, message, .. } = &entry.message else {
+                continue;
+            };
+            let mut line = ChatLine::new(
+                *from,
+                String::from_utf8(message.clone())
+                    .unwrap_or_else(|_| "Invalid Message here".to_string()),
+                Some(entry.id),
+            );
+            line.edited = entry.edited;
+            line.deleted = entry.deleted;
+            if let Some(shown) = messages
+                .iter_mut()
+                .find(|shown| shown.from == line.from && shown.id == line.id)
+            {
+                if !shown.deleted && (line.edited || line.deleted) {
+                    shown.text = line.text;
+                    shown.edited = line.edited;
+                    shown.deleted = line.deleted;
+                }
+                continue;
+            }
+            // the server kept it, so it was accepted at least
+            if line.from == my_id {
+                self.message_status
+                    .entry(entry.id)
+                    .or_insert(ReceiptStatus::Accepted);
+            }
+            lines.push(line);
+        }
+        messages.splice(0..0, lines);
+        self.dirty = true;
+    }
+
+    /// Restore the saved chats, if any
+    fn load(&mut self) {
+        let Some(path) = self.history_file.clone() else {
+            return;
+        };
+        match chat_store::load(&path) {
+            Ok(Some(saved)) => saved.restore(self),
+            Ok(None) => {}
+            Err(e) => warn!(
+                "{}: unable to restore the chats from {:?}: {}",
+                self.my_id, path, e
+            ),
+        }
+    }
+
+    /// Replace the saved chats, not while logged out: the view is empty but the file is kept
+    fn save(&mut self) {
+        if self.logged_out {
+            self.dirty = false;
+            return;
+        }
+        if let Some(path) = &self.history_file {
+            if let Err(e) = chat_store::save(path, &SavedChats::from_state(self)) {
+                warn!(
+                    "{}: unable to save the chats to {:?}: {}",
+                    self.my_id, path, e
+                );
+            }
+        }
+        self.dirty = false;
+    }
+}
+
+/// The changes not saved yet, like receipts, are saved on exit
+impl Drop for ClientState {
+    fn drop(&mut self) {
+        self.save();
+    }
 }
 
 pub struct UiState {
     input: String,
     current_client: Option<NodeId>,
     client_states: HashMap<NodeId, ClientState>,
+    history_dir: Option<PathBuf>,
 }
 
 impl UiState {
@@ -45,10 +305,21 @@ impl UiState {
             input: String::new(),
             current_client: None,
             client_states: HashMap::new(),
+            history_dir: None,
         }
     }
 
+    /// Save the chats of every client added from now on in `dir`, see `ClientState::with_history_dir`
+    pub fn with_history_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.history_dir = Some(dir.into());
+        self
+    }
+
     pub fn add_client(&mut self, client_id: NodeId, client_state: ClientState) {
+        let client_state = match &self.history_dir {
+            Some(dir) => client_state.with_history_dir(dir),
+            None => client_state,
+        };
         self.client_states.insert(client_id, client_state);
         if self.current_client.is_none() {
             self.current_client = Some(client_id);
@@ -131,7 +402,7 @@ impl Ui {
         client_state: &mut ClientState,
         enter_pressed: bool,
     ) {
-        let old_chat = client_state.current_chat;
+        let old_chat = client_state.current_chat.clone();
         let available_rect = ui.available_rect_before_wrap();
         let total_width = available_rect.width();
         let total_height = available_rect.height();
@@ -150,7 +421,7 @@ impl Ui {
                     ui.separator();
 
                     // Calculate heights for chat list and button area
-                    let button_area_height = 80.0;
+                    let button_area_height = 220.0;
                     let heading_height = 40.0;
                     let chat_list_height = total_height - heading_height - button_area_height;
 
@@ -164,33 +435,48 @@ impl Ui {
                                     .id_salt("chat_list")
                                     .auto_shrink([false, false])
                                     .show(ui, |ui| {
-                                        let mut chats_to_show: Vec<NodeId> =
-                                            client_state.chat_message.keys().copied().collect();
-                                        chats_to_show.sort(); // Sort for consistent order
+                                        let mut chats_to_show: Vec<Chat> =
+                                            client_state.chat_message.keys().cloned().collect();
+                                        chats_to_show.sort(); // Sort for consistent order, broadcasts first and rooms last
 
-                                        for node_id in chats_to_show {
-                                            let unread =
-                                                client_state.unread_chat.contains(&node_id);
+                                        for chat in chats_to_show {
+                                            let unread = client_state.unread_chat.contains(&chat);
                                             let label = if unread {
-                                                RichText::new(format!("Chat {node_id}"))
+                                                RichText::new(chat.label(client_state))
                                                     .strong()
                                                     .color(egui::Color32::LIGHT_RED)
                                             } else {
-                                                RichText::new(format!("Chat {node_id}"))
+                                                RichText::new(chat.label(client_state))
                                             };
 
                                             let is_selected =
-                                                client_state.current_chat == Some(node_id);
+                                                client_state.current_chat.as_ref() == Some(&chat);
                                             let button = if is_selected {
                                                 egui::Button::new(label).fill(egui::Color32::BLUE)
                                             } else {
                                                 egui::Button::new(label)
                                             };
 
-                                            if ui.add(button).clicked() {
-                                                client_state.current_chat = Some(node_id);
-                                                client_state.unread_chat.remove(&node_id);
-                                            }
+                                            ui.horizontal(|ui| {
+                                                if let Chat::Direct(nid) = &chat {
+                                                    let color = match client_state.presence.get(nid)
+                                                    {
+                                                        Some((Presence::Online, _)) => {
+                                                            egui::Color32::GREEN
+                                                        }
+                                                        Some((Presence::Away, _)) => {
+                                                            egui::Color32::YELLOW
+                                                        }
+                                                        _ => egui::Color32::GRAY,
+                                                    };
+                                                    ui.label(RichText::new("●").color(color));
+                                                }
+                                                if ui.add(button).clicked() {
+                                                    client_state.unread_chat.remove(&chat);
+                                                    client_state.current_chat = Some(chat);
+                                                    client_state.dirty = true;
+                                                }
+                                            });
                                         }
                                     });
                             } else {
@@ -222,6 +508,121 @@ impl Ui {
 
                                 ui.add_space(5.0); // Spazio tra i bottoni
 
+                                // Logout / Login button
+                                let (label, command) = if client_state.logged_out {
+                                    ("Login", FromUiCommunication::Login)
+                                } else {
+                                    ("Logout", FromUiCommunication::Logout)
+                                };
+                                if ui
+                                    .add_sized([left_width - 10.0, 25.0], egui::Button::new(label))
+                                    .clicked()
+                                {
+                                    if client_state.logged_out {
+                                        client_state.logged_out = false;
+                                        client_state.load();
+                                    } else {
+                                        // only the view is cleared, the saved chats are kept
+                                        client_state.save();
+                                        client_state.logged_out = true;
+                                        client_state.chat_message.clear();
+                                        client_state.unread_chat.clear();
+                                        client_state.encrypted_chat.clear();
+                                        client_state.offline_chat.clear();
+                                        client_state.room_members.clear();
+                                        client_state.current_chat = None;
+                                    }
+                                    client_state
+                                        .tx_to_worker
+                                        .send(command)
+                                        .expect("Failed to transmit to UI");
+                                }
+
+                                ui.add_space(5.0);
+
+                                // Create / Join room
+                                ui.add_sized(
+                                    [left_width - 10.0, 20.0],
+                                    egui::TextEdit::singleline(&mut client_state.room_input)
+                                        .hint_text("Room name"),
+                                );
+                                ui.horizontal(|ui| {
+                                    let room = client_state.room_input.trim().to_string();
+                                    let button_width = (left_width - 20.0) / 2.0;
+                                    let create = ui
+                                        .add_sized(
+                                            [button_width, 25.0],
+                                            egui::Button::new("Create"),
+                                        )
+                                        .clicked();
+                                    let join = ui
+                                        .add_sized([button_width, 25.0], egui::Button::new("Join"))
+                                        .clicked();
+                                    if (create || join) && !room.is_empty() {
+                                        let command = if create {
+                                            FromUiCommunication::CreateRoom(room)
+                                        } else {
+                                            FromUiCommunication::JoinRoom(room)
+                                        };
+                                        client_state
+                                            .tx_to_worker
+                                            .send(command)
+                                            .expect("Failed to transmit to UI");
+                                        client_state.room_input.clear();
+                                    }
+                                });
+
+                                // Own profile, shown to the other clients
+                                ui.add_space(5.0);
+                                ui.add_sized(
+                                    [left_width - 10.0, 20.0],
+                                    egui::TextEdit::singleline(
+                                        &mut client_state.profile_input.name,
+                                    )
+                                    .hint_text("Name"),
+                                );
+                                ui.add_sized(
+                                    [left_width - 10.0, 20.0],
+                                    egui::TextEdit::singleline(
+                                        &mut client_state.profile_input.status,
+                                    )
+                                    .hint_text("Status"),
+                                );
+                                if ui
+                                    .add_sized(
+                                        [left_width - 10.0, 25.0],
+                                        egui::Button::new("Save profile"),
+                                    )
+                                    .clicked()
+                                {
+                                    let profile = Profile {
+                                        name: client_state.profile_input.name.trim().to_string(),
+                                        status: client_state
+                                            .profile_input
+                                            .status
+                                            .trim()
+                                            .to_string(),
+                                    };
+                                    client_state.profile = profile.clone();
+                                    client_state.dirty = true;
+                                    client_state
+                                        .tx_to_worker
+                                        .send(FromUiCommunication::SetProfile(profile))
+                                        .expect("Failed to transmit to UI");
+                                }
+
+                                if ui.checkbox(&mut client_state.away, "Away").changed() {
+                                    let presence = if client_state.away {
+                                        Presence::Away
+                                    } else {
+                                        Presence::Online
+                                    };
+                                    client_state
+                                        .tx_to_worker
+                                        .send(FromUiCommunication::SetPresence(presence))
+                                        .expect("Failed to transmit to UI");
+                                }
+
                                 // Reload All button
                                 // if ui
                                 //     .add_sized(
@@ -246,20 +647,172 @@ impl Ui {
                 egui::Vec2::new(right_width, total_height),
                 egui::Layout::top_down(egui::Align::LEFT),
                 |ui| {
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(&mut client_state.view, View::Chat, "Chat");
+                        ui.selectable_value(&mut client_state.view, View::Files, "Files");
+                        ui.selectable_value(&mut client_state.view, View::Documents, "Documents");
+                        ui.separator();
+                        let mut servers: Vec<_> = client_state.server_types.iter().collect();
+                        servers.sort_by_key(|(server, _)| **server);
+                        for (server, kind) in servers {
+                            let kind = match kind {
+                                ServerType::ChatServer => "chat",
+                                ServerType::MediaServer => "media",
+                                ServerType::TextServer => "text",
+                            };
+                            ui.label(
+                                RichText::new(format!("Server {server}: {kind}"))
+                                    .color(egui::Color32::GRAY),
+                            );
+                        }
+                    });
+                    match client_state.view {
+                        View::Files => return Self::render_files(ui, client_state),
+                        View::Documents => return Self::render_documents(ui, client_state),
+                        View::Chat => {}
+                    }
+
                     // Clear input if chat changed
                     if old_chat != client_state.current_chat {
                         input.clear();
+                        client_state.editing = None;
+                    }
+
+                    // the messages of the open chat are read, its history is asked the first time
+                    if let Some(Chat::Direct(current_chat_id)) = &client_state.current_chat {
+                        if let Some(ids) = client_state.to_mark_read.remove(current_chat_id) {
+                            client_state.dirty = true;
+                            client_state
+                                .tx_to_worker
+                                .send(FromUiCommunication::MarkRead {
+                                    to_client: *current_chat_id,
+                                    ids,
+                                })
+                                .expect("Failed to transmit to Worker");
+                        }
+                        if !client_state.history_page.contains_key(current_chat_id) {
+                            client_state
+                                .history_page
+                                .insert(*current_chat_id, (None, true));
+                            client_state
+                                .tx_to_worker
+                                .send(FromUiCommunication::GetHistory {
+                                    with: *current_chat_id,
+                                    before: None,
+                                })
+                                .expect("Failed to transmit to Worker");
+                        }
                     }
 
-                    ui.heading("Current Chat");
+                    ui.horizontal(|ui| {
+                        ui.heading("Current Chat");
+                        match client_state.current_chat.clone() {
+                            Some(Chat::Direct(current_chat_id)) => {
+                                ui.label(
+                                    RichText::new(client_state.display_name(current_chat_id))
+                                        .strong(),
+                                );
+                                if let Some(profile) = client_state.profiles.get(&current_chat_id) {
+                                    if !profile.status.is_empty() {
+                                        ui.label(
+                                            RichText::new(&profile.status)
+                                                .italics()
+                                                .color(egui::Color32::GRAY),
+                                        );
+                                    }
+                                }
+                                if client_state.encrypted_chat.contains(&current_chat_id) {
+                                    ui.label(
+                                        RichText::new("end-to-end encrypted")
+                                            .color(egui::Color32::DARK_GREEN),
+                                    );
+                                } else {
+                                    ui.label(
+                                        RichText::new("not encrypted").color(egui::Color32::GRAY),
+                                    );
+                                }
+                                if client_state.offline_chat.contains(&current_chat_id) {
+                                    ui.label(
+                                        RichText::new("offline, messages are queued")
+                                            .color(egui::Color32::GRAY),
+                                    );
+                                }
+                                match client_state.presence.get(&current_chat_id) {
+                                    Some((Presence::Away, _)) => {
+                                        ui.label(RichText::new("away").color(egui::Color32::GRAY));
+                                    }
+                                    Some((Presence::Offline, last_seen)) => {
+                                        let minutes = last_seen
+                                            .elapsed()
+                                            .map(|elapsed| elapsed.as_secs() / 60)
+                                            .unwrap_or_default();
+                                        ui.label(
+                                            RichText::new(format!("last seen {minutes} min ago"))
+                                                .color(egui::Color32::GRAY),
+                                        );
+                                    }
+                                    _ => {}
+                                }
+                                if let Some(since) = client_state.typing.get(&current_chat_id) {
+                                    let shown = since.elapsed();
+                                    if shown < TYPING_TIMEOUT {
+                                        ui.label(
+                                            RichText::new("is typing...")
+                                                .italics()
+                                                .color(egui::Color32::LIGHT_BLUE),
+                                        );
+                                        // hidden again without waiting for other events
+                                        ui.ctx().request_repaint_after(TYPING_TIMEOUT - shown);
+                                    }
+                                }
+                            }
+                            Some(Chat::Room(room)) => {
+                                let members = client_state
+                                    .room_members
+                                    .get(&room)
+                                    .map(|members| {
+                                        members
+                                            .iter()
+                                            .map(|nid| client_state.display_name(*nid))
+                                            .collect::<Vec<_>>()
+                                            .join(", ")
+                                    })
+                                    .unwrap_or_default();
+                                ui.label(
+                                    RichText::new(format!("members: {members}"))
+                                        .color(egui::Color32::GRAY),
+                                );
+                                if ui.button("Leave").clicked() {
+                                    let chat = Chat::Room(room.clone());
+                                    client_state.chat_message.remove(&chat);
+                                    client_state.unread_chat.remove(&chat);
+                                    client_state.room_members.remove(&room);
+                                    client_state.current_chat = None;
+                                    client_state.dirty = true;
+                                    client_state
+                                        .tx_to_worker
+                                        .send(FromUiCommunication::LeaveRoom(room))
+                                        .expect("Failed to transmit to UI");
+                                }
+                            }
+                            Some(Chat::Broadcast) => {
+                                ui.label(
+                                    RichText::new("to every client, not encrypted")
+                                        .color(egui::Color32::GRAY),
+                                );
+                            }
+                            None => {}
+                        }
+                    });
                     ui.separator();
 
-                    if let Some(current_chat_id) = client_state.current_chat {
-                        if let Some(messages) = client_state.chat_message.get(&current_chat_id) {
+                    if let Some(current_chat) = client_state.current_chat.clone() {
+                        if let Some(messages) = client_state.chat_message.get(&current_chat) {
                             // Calculate fixed heights
                             let heading_height = 40.0; // Height for heading + separator
                             let input_height = 50.0; // Fixed height for input area
                             let messages_height = total_height - heading_height - input_height;
+                            let mut change = None; // own message to edit or delete, by id
 
                             // Messages area
                             ui.allocate_ui_with_layout(
@@ -270,20 +823,102 @@ impl Ui {
                                         .id_salt("messages")
                                         .auto_shrink([false, false])
                                         .show(ui, |ui| {
-                                            for (sender_id, msg) in messages {
+                                            if let Chat::Direct(nid) = current_chat {
+                                                if let Some((Some(oldest), true)) =
+                                                    client_state.history_page.get(&nid)
+                                                {
+                                                    if ui.button("Load older messages").clicked() {
+                                                        client_state
+                                                            .tx_to_worker
+                                                            .send(FromUiCommunication::GetHistory {
+                                                                with: nid,
+                                                                before: Some(*oldest),
+                                                            })
+                                                            .expect("Failed to transmit to Worker");
+                                                    }
+                                                }
+                                            }
+                                            for line in messages {
                                                 ui.horizontal_wrapped(|ui| {
                                                     ui.label(
-                                                        RichText::new(format!("Node {sender_id}:"))
-                                                            .strong()
-                                                            .color(egui::Color32::GRAY),
+                                                        RichText::new(format!(
+                                                            "{}:",
+                                                            client_state.display_name(line.from)
+                                                        ))
+                                                        .strong()
+                                                        .color(egui::Color32::GRAY),
                                                     );
-                                                    ui.add(egui::Label::new(msg).wrap());
+                                                    if line.deleted {
+                                                        ui.label(
+                                                            RichText::new("message deleted")
+                                                                .italics()
+                                                                .color(egui::Color32::GRAY),
+                                                        );
+                                                        return;
+                                                    }
+                                                    ui.add(egui::Label::new(&line.text).wrap());
+                                                    if line.edited {
+                                                        ui.label(
+                                                            RichText::new("(edited)")
+                                                                .small()
+                                                                .color(egui::Color32::GRAY),
+                                                        );
+                                                    }
+                                                    if line.from != client_state.my_id {
+                                                        return;
+                                                    }
+                                                    if let Some(id) = line.id {
+                                                        let status =
+                                                            client_state.message_status.get(&id);
+                                                        ui.label(Self::status_icon(status));
+                                                        if line.queued
+                                                            && status
+                                                                < Some(&ReceiptStatus::Delivered)
+                                                        {
+                                                            ui.label(
+                                                                RichText::new("queued")
+                                                                    .small()
+                                                                    .color(egui::Color32::GRAY),
+                                                            );
+                                                        }
+                                                        if ui.small_button("Edit").clicked() {
+                                                            change =
+                                                                Some((id, Some(line.text.clone())));
+                                                        }
+                                                        if ui.small_button("Delete").clicked() {
+                                                            change = Some((id, None));
+                                                        }
+                                                    }
                                                 });
                                                 ui.separator();
                                             }
                                         });
                                 },
                             );
+                            if let (Chat::Direct(nid), Some((id, text))) = (&current_chat, change) {
+                                match text {
+                                    Some(text) => {
+                                        *input = text;
+                                        client_state.editing = Some((*nid, id));
+                                    }
+                                    None => {
+                                        client_state.amend(*nid, client_state.my_id, id, None);
+                                        client_state
+                                            .tx_to_worker
+                                            .send(FromUiCommunication::DeleteMessage {
+                                                to_client: *nid,
+                                                id,
+                                            })
+                                            .expect("Failed to transmit to Worker");
+                                    }
+                                }
+                            }
+                            let editing = match current_chat {
+                                Chat::Direct(nid) => {
+                                    client_state.editing.filter(|(with, _)| *with == nid)
+                                }
+                                _ => None,
+                            };
 
                             // Input area
                             ui.separator();
@@ -292,27 +927,99 @@ impl Ui {
                                 egui::Layout::top_down(egui::Align::LEFT),
                                 |ui| {
                                     ui.horizontal(|ui| {
+                                        if editing.is_some() {
+                                            ui.label(RichText::new("Editing").italics());
+                                        }
                                         let response = ui.text_edit_singleline(input);
+                                        if let Chat::Direct(nid) = current_chat {
+                                            let recently_sent = client_state
+                                                .typing_sent
+                                                .is_some_and(|(to, sent)| {
+                                                    to == nid && sent.elapsed() < TYPING_INTERVAL
+                                                });
+                                            if response.changed()
+                                                && !input.is_empty()
+                                                && !recently_sent
+                                            {
+                                                client_state.typing_sent =
+                                                    Some((nid, Instant::now()));
+                                                client_state
+                                                    .tx_to_worker
+                                                    .send(FromUiCommunication::Typing(nid))
+                                                    .expect("Failed to transmit to Worker");
+                                            }
+                                        }
 
                                         let send_button_clicked = ui.button("Send").clicked();
+                                        if editing.is_some() && ui.button("Cancel").clicked() {
+                                            client_state.editing = None;
+                                            input.clear();
+                                        }
                                         let enter_send = response.lost_focus() && enter_pressed;
 
                                         if (send_button_clicked || enter_send)
                                             && !input.trim().is_empty()
                                         {
-                                            // Add message to chat
+                                            if let Some((nid, id)) = editing {
+                                                client_state.amend(
+                                                    nid,
+                                                    client_state.my_id,
+                                                    id,
+                                                    Some(input.clone()),
+                                                );
+                                                client_state
+                                                    .tx_to_worker
+                                                    .send(FromUiCommunication::EditMessage {
+                                                        to_client: nid,
+                                                        id,
+                                                        message: input.to_string(),
+                                                    })
+                                                    .expect("Failed to transmit to Worker");
+                                                client_state.editing = None;
+                                                input.clear();
+                                                return;
+                                            }
+
+                                            // Add message to chat, direct messages get receipts
+                                            let id = client_state.next_message_id;
+                                            let receipt_id =
+                                                matches!(current_chat, Chat::Direct(_))
+                                                    .then_some(id);
                                             if let Some(chat) =
-                                                client_state.chat_message.get_mut(&current_chat_id)
+                                                client_state.chat_message.get_mut(&current_chat)
                                             {
-                                                chat.push((client_state.my_id, input.clone()));
+                                                chat.push(ChatLine::new(
+                                                    client_state.my_id,
+                                                    input.clone(),
+                                                    receipt_id,
+                                                ));
                                             }
+                                            client_state.next_message_id += 1;
+                                            client_state.dirty = true;
 
+                                            let command = match &current_chat {
+                                                Chat::Direct(current_chat_id) => {
+                                                    FromUiCommunication::SendChatMessage {
+                                                        to_client: *current_chat_id,
+                                                        message: input.to_string(),
+                                                        id,
+                                                    }
+                                                }
+                                                Chat::Broadcast => {
+                                                    FromUiCommunication::SendBroadcast {
+                                                        message: input.to_string(),
+                                                    }
+                                                }
+                                                Chat::Room(room) => {
+                                                    FromUiCommunication::SendRoomMessage {
+                                                        room: room.clone(),
+                                                        message: input.to_string(),
+                                                    }
+                                                }
+                                            };
                                             client_state
                                                 .tx_to_worker
-                                                .send(FromUiCommunication::SendChatMessage {
-                                                    to_client: current_chat_id,
-                                                    message: input.to_string(),
-                                                })
+                                                .send(command)
                                                 .expect("Failed to transmit to Worker");
 
                                             input.clear();
@@ -331,41 +1038,361 @@ impl Ui {
         });
     }
 
+    /// Icon of a sent direct message, `None` until the server accepts it
+    fn status_icon(status: Option<&ReceiptStatus>) -> RichText {
+        match status {
+            None => RichText::new("·").color(egui::Color32::GRAY),
+            Some(ReceiptStatus::Accepted) => RichText::new("✓").color(egui::Color32::GRAY),
+            Some(ReceiptStatus::Delivered) => RichText::new("✓✓").color(egui::Color32::GRAY),
+            Some(ReceiptStatus::Read) => RichText::new("✓✓").color(egui::Color32::LIGHT_BLUE),
+        }
+    }
+
+    /// Keep the furthest status of a sent message, receipts can arrive out of order
+    fn update_status(client_state: &mut ClientState, id: MessageId, status: ReceiptStatus) {
+        let current = client_state.message_status.entry(id).or_insert(status);
+        *current = (*current).max(status);
+    }
+
     pub fn handle_drone_messages(state: &mut UiState) -> bool {
         let mut messages_handled = false;
 
         for (_, client_state) in state.client_states.iter_mut() {
             while let Ok(message) = client_state.rx_from_worker.try_recv() {
                 messages_handled = true;
-                if let ToUICommunication::ChatResponse { response } = message {
+                if let ToUICommunication::MessageDeliveredToServer(id) = message {
+                    Self::update_status(client_state, id, ReceiptStatus::Accepted);
+                } else if let ToUICommunication::Presence {
+                    client,
+                    presence,
+                    last_seen,
+                } = message
+                {
+                    client_state.presence.insert(client, (presence, last_seen));
+                } else if let ToUICommunication::Typing(from) = message {
+                    client_state.typing.insert(from, Instant::now());
+                } else if let ToUICommunication::MediaResponse { server, response } = message {
+                    client_state.media_response(server, response);
+                } else if let ToUICommunication::ServerType { server, kind } = message {
+                    client_state.server_types.insert(server, kind);
+                } else if let ToUICommunication::TextResponse { server, response } = message {
+                    client_state.text_response(server, response);
+                } else if let ToUICommunication::Transfer {
+                    server,
+                    session,
+                    name,
+                    upload,
+                    done,
+                    total,
+                } = message
+                {
+                    client_state.transfer((server, session, upload), name, done, total);
+                } else if let ToUICommunication::ChatResponse { response } = message {
+                    // the id of a message that expects receipts
+                    let (response, receipt) = match response {
+                        ChatResponse::WithReceipt { id, response } => (*response, Some(id)),
+                        response => (response, None),
+                    };
                     match response {
                         ChatResponse::ClientList(nids) => {
+                            client_state
+                                .chat_message
+                                .entry(Chat::Broadcast)
+                                .or_default();
                             for nid in nids {
+                                client_state.offline_chat.remove(&nid);
                                 if nid != client_state.my_id {
-                                    client_state.chat_message.entry(nid).or_default();
+                                    client_state
+                                        .chat_message
+                                        .entry(Chat::Direct(nid))
+                                        .or_default();
                                 }
                             }
                         }
-                        ChatResponse::MessageFrom { from: nid, message } => {
+                        ChatResponse::MessageFrom {
+                            from: nid,
+                            message,
+                            id,
+                        } => {
+                            client_state.typing.remove(&nid);
                             let messages = client_state
                                 .chat_message
-                                .entry(nid)
+                                .entry(Chat::Direct(nid))
                                 .or_insert_with(Vec::new);
 
                             if let Ok(message_string) = String::from_utf8(message) {
-                                messages.push((nid, message_string));
+                                messages.push(ChatLine::new(nid, message_string, Some(id)));
                             } else {
-                                messages.push((nid, "Invalid Message here".to_string()));
+                                messages.push(ChatLine::new(
+                                    nid,
+                                    "Invalid Message here".to_string(),
+                                    Some(id),
+                                ));
                             }
 
-                            client_state.unread_chat.insert(nid);
+                            client_state.unread_chat.insert(Chat::Direct(nid));
+                            client_state.dirty = true;
+                            if let Some(id) = receipt {
+                                client_state.to_mark_read.entry(nid).or_default().push(id);
+                            }
+                        }
+                        ChatResponse::History {
+                            with,
+                            entries,
+                            more,
+                        } => {
+                            let (oldest, _) = client_state
+                                .history_page
+                                .get(&with)
+                                .copied()
+                                .unwrap_or_default();
+                            client_state.merge_history(with, &entries);
+                            let oldest = entries.first().map(|entry| entry.seq).or(oldest);
+                            client_state.history_page.insert(with, (oldest, more));
+                        }
+                        ChatResponse::Directory(profiles) => {
+                            client_state.profiles.extend(profiles);
+                        }
+                        ChatResponse::Receipt { id, status, .. } => {
+                            Self::update_status(client_state, id, status);
+                        }
+                        ChatResponse::ClientLeft(nid) => {
+                            // the chat is kept, the server queues the messages until it is back
+                            client_state.offline_chat.insert(nid);
+                            for members in client_state.room_members.values_mut() {
+                                members.retain(|member| *member != nid);
+                            }
+                        }
+                        ChatResponse::MessageQueued { to, id } => {
+                            client_state.offline_chat.insert(to);
+                            let my_id = client_state.my_id;
+                            let line = client_state
+                                .chat_message
+                                .get_mut(&Chat::Direct(to))
+                                .and_then(|lines| {
+                                    lines
+                                        .iter_mut()
+                                        .find(|line| line.from == my_id && line.id == Some(id))
+                                });
+                            if let Some(line) = line {
+                                line.queued = true;
+                                client_state.dirty = true;
+                            }
+                        }
+                        ChatResponse::BroadcastFrom { from, message } => {
+                            let messages = client_state
+                                .chat_message
+                                .entry(Chat::Broadcast)
+                                .or_default();
+                            match String::from_utf8(message) {
+                                Ok(message_string) => {
+                                    messages.push(ChatLine::new(from, message_string, None))
+                                }
+                                Err(_) => messages.push(ChatLine::new(
+                                    from,
+                                    "Invalid Message here".to_string(),
+                                    None,
+                                )),
+                            }
+                            client_state.unread_chat.insert(Chat::Broadcast);
+                            client_state.dirty = true;
+                        }
+                        ChatResponse::BroadcastReport {
+                            reached,
+                            unreachable,
+                        } => {
+                            let list = |nids: Vec<NodeId>| {
+                                nids.iter()
+                                    .map(|nid| nid.to_string())
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            };
+                            let report = format!(
+                                "(delivered to: {}; unreachable: {})",
+                                list(reached),
+                                list(unreachable)
+                            );
+                            client_state
+                                .chat_message
+                                .entry(Chat::Broadcast)
+                                .or_default()
+                                .push(ChatLine::new(client_state.my_id, report, None));
+                            client_state.dirty = true;
+                        }
+                        ChatResponse::RoomList(rooms) => {
+                            // only the joined rooms are shown as chats
+                            for (room, members) in rooms {
+                                if members.contains(&client_state.my_id) {
+                                    client_state
+                                        .chat_message
+                                        .entry(Chat::Room(room.clone()))
+                                        .or_default();
+                                }
+                                client_state.room_members.insert(room, members);
+                            }
+                        }
+                        ChatResponse::MessageFromRoom {
+                            room,
+                            from,
+                            message,
+                        } => {
+                            let chat = Chat::Room(room);
+                            let messages =
+                                client_state.chat_message.entry(chat.clone()).or_default();
+                            match String::from_utf8(message) {
+                                Ok(message_string) => {
+                                    messages.push(ChatLine::new(from, message_string, None))
+                                }
+                                Err(_) => messages.push(ChatLine::new(
+                                    from,
+                                    "Invalid Message here".to_string(),
+                                    None,
+                                )),
+                            }
+                            client_state.unread_chat.insert(chat);
+                            client_state.dirty = true;
+                        }
+                        ChatResponse::MessageEdited {
+                            from, id, message, ..
+                        } => {
+                            let text = String::from_utf8(message)
+                                .unwrap_or_else(|_| "Invalid Message here".to_string());
+                            client_state.amend(from, from, id, Some(text));
+                        }
+                        ChatResponse::MessageDeleted { from, id } => {
+                            client_state.amend(from, from, id, None);
+                        }
+                        ChatResponse::ClientKeys(keys) => {
+                            for (nid, _) in keys {
+                                if nid != client_state.my_id {
+                                    client_state.encrypted_chat.insert(nid);
+                                }
+                            }
                         }
                         _ => {}
                     }
                 };
             }
+            // lines changed by the worker or by the last frame, the rest is saved on exit
+            if client_state.dirty {
+                client_state.save();
+            }
         }
 
         messages_handled
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam_channel::unbounded;
+
+    fn entry(seq: u64, from: NodeId, id: MessageId, text: &str) -> HistoryEntry {
+        HistoryEntry {
+            seq,
+            timestamp: SystemTime::UNIX_EPOCH,
+            to: if from == 3 { 5 } else { 3 },
+            id,
+            message: ChatResponse::MessageFrom {
+                from,
+                message: text.as_bytes().to_vec(),
+                id,
+            },
+            edited: false,
+            deleted: false,
+        }
+    }
+
+    #[test]
+    fn test_queued_flag_on_the_line_with_its_id() {
+        let (tx_to_ui, rx_from_worker) = unbounded();
+        let (tx_to_worker, _rx_from_ui) = unbounded();
+        let mut state = UiState::new();
+        state.add_client(3, ClientState::new(3, rx_from_worker, tx_to_worker));
+        state
+            .client_states
+            .get_mut(&3)
+            .unwrap()
+            .chat_message
+            .insert(
+                Chat::Direct(5),
+                vec![
+                    ChatLine::new(3, "first".to_string(), Some(0)),
+                    ChatLine::new(3, "second".to_string(), Some(1)),
+                ],
+            );
+
+        // two messages are pending, the server names the one it queued
+        tx_to_ui
+            .send(ToUICommunication::ChatResponse {
+                response: ChatResponse::MessageQueued { to: 5, id: 0 },
+            })
+            .unwrap();
+        assert!(Ui::handle_drone_messages(&mut state));
+        let client_state = &state.client_states[&3];
+        let lines = &client_state.chat_message[&Chat::Direct(5)];
+        assert!(lines[0].queued && !lines[1].queued);
+        assert!(client_state.offline_chat.contains(&5));
+    }
+
+    #[test]
+    fn test_client_that_left_is_offline() {
+        let (tx_to_ui, rx_from_worker) = unbounded();
+        let (tx_to_worker, _rx_from_ui) = unbounded();
+        let mut state = UiState::new();
+        state.add_client(3, ClientState::new(3, rx_from_worker, tx_to_worker));
+        let send = |response| {
+            tx_to_ui
+                .send(ToUICommunication::ChatResponse { response })
+                .unwrap()
+        };
+
+        // the chat is kept, messages to it are queued by the server
+        send(ChatResponse::ClientList(vec![3, 5]));
+        send(ChatResponse::ClientLeft(5));
+        Ui::handle_drone_messages(&mut state);
+        let client_state = &state.client_states[&3];
+        assert!(client_state.chat_message.contains_key(&Chat::Direct(5)));
+        assert!(client_state.offline_chat.contains(&5));
+
+        send(ChatResponse::ClientList(vec![3, 5]));
+        Ui::handle_drone_messages(&mut state);
+        assert!(!state.client_states[&3].offline_chat.contains(&5));
+    }
+
+    #[test]
+    fn test_history_merged_by_id() {
+        let (_, rx_from_worker) = unbounded();
+        let (tx_to_worker, _rx_from_ui) = unbounded();
+        let mut state = ClientState::new(3, rx_from_worker, tx_to_worker);
+        let mut sent = ChatLine::new(3, "hi".to_string(), Some(0));
+        sent.queued = true;
+        state.chat_message.insert(
+            Chat::Direct(5),
+            vec![sent.clone(), ChatLine::new(5, "hi".to_string(), Some(1))],
+        );
+        state.message_status.insert(0, ReceiptStatus::Read);
+
+        let mut edited = entry(3, 5, 1, "hi again");
+        edited.edited = true;
+        state.merge_history(5, &[entry(1, 3, 2, "hi"), entry(2, 3, 0, "hi"), edited]);
+        let lines = &state.chat_message[&Chat::Direct(5)];
+        // same text, another id: an older message
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], ChatLine::new(3, "hi".to_string(), Some(2)));
+        // the shown line keeps its state and gets the edit
+        assert_eq!(lines[1], sent);
+        assert!(lines[2].edited && lines[2].text == "hi again");
+        assert_eq!(state.message_status.get(&0), Some(&ReceiptStatus::Read));
+        assert_eq!(state.message_status.get(&2), Some(&ReceiptStatus::Accepted));
+
+        // a page loaded again adds nothing, a tombstone deletes the line
+        let mut deleted = entry(3, 5, 1, "");
+        deleted.deleted = true;
+        state.merge_history(5, &[entry(1, 3, 2, "hi"), deleted]);
+        let lines = &state.chat_message[&Chat::Direct(5)];
+        assert_eq!(lines.len(), 3);
+        assert!(lines[2].deleted && lines[2].text.is_empty());
+    }
+}
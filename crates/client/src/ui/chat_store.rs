@@ -0,0 +1,217 @@
+use super::{Chat, ChatLine, ClientState};
+use message::{MessageId, Profile, ReceiptStatus};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use wg_2024::network::NodeId;
+
+// ------------------------------ SAVED CHATS
+// The chats of each client are saved as one JSON file in the history directory, named after
+// the node, and restored when its `ClientState` is created. Maps are saved as lists of pairs
+// because JSON keys can only be strings.
+
+/// What survives a restart of a `ClientState`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(super) struct SavedChats {
+    chats: Vec<(Chat, Vec<ChatLine>)>,
+    unread: Vec<Chat>,
+    next_message_id: MessageId,
+    message_status: Vec<(MessageId, ReceiptStatus)>,
+    to_mark_read: Vec<(NodeId, Vec<MessageId>)>,
+    #[serde(default)]
+    profile: Profile,
+    #[serde(default)]
+    profiles: Vec<(NodeId, Profile)>,
+}
+
+/// File of the chats of `node_id` inside `dir`
+pub(super) fn history_file(dir: &Path, node_id: NodeId) -> PathBuf {
+    dir.join(format!("client_{node_id}.json"))
+}
+
+/// Chats saved in `path`, `None` if they were never saved
+pub(super) fn load(path: &Path) -> io::Result<Option<SavedChats>> {
+    match fs::read(path) {
+        Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Replace the file in `path`, through a temporary file so a crash never leaves half of it
+pub(super) fn save(path: &Path, chats: &SavedChats) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec(chats)?)?;
+    fs::rename(tmp, path)
+}
+
+impl SavedChats {
+    pub(super) fn from_state(state: &ClientState) -> Self {
+        Self {
+            chats: state
+                .chat_message
+                .iter()
+                .map(|(chat, messages)| (chat.clone(), messages.clone()))
+                .collect(),
+            unread: state.unread_chat.iter().cloned().collect(),
+            next_message_id: state.next_message_id,
+            message_status: state
+                .message_status
+                .iter()
+                .map(|(id, status)| (*id, *status))
+                .collect(),
+            to_mark_read: state
+                .to_mark_read
+                .iter()
+                .map(|(nid, ids)| (*nid, ids.clone()))
+                .collect(),
+            profile: state.profile.clone(),
+            profiles: state
+                .profiles
+                .iter()
+                .map(|(nid, profile)| (*nid, profile.clone()))
+                .collect(),
+        }
+    }
+
+    pub(super) fn restore(self, state: &mut ClientState) {
+        state.chat_message = self.chats.into_iter().collect();
+        state.unread_chat = self.unread.into_iter().collect();
+        state.next_message_id = self.next_message_id;
+        state.message_status = self.message_status.into_iter().collect();
+        state.to_mark_read = self.to_mark_read.into_iter().collect();
+        state.profile_input = self.profile.clone();
+        state.profile = self.profile;
+        state.profiles = self.profiles.into_iter().collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::FromUiCommunication;
+    use crossbeam_channel::{unbounded, Receiver};
+    use std::env;
+
+    fn client_state(my_id: NodeId) -> (ClientState, Receiver<FromUiCommunication>) {
+        let (_, rx_from_worker) = unbounded();
+        let (tx_to_worker, rx_from_ui) = unbounded();
+        let state = ClientState::new(my_id, rx_from_worker, tx_to_worker);
+        (state, rx_from_ui)
+    }
+
+    #[test]
+    fn test_chats_survive_a_restart() {
+        let dir = env::temp_dir().join(format!("flypath_chats_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        // nothing saved yet
+        let (state, rx_from_ui) = client_state(3);
+        let mut state = state.with_history_dir(&dir);
+        assert!(state.chat_message.is_empty());
+        assert!(rx_from_ui.try_recv().is_err());
+
+        state.chat_message.insert(
+            Chat::Direct(5),
+            vec![
+                ChatLine::new(3, "hi".to_string(), Some(0)),
+                ChatLine::new(5, "hello".to_string(), Some(7)),
+            ],
+        );
+        state.chat_message.insert(
+            Chat::Room("lobby".to_string()),
+            vec![ChatLine::new(6, "hey".to_string(), None)],
+        );
+        state.amend(5, 3, 0, Some("hi!".to_string()));
+        state.amend(5, 5, 7, None);
+        state.unread_chat.insert(Chat::Room("lobby".to_string()));
+        state.next_message_id = 1;
+        state.message_status.insert(0, ReceiptStatus::Delivered);
+        state.to_mark_read.insert(5, vec![7]);
+        state.profile = Profile {
+            name: "Carol".to_string(),
+            status: String::new(),
+        };
+        state.profiles.insert(
+            5,
+            Profile {
+                name: "Dave".to_string(),
+                status: "busy".to_string(),
+            },
+        );
+        state.dirty = true;
+        state.save();
+        assert!(!state.dirty);
+
+        let (restored, rx_from_ui) = client_state(3);
+        let restored = restored.with_history_dir(&dir);
+        assert_eq!(restored.chat_message, state.chat_message);
+        let lines = &restored.chat_message[&Chat::Direct(5)];
+        assert!(lines[0].edited && lines[0].text == "hi!");
+        assert!(lines[1].deleted && lines[1].text.is_empty());
+        assert_eq!(restored.unread_chat, state.unread_chat);
+        assert_eq!(restored.next_message_id, 1);
+        assert_eq!(
+            restored.message_status.get(&0),
+            Some(&ReceiptStatus::Delivered)
+        );
+        assert_eq!(restored.to_mark_read.get(&5), Some(&vec![7]));
+        assert_eq!(restored.display_name(5), "Dave");
+        // the restored profile is sent to the servers again
+        assert!(matches!(
+            rx_from_ui.try_recv(),
+            Ok(FromUiCommunication::SetProfile(profile)) if profile.name == "Carol"
+        ));
+
+        // every node has its own file
+        assert!(client_state(4)
+            .0
+            .with_history_dir(&dir)
+            .chat_message
+            .is_empty());
+
+        // a damaged file is ignored
+        fs::write(history_file(&dir, 3), b"not json").unwrap();
+        assert!(client_state(3)
+            .0
+            .with_history_dir(&dir)
+            .chat_message
+            .is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_chats_kept_while_logged_out() {
+        let dir = env::temp_dir().join(format!("flypath_logout_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut state = client_state(3).0.with_history_dir(&dir);
+        state.chat_message.insert(
+            Chat::Direct(5),
+            vec![ChatLine::new(5, "hello".to_string(), Some(7))],
+        );
+        state.save();
+
+        // the view is cleared, the file is not
+        state.logged_out = true;
+        state.chat_message.clear();
+        state.dirty = true;
+        state.save();
+        state.logged_out = false;
+        state.load();
+        assert_eq!(state.chat_message[&Chat::Direct(5)].len(), 1);
+
+        // receipts are not saved at once, but on exit
+        state.message_status.insert(0, ReceiptStatus::Read);
+        drop(state);
+        let restored = client_state(3).0.with_history_dir(&dir);
+        assert_eq!(restored.message_status.get(&0), Some(&ReceiptStatus::Read));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+use super::{ClientState, Ui};
+use crate::communication::FromUiCommunication;
+use egui::RichText;
+use message::{ChatResponse, FileName};
+use wg_2024::network::NodeId;
+
+// ------------------------------ DOCUMENTS
+// Text documents served by the text servers, listed on the left and read on the right.
+
+/// A document fetched from a text server, shown in the viewer
+#[derive(Debug, Clone)]
+pub(super) struct OpenDocument {
+    server: NodeId,
+    name: FileName,
+    text: String,
+}
+
+impl ClientState {
+    pub(super) fn text_response(&mut self, server: NodeId, response: ChatResponse) {
+        match response {
+            ChatResponse::DocumentList(names) => {
+                self.documents.insert(server, names);
+            }
+            ChatResponse::Document { name, text } => {
+                self.document_notice = None;
+                self.open_document = Some(OpenDocument { server, name, text });
+            }
+            ChatResponse::ErrorWrongDocument(name) => {
+                self.document_notice = Some(format!("server {server} has no document {name}"));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Ui {
+    pub(super) fn render_documents(ui: &mut egui::Ui, client_state: &mut ClientState) {
+        let total_width = ui.available_width();
+        let total_height = ui.available_height();
+        let mut servers: Vec<NodeId> = client_state.documents.keys().copied().collect();
+        servers.sort();
+        let mut fetch = None;
+
+        ui.horizontal(|ui| {
+            // Left column (1/4 width) - Documents of every text server
+            ui.allocate_ui_with_layout(
+                egui::Vec2::new(total_width / 4.0, total_height),
+                egui::Layout::top_down(egui::Align::LEFT),
+                |ui| {
+                    if ui.button("Refresh").clicked() {
+                        client_state
+                            .tx_to_worker
+                            .send(FromUiCommunication::ListDocuments)
+                            .expect("Failed to transmit to Worker");
+                    }
+                    ui.separator();
+                    egui::ScrollArea::vertical()
+                        .id_salt("document_list")
+                        .auto_shrink([false, false])
+                        .show(ui, |ui| {
+                            if servers.is_empty() {
+                                ui.label("No text servers found");
+                            }
+                            for server in servers {
+                                ui.label(RichText::new(format!("Server {server}")).strong());
+                                let names = &client_state.documents[&server];
+                                if names.is_empty() {
+                                    ui.label(
+                                        RichText::new("no documents").color(egui::Color32::GRAY),
+                                    );
+                                }
+                                for name in names {
+                                    let open =
+                                        client_state.open_document.as_ref().is_some_and(|open| {
+                                            open.server == server && open.name == *name
+                                        });
+                                    if ui.selectable_label(open, name).clicked() {
+                                        fetch = Some((server, name.clone()));
+                                    }
+                                }
+                                ui.separator();
+                            }
+                        });
+                },
+            );
+
+            // Right column (3/4 width) - The open document
+            ui.allocate_ui_with_layout(
+                egui::Vec2::new(ui.available_width(), total_height),
+                egui::Layout::top_down(egui::Align::LEFT),
+                |ui| {
+                    if let Some(notice) = &client_state.document_notice {
+                        ui.label(RichText::new(notice).color(egui::Color32::LIGHT_RED));
+                    }
+                    match &client_state.open_document {
+                        Some(document) => {
+                            ui.horizontal(|ui| {
+                                ui.heading(&document.name);
+                                ui.label(
+                                    RichText::new(format!("from server {}", document.server))
+                                        .color(egui::Color32::GRAY),
+                                );
+                            });
+                            ui.separator();
+                            egui::ScrollArea::vertical()
+                                .id_salt("document_text")
+                                .auto_shrink([false, false])
+                                .show(ui, |ui| {
+                                    ui.label(&document.text);
+                                });
+                        }
+                        None => {
+                            ui.label("No document open");
+                        }
+                    }
+                },
+            );
+        });
+
+        if let Some((server, name)) = fetch {
+            client_state
+                .tx_to_worker
+                .send(FromUiCommunication::GetDocument { server, name })
+                .expect("Failed to transmit to Worker");
+        }
+    }
+}
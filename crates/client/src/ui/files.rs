@@ -0,0 +1,212 @@
+use super::{ClientState, Ui};
+use crate::communication::FromUiCommunication;
+use egui::RichText;
+use log::warn;
+use message::{ChatResponse, FileName};
+use std::fs;
+use std::path::{Path, PathBuf};
+use wg_2024::network::NodeId;
+
+// ------------------------------ FILES
+// Files stored by the media servers, uploaded from a local path and downloaded next to the
+// saved chats. Every upload and download longer than one fragment shows its progress.
+
+/// Notices shown under the files, the oldest are dropped
+const MAX_FILE_NOTICES: usize = 5;
+
+/// Progress of a file being sent to or received from a media server
+#[derive(Debug, Clone)]
+pub(super) struct Transfer {
+    name: Option<FileName>, // known only for uploads
+    done: u64,
+    total: u64,
+}
+
+impl ClientState {
+    /// Where the files downloaded by this client are written
+    fn download_dir(&self) -> PathBuf {
+        let dir = self
+            .history_file
+            .as_deref()
+            .and_then(Path::parent)
+            .unwrap_or(Path::new("."));
+        dir.join(format!("downloads_{}", self.my_id))
+    }
+
+    fn file_notice(&mut self, notice: String) {
+        if self.file_notices.len() >= MAX_FILE_NOTICES {
+            self.file_notices.remove(0);
+        }
+        self.file_notices.push(notice);
+    }
+
+    pub(super) fn media_response(&mut self, server: NodeId, response: ChatResponse) {
+        match response {
+            ChatResponse::FileList(files) => {
+                self.upload_server.get_or_insert(server);
+                self.files.insert(server, files);
+            }
+            ChatResponse::FileStored(file) => {
+                self.file_notice(format!("{} uploaded to server {server}", file.name));
+                let files = self.files.entry(server).or_default();
+                files.retain(|stored| stored.name != file.name);
+                files.push(file);
+                files.sort_by(|a, b| a.name.cmp(&b.name));
+            }
+            ChatResponse::File { name, data } => {
+                // only the last component, the server can't choose where it is written
+                let Some(file_name) = Path::new(&name).file_name() else {
+                    self.file_notice(format!("{name} can't be saved"));
+                    return;
+                };
+                let path = self.download_dir().join(file_name);
+                let saved =
+                    fs::create_dir_all(self.download_dir()).and_then(|_| fs::write(&path, data));
+                match saved {
+                    Ok(()) => self.file_notice(format!("{name} saved to {}", path.display())),
+                    Err(e) => {
+                        warn!("{}: unable to save {:?}: {}", self.my_id, path, e);
+                        self.file_notice(format!("{name} can't be saved: {e}"));
+                    }
+                }
+            }
+            ChatResponse::ErrorWrongFile(name) => {
+                self.file_notice(format!("server {server} refused {name}"));
+            }
+            _ => {}
+        }
+    }
+
+    pub(super) fn transfer(
+        &mut self,
+        key: (NodeId, u64, bool),
+        name: Option<FileName>,
+        done: u64,
+        total: u64,
+    ) {
+        if done >= total {
+            self.transfers.remove(&key);
+        } else {
+            self.transfers.insert(key, Transfer { name, done, total });
+        }
+    }
+}
+
+impl Ui {
+    pub(super) fn render_files(ui: &mut egui::Ui, client_state: &mut ClientState) {
+        ui.horizontal(|ui| {
+            let mut servers: Vec<NodeId> = client_state.files.keys().copied().collect();
+            servers.sort();
+            let selected = client_state
+                .upload_server
+                .map(|server| format!("Server {server}"))
+                .unwrap_or_else(|| "No media server".to_string());
+            egui::ComboBox::from_id_salt("upload_server")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    for server in servers {
+                        ui.selectable_value(
+                            &mut client_state.upload_server,
+                            Some(server),
+                            format!("Server {server}"),
+                        );
+                    }
+                });
+            ui.add(
+                egui::TextEdit::singleline(&mut client_state.file_path_input)
+                    .hint_text("Path of the file to upload"),
+            );
+            let path = PathBuf::from(client_state.file_path_input.trim());
+            if ui.button("Upload").clicked() {
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string());
+                match (client_state.upload_server, name) {
+                    (Some(server), Some(name)) => match fs::read(&path) {
+                        Ok(data) => {
+                            client_state
+                                .tx_to_worker
+                                .send(FromUiCommunication::UploadFile { server, name, data })
+                                .expect("Failed to transmit to Worker");
+                            client_state.file_path_input.clear();
+                        }
+                        Err(e) => client_state
+                            .file_notice(format!("{} can't be read: {e}", path.display())),
+                    },
+                    (None, _) => client_state.file_notice("no media server found".to_string()),
+                    (_, None) => client_state.file_notice("choose a file to upload".to_string()),
+                }
+            }
+            if ui.button("Refresh").clicked() {
+                client_state
+                    .tx_to_worker
+                    .send(FromUiCommunication::ListFiles)
+                    .expect("Failed to transmit to Worker");
+            }
+        });
+        ui.separator();
+
+        let mut transfers: Vec<_> = client_state.transfers.iter().collect();
+        transfers.sort_by_key(|(key, _)| **key);
+        for ((server, _, upload), transfer) in transfers {
+            let label = match (upload, &transfer.name) {
+                (true, Some(name)) => format!("Uploading {name} to server {server}"),
+                (true, None) => format!("Uploading to server {server}"),
+                (false, _) => format!("Downloading from server {server}"),
+            };
+            ui.horizontal(|ui| {
+                ui.label(label);
+                ui.add(
+                    egui::ProgressBar::new(transfer.done as f32 / transfer.total as f32)
+                        .text(format!("{}/{} fragments", transfer.done, transfer.total)),
+                );
+            });
+        }
+
+        let mut servers: Vec<NodeId> = client_state.files.keys().copied().collect();
+        servers.sort();
+        let mut download = None;
+        egui::ScrollArea::vertical()
+            .id_salt("files")
+            .max_height(ui.available_height() - 120.0)
+            .show(ui, |ui| {
+                if servers.is_empty() {
+                    ui.label("No media servers found");
+                }
+                for server in servers {
+                    ui.label(RichText::new(format!("Server {server}")).strong());
+                    let files = &client_state.files[&server];
+                    if files.is_empty() {
+                        ui.label(RichText::new("no files").color(egui::Color32::GRAY));
+                    }
+                    for file in files {
+                        ui.horizontal(|ui| {
+                            ui.label(&file.name);
+                            ui.label(
+                                RichText::new(format!(
+                                    "{} bytes, by {}",
+                                    file.size,
+                                    client_state.display_name(file.owner)
+                                ))
+                                .color(egui::Color32::GRAY),
+                            );
+                            if ui.button("Download").clicked() {
+                                download = Some((server, file.name.clone()));
+                            }
+                        });
+                    }
+                    ui.separator();
+                }
+            });
+        if let Some((server, name)) = download {
+            client_state
+                .tx_to_worker
+                .send(FromUiCommunication::DownloadFile { server, name })
+                .expect("Failed to transmit to Worker");
+        }
+
+        for notice in client_state.file_notices.iter() {
+            ui.label(RichText::new(notice).color(egui::Color32::GRAY));
+        }
+    }
+}
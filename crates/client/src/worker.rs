@@ -1,25 +1,38 @@
 use crate::channel::ChannelManager;
-use crate::communication::FromUiCommunication::{AskClientList, RefreshTopology, SendChatMessage};
+use crate::communication::FromUiCommunication::{
+    AskClientList, CreateRoom, DeleteMessage, DownloadFile, EditMessage, GetDocument, GetHistory,
+    JoinRoom, LeaveRoom, ListDocuments, ListFiles, Login, Logout, MarkRead, RefreshTopology,
+    SendBroadcast, SendChatMessage, SendRoomMessage, SetPresence, SetProfile, Typing, UploadFile,
+};
 use crate::communication::{FromUiCommunication, ToUICommunication};
-use crate::message::MessagerManager;
+use crate::message::{FragmentStatus, MessagerManager};
 use crate::network::NetworkManager;
 use crossbeam_channel::{select_biased, Receiver, Sender};
 use log::{debug, error, info, warn};
 use message::NodeEvent::{ControllerShortcut, CreateMessage};
-use message::{ChatRequest, NodeCommand, NodeEvent};
+use message::{
+    ChatRequest, Features, NodeCommand, NodeEvent, Presence, Profile, ReceiptStatus, ServerType,
+};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use wg_2024::network::{NodeId, SourceRoutingHeader};
 use wg_2024::packet::NodeType::{Client, Drone};
-use wg_2024::packet::Packet;
 use wg_2024::packet::PacketType::{Ack, FloodRequest, FloodResponse, MsgFragment, Nack};
+use wg_2024::packet::{NackType, Packet};
+
+/// Entries asked with each `GetHistory`
+const HISTORY_PAGE: u32 = 20;
 
 pub struct Worker {
     my_id: NodeId,
     network: NetworkManager,
     message: MessagerManager,
     channels: Rc<RefCell<ChannelManager>>,
+    logged_out: bool, // no registration to the servers found by the next floods
+    profile: Option<Profile>, // set by the UI, sent after each registration
+    presence: Presence, // set by the UI, sent after each registration if Away
+    handshaken: HashSet<NodeId>, // chat servers the registration was sent to since they were reached
 }
 
 impl Worker {
@@ -42,6 +55,10 @@ impl Worker {
             network,
             message,
             channels,
+            logged_out: false,
+            profile: None,
+            presence: Presence::Online,
+            handshaken: HashSet::new(),
         }
     }
 
@@ -88,32 +105,199 @@ impl Worker {
                         self.network.send_flood_request();
                     }
                     AskClientList => {
-                        let servers: Vec<_> =
-                            self.network.state.server_list.iter().cloned().collect();
+                        let servers = self._chat_servers();
                         for server in servers {
                             self._send_message(&server, ChatRequest::ClientList);
+                            self._send_message(&server, ChatRequest::ClientKeys);
+                            self._send_message(&server, ChatRequest::Directory);
+                            self._send_message(&server, ChatRequest::WatchPresence);
+                            if self.message.server_agreed(&server, Features::GROUPS) {
+                                self._send_message(&server, ChatRequest::RoomList);
+                            }
                         }
                     }
+                    Logout => {
+                        info!("{}: Logout from every server", self.my_id);
+                        self.logged_out = true;
+                        self.handshaken.clear();
+                        self.message.registered_on.clear();
+                        for server in self._chat_servers() {
+                            self._send_message(&server, ChatRequest::Unregister);
+                        }
+                        self.message.clients.clear();
+                        self.message.departed.clear();
+                        self.message.public_keys.clear();
+                        self.message.rooms.clear();
+                    }
+                    Login => {
+                        info!("{}: Login to every server", self.my_id);
+                        self.logged_out = false;
+                        let servers: Vec<_> =
+                            self.network.state.server_list.iter().cloned().collect();
+                        self._registry_and_client_list(&servers);
+                    }
                     SendChatMessage {
                         to_client: destination,
                         message: body,
+                        id,
+                    } => {
+                        if let Some(sid) = self.message.server_for(&destination) {
+                            let mut request = self.message.chat_request(destination, id, body);
+                            if self.message.server_supports(&sid, Features::RECEIPTS) {
+                                request = ChatRequest::WithReceipt {
+                                    id,
+                                    request: Box::new(request),
+                                };
+                            }
+                            self._send_message(&sid, request);
+                        } else {
+                            warn!(
+                                "{}: Client {:?} has no known servers",
+                                self.my_id, destination
+                            );
+                        }
+                    }
+                    EditMessage {
+                        to_client,
+                        id,
+                        message,
                     } => {
-                        if let Some(sids) = self.message.clients.get(&destination) {
-                            if let Some(&sid) = sids.iter().next() {
-                                let request = ChatRequest::SendMessage {
+                        if let Some(sid) = self.message.server_for(&to_client) {
+                            let request = self.message.edit_request(to_client, id, message);
+                            self._send_message(&sid, request);
+                        }
+                    }
+                    DeleteMessage { to_client, id } => {
+                        if let Some(sid) = self.message.server_for(&to_client) {
+                            let request = ChatRequest::DeleteMessage {
+                                from: self.my_id,
+                                to: to_client,
+                                id,
+                            };
+                            self._send_message(&sid, request);
+                        }
+                    }
+                    MarkRead { to_client, ids } => {
+                        if let Some(sid) = self.message.server_for(&to_client) {
+                            for id in ids {
+                                let receipt = ChatRequest::Receipt {
                                     from: self.my_id,
-                                    to: destination,
-                                    message: body,
+                                    to: to_client,
+                                    id,
+                                    status: ReceiptStatus::Read,
                                 };
-                                self._send_message(&sid, request);
-                            } else {
-                                warn!(
-                                    "{}: Client {:?} has no known servers",
-                                    self.my_id, destination
-                                );
+                                self._send_message(&sid, receipt);
                             }
                         }
                     }
+                    GetHistory { with, before } => {
+                        if let Some(sid) = self.message.server_for(&with) {
+                            let request = ChatRequest::GetHistory {
+                                with,
+                                before,
+                                limit: HISTORY_PAGE,
+                            };
+                            self._send_message(&sid, request);
+                        }
+                    }
+                    SendBroadcast { message } => {
+                        // the server hands it to the other servers for their clients
+                        if let Some(server) = self._chat_servers().first() {
+                            let request = ChatRequest::Broadcast {
+                                from: self.my_id,
+                                message,
+                            };
+                            self._send_message(server, request);
+                        }
+                    }
+                    SetProfile(profile) => {
+                        info!("{}: Profile set to {:?}", self.my_id, profile);
+                        let servers = self._chat_servers();
+                        for server in servers {
+                            self._send_message(&server, ChatRequest::SetProfile(profile.clone()));
+                        }
+                        self.profile = Some(profile);
+                    }
+                    SetPresence(presence) => {
+                        info!("{}: Presence set to {:?}", self.my_id, presence);
+                        let servers = self._chat_servers();
+                        for server in servers {
+                            self._send_message(&server, ChatRequest::SetPresence(presence));
+                        }
+                        self.presence = presence;
+                    }
+                    Typing(to) => {
+                        if let Some(sid) = self.message.server_for(&to) {
+                            let request = ChatRequest::Typing {
+                                from: self.my_id,
+                                to,
+                            };
+                            self._send_message(&sid, request);
+                        }
+                    }
+                    ListFiles => {
+                        for server in self.network.state.servers_of(ServerType::MediaServer) {
+                            self._send_message(&server, ChatRequest::FileList);
+                        }
+                    }
+                    UploadFile { server, name, data } => {
+                        info!(
+                            "{}: Upload {:?} of {} bytes to {}",
+                            self.my_id,
+                            name,
+                            data.len(),
+                            server
+                        );
+                        self._send_message(&server, ChatRequest::UploadFile { name, data });
+                    }
+                    DownloadFile { server, name } => {
+                        self._send_message(&server, ChatRequest::DownloadFile(name));
+                    }
+                    ListDocuments => {
+                        for server in self.network.state.servers_of(ServerType::TextServer) {
+                            self._send_message(&server, ChatRequest::DocumentList);
+                        }
+                    }
+                    GetDocument { server, name } => {
+                        self._send_message(&server, ChatRequest::GetDocument(name));
+                    }
+                    CreateRoom(room) => {
+                        // hosted by the first server with rooms
+                        let servers: Vec<_> = self
+                            ._chat_servers()
+                            .into_iter()
+                            .filter(|server| self.message.server_agreed(server, Features::GROUPS))
+                            .collect();
+                        if let Some(sid) = servers.first() {
+                            self._send_message(sid, ChatRequest::CreateRoom(room));
+                        } else {
+                            warn!("{}: No server supports rooms", self.my_id);
+                        }
+                    }
+                    JoinRoom(room) => {
+                        if let Some(&sid) = self.message.rooms.get(&room) {
+                            self._send_message(&sid, ChatRequest::JoinRoom(room));
+                        } else {
+                            warn!("{}: Room {:?} has no known servers", self.my_id, room);
+                        }
+                    }
+                    LeaveRoom(room) => {
+                        if let Some(&sid) = self.message.rooms.get(&room) {
+                            self._send_message(&sid, ChatRequest::LeaveRoom(room));
+                        }
+                    }
+                    SendRoomMessage { room, message } => {
+                        if let Some(&sid) = self.message.rooms.get(&room) {
+                            let request = ChatRequest::SendToRoom {
+                                from: self.my_id,
+                                room,
+                                message,
+                            };
+                            self._send_message(&sid, request);
+                        } else {
+                            warn!("{}: Room {:?} has no known servers", self.my_id, room);
+                        }
+                    }
                 }
             }
             if let Some(pack) = pack {
@@ -124,6 +308,8 @@ impl Worker {
                 info!("{}: Network State EXPIRED, ask for flooding", self.my_id);
                 self.network.send_flood_request();
             }
+
+            self.message.collect_garbage();
         }
     }
 
@@ -181,6 +367,7 @@ impl Worker {
                             self.network.update_network_from_flood_response(response)
                         {
                             self._send_buffer(&server_reach);
+                            self._reconnected(&server_reach);
                             self._registry_and_client_list(&server_reach);
                         }
                     }
@@ -191,6 +378,7 @@ impl Worker {
                         .update_network_from_flood_response(flood_response)
                     {
                         self._send_buffer(&server_reach);
+                        self._reconnected(&server_reach);
                         self._registry_and_client_list(&server_reach);
                     }
                 }
@@ -200,8 +388,14 @@ impl Worker {
                     self.message.ack_and_build_message(ack, session);
                 }
                 Nack(nack) => {
-                    self.network.update_network_from_nack(nack, &from);
-                    if let Some(fragment) = self.message.get_dropped_fragment(nack, session) {
+                    // the destination asks a corrupted message again, no drone dropped it
+                    let corrupted = matches!(nack.nack_type, NackType::Dropped)
+                        && self.message.get_destination(&session) == Some(from);
+                    if !corrupted {
+                        self.network.update_network_from_nack(nack, &from);
+                    }
+                    if let Some(fragment) = self.message.get_dropped_fragment(nack, session, &from)
+                    {
                         let packet = Packet::new_fragment(
                             SourceRoutingHeader::empty_route(),
                             session,
@@ -222,41 +416,77 @@ impl Worker {
                     }
                 }
                 MsgFragment(frag) => {
-                    if self
+                    match self
                         .message
                         .save_received_message(frag.clone(), session, from)
                     {
-                        let mut packet = Packet::new_ack(
-                            SourceRoutingHeader::empty_route(),
-                            session,
-                            frag.fragment_index,
-                        );
-                        if self.network.send_packet(&packet, &from) {
-                            debug!(
-                                "{}: sended ACK frag_index {}, session {}, to {}",
-                                self.my_id, frag.fragment_index, session, from
-                            );
-                        } else {
-                            debug!(
-                                "{}: TRANSMIT SHORTCUT ACK frag_index {}, session {}, to {}",
-                                self.my_id, frag.fragment_index, session, from
-                            );
-                            let mut return_hops = vec![from];
-                            return_hops.extend(packet.routing_header.hops.iter().rev().cloned());
-                            packet.routing_header =
-                                SourceRoutingHeader::with_first_hop(return_hops);
-                            self.channels
-                                .borrow()
-                                .tx_ctrl
-                                .send(ControllerShortcut(packet))
-                                .expect("Failed to transmit to CONTROLLER");
+                        FragmentStatus::Stored(to_ack) => {
+                            for fragment_index in to_ack {
+                                self._send_ack(fragment_index, session, from);
+                            }
+                        }
+                        FragmentStatus::Duplicate => {}
+                        FragmentStatus::Corrupted(total_n_fragments) => {
+                            self._ask_resend(total_n_fragments, session, from)
                         }
                     }
+                    let requests: Vec<_> = self.message.outbox.drain(..).collect();
+                    for (sid, request) in requests {
+                        self._send_message(&sid, request);
+                    }
+                    let discovered: Vec<_> = self.message.discovered.drain(..).collect();
+                    for (sid, kind) in discovered {
+                        self._server_discovered(sid, kind);
+                    }
                 }
             }
         }
     }
 
+    fn _send_ack(&mut self, fragment_index: u64, session: u64, from: NodeId) {
+        let mut packet =
+            Packet::new_ack(SourceRoutingHeader::empty_route(), session, fragment_index);
+        if self.network.send_packet(&packet, &from) {
+            debug!(
+                "{}: sended ACK frag_index {}, session {}, to {}",
+                self.my_id, fragment_index, session, from
+            );
+        } else {
+            debug!(
+                "{}: TRANSMIT SHORTCUT ACK frag_index {}, session {}, to {}",
+                self.my_id, fragment_index, session, from
+            );
+            let mut return_hops = vec![from];
+            return_hops.extend(packet.routing_header.hops.iter().rev().cloned());
+            packet.routing_header = SourceRoutingHeader::with_first_hop(return_hops);
+            self.channels
+                .borrow()
+                .tx_ctrl
+                .send(ControllerShortcut(packet))
+                .expect("Failed to transmit to CONTROLLER");
+        }
+    }
+
+    /// Nack every fragment of a corrupted message, the sender transmits all of them again
+    fn _ask_resend(&mut self, total_n_fragments: u64, session: u64, from: NodeId) {
+        for fragment_index in 0..total_n_fragments {
+            let packet = Packet::new_nack(
+                SourceRoutingHeader::empty_route(),
+                session,
+                wg_2024::packet::Nack {
+                    fragment_index,
+                    nack_type: NackType::Dropped,
+                },
+            );
+            if !self.network.send_packet(&packet, &from) {
+                warn!(
+                    "{}: unable to ask {} to resend fragment {} of session {}",
+                    self.my_id, from, fragment_index, session
+                );
+            }
+        }
+    }
+
     fn _send_message(&mut self, sid: &NodeId, msg: ChatRequest) {
         let wrapper = self.message.create_and_store_wrapper(sid, msg.clone());
         debug!(
@@ -325,10 +555,80 @@ impl Worker {
         }
     }
 
+    /// Chat servers found by flooding that speak our protocol, sorted
+    fn _chat_servers(&self) -> Vec<NodeId> {
+        let mut servers = self.network.state.servers_of(ServerType::ChatServer);
+        servers.retain(|server| !self.message.incompatible.contains(server));
+        servers
+    }
+
+    /// Save the kind of `sid`, then register to it if it is a chat server or ask its content
+    fn _server_discovered(&mut self, sid: NodeId, kind: ServerType) {
+        if !self.network.state.server_list.contains(&sid) {
+            return;
+        }
+        let known = self.network.state.server_types.insert(sid, kind);
+        self.channels
+            .borrow()
+            .tx_ui
+            .send(ToUICommunication::ServerType { server: sid, kind })
+            .expect("Failed to transmit to UI");
+        if known == Some(kind) {
+            return;
+        }
+        match kind {
+            ServerType::ChatServer => self._registry_and_client_list(&[sid]),
+            ServerType::MediaServer => self._send_message(&sid, ChatRequest::FileList),
+            ServerType::TextServer => self._send_message(&sid, ChatRequest::DocumentList),
+        }
+    }
+
+    /// Servers found again by a flood are sent the registration once more
+    fn _reconnected(&mut self, reachable: &[NodeId]) {
+        for server in reachable {
+            self.handshaken.remove(server);
+            // the rooms are asked again after the new Welcome
+            self.message.registered_on.remove(server);
+            self.message.servers.remove(server);
+        }
+    }
+
+    /// Ask the kind of the servers never heard of, register once to the chat ones
     fn _registry_and_client_list(&mut self, reachable: &[NodeId]) {
         for server in reachable {
+            if self.message.incompatible.contains(server) {
+                continue;
+            }
+            match self.network.state.server_types.get(server) {
+                Some(ServerType::ChatServer) if !self.logged_out => {
+                    if !self.handshaken.insert(*server) {
+                        continue;
+                    }
+                }
+                Some(_) => continue,
+                None => {
+                    // asked again each time a flood reaches it anew, until it answers
+                    let hello = ChatRequest::Hello(self.message.capabilities);
+                    self._send_message(server, hello);
+                    self._send_message(server, ChatRequest::ServerType);
+                    continue;
+                }
+            }
+            let hello = ChatRequest::Hello(self.message.capabilities);
+            self._send_message(server, hello);
             self._send_message(server, ChatRequest::Register(self.my_id));
+            let public_key = self.message.public_key();
+            self._send_message(server, ChatRequest::PublishKey(public_key));
+            if let Some(profile) = self.profile.clone() {
+                self._send_message(server, ChatRequest::SetProfile(profile));
+            }
             self._send_message(server, ChatRequest::ClientList);
+            self._send_message(server, ChatRequest::ClientKeys);
+            self._send_message(server, ChatRequest::Directory);
+            if self.presence != Presence::Online {
+                self._send_message(server, ChatRequest::SetPresence(self.presence));
+            }
+            self._send_message(server, ChatRequest::WatchPresence);
         }
     }
 }
@@ -1,6 +1,10 @@
-use message::{NodeCommand, NodeEvent};
+use message::{NodeCommand, NodeEvent, ServerType};
+use message::record::{self, CommandRecord, EventRecord};
 use crossbeam_channel::{Receiver, Sender, unbounded, TryRecvError};
 use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, LineWriter};
+use std::path::Path;
 use std::thread;
 use std::thread::JoinHandle;
 use thread::sleep;
@@ -72,6 +76,8 @@ pub struct ControllerHandler {
     drones_counter: HashMap<DroneGroup, i8>,
 
     thread_handler: HashMap<NodeId, JoinHandle<()>>,
+
+    recorder: Option<LineWriter<File>>, // see `with_recording`
 }
 
 impl ControllerHandler {
@@ -107,9 +113,36 @@ impl ControllerHandler {
             drones_counter,
             client_state_sender,
             thread_handler,
+            recorder: None,
+        }
+    }
+
+    /// Append the events of the clients and servers, and the commands sent to them, to `path`
+    /// as JSON lines, see `message::record`
+    pub fn with_recording<P: AsRef<Path>>(mut self, path: P) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        self.recorder = Some(LineWriter::new(file));
+        Ok(self)
+    }
+
+    /// Write to the recording with `write`, an error is shown and the next entries are still written
+    fn record(&mut self, write: impl FnOnce(&mut LineWriter<File>) -> io::Result<()>) {
+        let Some(writer) = self.recorder.as_mut() else {
+            return;
+        };
+        if let Err(e) = write(writer) {
+            self.send_error_message(&format!("Failed to write the recording: {}", e));
         }
     }
 
+    fn record_event(&mut self, node_id: NodeId, event: &NodeEvent) {
+        self.record(|writer| record::write_record(writer, &EventRecord::from_event(node_id, event)));
+    }
+
+    fn record_command(&mut self, node_id: NodeId, command: &NodeCommand) {
+        self.record(|writer| record::write_record(writer, &CommandRecord::from_command(node_id, command)));
+    }
+
     pub fn run(&mut self) {
         loop {
             let mut button_events_processed = 0;
@@ -190,8 +223,8 @@ impl ControllerHandler {
             ButtonEvent::NewDrone(id, pdr) => {
                 self.spawn_drone(&id, pdr)
             },
-            ButtonEvent::NewServerWithTwoConnections(drone1, drone2) => {
-                self.create_server_with_two_connections(drone1, drone2)
+            ButtonEvent::NewServerWithTwoConnections(drone1, drone2, kind) => {
+                self.create_server_with_two_connections(drone1, drone2, kind)
             },
             ButtonEvent::NewClient(id) => {
                 self.create_client(id)
@@ -222,19 +255,32 @@ impl ControllerHandler {
     }
 
     fn handle_node_event(&mut self, event: NodeEvent, node_id: NodeId) {
+        self.record_event(node_id, &event);
         match event {
             NodeEvent::PacketSent(c) => {
                 let message = format!("The node ID [{}] has sent a packet {}", node_id, c);
                 let _ = self.message_sender.try_send(MessageType::Info(message));
             },
-            NodeEvent::CreateMessage(_c) => {
-                let message = "Message created".to_string();
+            NodeEvent::CreateMessage(c) => {
+                let message = format!("The node ID [{}] created a message with session ID [{}] to [{}]: {}", node_id, c.session_id, c.destination, c.summary);
                 let _ = self.message_sender.try_send(MessageType::Info(message));
             },
-            NodeEvent::MessageRecv(_c) => {
-                let message = "Message received".to_string();
+            NodeEvent::MessageRecv(c) => {
+                let message = format!("The node ID [{}] received a message with session ID [{}] from [{}]: {}", node_id, c.session_id, c.source, c.summary);
                 let _ = self.message_sender.try_send(MessageType::Info(message));
             },
+            NodeEvent::MessageRecvAbandoned(c) => {
+                let message = format!("The node ID [{}] abandoned a partial message with session ID [{}] from [{}]", node_id, c.session_id, c.source);
+                let _ = self.message_sender.try_send(MessageType::Error(message));
+            },
+            NodeEvent::MessageSentAbandoned(c) => {
+                let message = format!("The node ID [{}] abandoned a message with session ID [{}] to [{}]", node_id, c.session_id, c.destination);
+                let _ = self.message_sender.try_send(MessageType::Error(message));
+            },
+            NodeEvent::MessageCorrupted(c) => {
+                let message = format!("The node ID [{}] received a corrupted message with session ID [{}] from [{}], asked it again", node_id, c.session_id, c.source);
+                let _ = self.message_sender.try_send(MessageType::Error(message));
+            },
             NodeEvent::ControllerShortcut(packet) => {
                 if let Err(e) = self.send_packet_to_client(packet) {
                     self.send_error_message(&format!("Failed to send shortcut packet [{}]", e));
@@ -572,7 +618,7 @@ impl ControllerHandler {
         Ok(())
     }
 
-    fn create_server_with_two_connections(&mut self, drone1: NodeId, drone2: NodeId) -> Result<(), ControllerError> {
+    fn create_server_with_two_connections(&mut self, drone1: NodeId, drone2: NodeId, kind: ServerType) -> Result<(), ControllerError> {
         let id = self.generate_random_id()?;
 
         // Verifica che entrambi siano droni
@@ -600,7 +646,7 @@ impl ControllerHandler {
             ));
         }
 
-        if let Err(e) = self.create_server_without_connection(id) {
+        if let Err(e) = self.create_server_without_connection(id, kind) {
             return Err(e);
         }
 
@@ -613,7 +659,7 @@ impl ControllerHandler {
 
         match self.add_server_connections(&id, &drone1, &drone2) {
             Ok(()) => {
-                self.send_success_message(&format!("Server {} created with connections to drones {} and {}", id, drone1, drone2));
+                self.send_success_message(&format!("{:?} {} created with connections to drones {} and {}", kind, id, drone1, drone2));
                 Ok(())
             }
             Err(e) => {
@@ -624,7 +670,7 @@ impl ControllerHandler {
         }
     }
 
-    fn create_server_without_connection(&mut self, id: NodeId) -> Result<(), ControllerError> {
+    fn create_server_without_connection(&mut self, id: NodeId, kind: ServerType) -> Result<(), ControllerError> {
         let (p_send, p_receiver) = unbounded::<Packet>();
         let (node_event_send, node_event_receiver) = unbounded::<NodeEvent>();
         let (node_command_send, node_command_receiver) = unbounded::<NodeCommand>();
@@ -644,7 +690,7 @@ impl ControllerHandler {
                     node_command_receiver,
                     p_receiver,
                     packet_send
-                );
+                ).with_kind(kind);
                 chat_server.run();
             })
             .expect("Can't spawn Server");
@@ -830,6 +876,7 @@ impl ControllerHandler {
             ))
 
         } else {
+            self.record_command(*id, &NodeCommand::AddSender(*dst_id, dst_sender.clone()));
             let sender = self.send_command_node.get(id)
                 .ok_or_else(|| ControllerError::NodeNotFound(*id))?;
 
@@ -874,9 +921,11 @@ impl ControllerHandler {
             sender.send(DroneCommand::RemoveSender(*dst_id))
                 .map_err(|e| ControllerError::ChannelSend(e.to_string()))?;
         } else {
+            let command = NodeCommand::RemoveSender(*dst_id);
+            self.record_command(*id, &command);
             let sender = self.send_command_node.get(id)
                 .ok_or_else(|| ControllerError::NodeNotFound(*id))?;
-            sender.send(NodeCommand::RemoveSender(*dst_id))
+            sender.send(command)
                 .map_err(|e| ControllerError::ChannelSend(e.to_string()))?;
         }
 
@@ -939,22 +988,26 @@ impl ControllerHandler {
         Ok(())
     }
 
-    pub(crate) fn send_packet_to_client(&self, packet: Packet) -> Result<(), ControllerError> {
+    pub(crate) fn send_packet_to_client(&mut self, packet: Packet) -> Result<(), ControllerError> {
         let _ = packet.session_id;
         let destination = packet.routing_header.hops.last().copied();
 
         if let Some(destination) = destination {
             match self.get_node_type(&destination) {
                 Some(NodeType::Client) => {
+                    let command = FromShortcut(packet);
+                    self.record_command(destination, &command);
                     let sender = self.send_command_node.get(&destination)
                         .ok_or_else(|| ControllerError::NodeNotFound(destination))?;
-                    sender.try_send(FromShortcut(packet))
+                    sender.try_send(command)
                         .map_err(|e| ControllerError::ChannelSend(e.to_string()))?;
                 }
                 Some(NodeType::Server) => {
+                    let command = FromShortcut(packet);
+                    self.record_command(destination, &command);
                     let sender = self.send_command_node.get(&destination)
                         .ok_or_else(|| ControllerError::NodeNotFound(destination))?;
-                    sender.try_send(FromShortcut(packet))
+                    sender.try_send(command)
                         .map_err(|e| ControllerError::ChannelSend(e.to_string()))?;
                 }
                 Some(NodeType::Drone) => {
@@ -1093,6 +1146,7 @@ impl ControllerHandler {
             ))
 
         } else {
+            self.record_command(*id, &NodeCommand::AddSender(*dst_id, dst_sender.clone()));
             let sender = self.send_command_node.get(id)
                 .ok_or_else(|| ControllerError::NodeNotFound(*id))?;
 
@@ -1,4 +1,5 @@
 use wg_2024::network::NodeId;
+use message::ServerType;
 use egui::{Color32};
 
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Copy)]
@@ -20,7 +21,7 @@ pub enum GraphAction {
 pub enum ButtonEvent {
     NewDrone(NodeId, f32),
     NewClient(NodeId),
-    NewServerWithTwoConnections(NodeId, NodeId),
+    NewServerWithTwoConnections(NodeId, NodeId, ServerType),
     NewConnection(NodeId, NodeId),
     Crash(NodeId),
     RemoveConection(NodeId, NodeId),
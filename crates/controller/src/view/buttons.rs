@@ -1,6 +1,7 @@
 use crossbeam_channel::{Receiver, Sender};
 use egui::{Button, Color32, RichText};
 use wg_2024::network::NodeId;
+use message::ServerType;
 use crate::utility::{ButtonEvent, ButtonsMessages, LIGHT_BLUE, DARK_BLUE, LIGHT_ORANGE};
 use crate::drawable::{Drawable, PanelDrawable, PanelType};
 const BUTTON_TEXT_COLOR: Color32 = Color32::WHITE;
@@ -250,7 +251,16 @@ impl Drawable for ButtonWindow {
                                 .fill(LIGHT_BLUE)
                         ).clicked() {
                             if let (Some(drone1), Some(drone2)) = (self.node_id1, self.node_id2) {
-                                self.send_button_event(ButtonEvent::NewServerWithTwoConnections(drone1, drone2));
+                                self.send_button_event(ButtonEvent::NewServerWithTwoConnections(drone1, drone2, ServerType::ChatServer));
+                                self.clear_selection();
+                            }
+                        }
+                        if ui.add(
+                            Button::new(RichText::new("Create Media Server").color(BUTTON_TEXT_COLOR))
+                                .fill(LIGHT_BLUE)
+                        ).clicked() {
+                            if let (Some(drone1), Some(drone2)) = (self.node_id1, self.node_id2) {
+                                self.send_button_event(ButtonEvent::NewServerWithTwoConnections(drone1, drone2, ServerType::MediaServer));
                                 self.clear_selection();
                             }
                         }
@@ -1,10 +1,11 @@
 use client::communication::{FromUiCommunication, ToUICommunication};
 use client::worker::Worker;
 use crossbeam_channel::{unbounded, Receiver, Sender};
-use message::{NodeCommand, NodeEvent};
-use server::ChatServer;
+use message::{NodeCommand, NodeEvent, ServerType};
+use serde::Deserialize;
+use server::{ChatServer, DocumentStore};
 use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::{fs, thread};
 use std::thread::JoinHandle;
 use thiserror::Error;
@@ -47,6 +48,33 @@ pub fn parse_config<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
     Ok(cfg)
 }
 
+// `kind` of a server in the Network Initialization File, the chat servers can omit it
+#[derive(Deserialize)]
+pub struct ServerKind {
+    pub id: NodeId,
+    #[serde(default)]
+    pub kind: ServerType,
+    // directory with the `.txt` files of a text server, relative to the file
+    #[serde(default)]
+    pub documents: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct ServerKinds {
+    #[serde(default)]
+    server: Vec<ServerKind>,
+}
+
+pub fn parse_server_kinds<P: AsRef<Path>>(path: P) -> Result<HashMap<NodeId, ServerKind>, ConfigError> {
+    let content = fs::read_to_string(&path)?;
+    let kinds: ServerKinds = toml::from_str(&content)?;
+    let dir = path.as_ref().parent().unwrap_or(Path::new("."));
+    Ok(kinds.server.into_iter().map(|mut server| {
+        server.documents = server.documents.map(|documents| dir.join(documents));
+        (server.id, server)
+    }).collect())
+}
+
 //checks if the Network Initialization File meets the needed requirements
 fn validate(cfg: &Config) -> Result<(), ConfigError> {
     are_ids_unique(cfg)?;
@@ -78,13 +106,16 @@ fn validate(cfg: &Config) -> Result<(), ConfigError> {
     Ok(())
 }
 
-pub fn start<P: AsRef<Path>>(config_path: P) -> Result<(
+/// Start the network described by `config_path`, the controller records the events and commands
+/// of clients and servers to `record_path` when given
+pub fn start<P: AsRef<Path>>(config_path: P, record_path: Option<&Path>) -> Result<(
     HashMap<NodeId, (Sender<ToUICommunication>, Receiver<ToUICommunication>)>,
     HashMap<NodeId, (Sender<FromUiCommunication>, Receiver<FromUiCommunication>)>,
     Sender<ButtonEvent>, Receiver<GraphAction>, Receiver<MessageType>,
     Sender<MessageType>,Receiver<(NodeId, ClientState)>, HashMap<NodeId, Vec<NodeId>>,
     HashMap<NodeId, NodeType>), Box<dyn std::error::Error>>{
-    let cfg = parse_config(config_path)?;
+    let cfg = parse_config(&config_path)?;
+    let server_kinds = parse_server_kinds(&config_path)?;
     let mut packet_senders: HashMap<NodeId, Sender<Packet>> = HashMap::new();
 
     let mut sender_receiver_pair_drone_event: HashMap<NodeId, (Sender<DroneEvent>, Receiver<DroneEvent>)> = HashMap::new();
@@ -431,6 +462,12 @@ pub fn start<P: AsRef<Path>>(config_path: P) -> Result<(
         nodes.insert(node.id, NodeType::Server);
 
         let node_id = node.id;
+        let kind = server_kinds.get(&node_id).map(|server| server.kind).unwrap_or_default();
+        let documents = match server_kinds.get(&node_id).and_then(|server| server.documents.as_ref()) {
+            Some(dir) => DocumentStore::from_dir(dir)
+                .map_err(|e| format!("Documents of server {} not found in {:?}: {}", node_id, dir, e))?,
+            None => DocumentStore::new(),
+        };
         let handle = thread::Builder::new().name(format!("Server ID [{}]", node_id)).spawn(move || {
             let mut node = ChatServer::new(
                 node_id,
@@ -438,7 +475,7 @@ pub fn start<P: AsRef<Path>>(config_path: P) -> Result<(
                 receiver_node_command,
                 receiver_packet,
                 sender_hash
-            );
+            ).with_kind(kind).with_documents(documents);
             node.run();
         }).expect("Can't spawn thread ChatServer");
         thread_handles.insert(node_id, handle);
@@ -460,6 +497,9 @@ pub fn start<P: AsRef<Path>>(config_path: P) -> Result<(
         drones_counter,
         thread_handles,
     );
+    if let Some(path) = record_path {
+        controller_handler = controller_handler.with_recording(path)?;
+    }
     
     let _controller_handle = thread::spawn(move || {
         controller_handler.run();
@@ -827,6 +867,28 @@ mod tests {
         assert_eq!(config.server[0].connected_drone_ids, vec![2, 3]);
     }
 
+    #[test]
+    fn parse_server_kinds_test() {
+        const FILE_MEDIA: &str = "src/test_data/input14.toml";
+        assert!(parse_config(FILE_MEDIA).is_ok(), "Failed to parse the config file");
+        let kinds = parse_server_kinds(FILE_MEDIA).unwrap();
+        assert_eq!(kinds.len(), 2);
+        assert_eq!(kinds[&6].kind, ServerType::ChatServer);
+        assert_eq!(kinds[&7].kind, ServerType::MediaServer);
+        assert!(kinds[&7].documents.is_none());
+    }
+
+    #[test]
+    fn parse_text_server_test() {
+        const FILE_TEXT: &str = "src/test_data/input15.toml";
+        assert!(parse_config(FILE_TEXT).is_ok(), "Failed to parse the config file");
+        let kinds = parse_server_kinds(FILE_TEXT).unwrap();
+        assert_eq!(kinds[&7].kind, ServerType::TextServer);
+        let dir = kinds[&7].documents.as_ref().expect("Documents of the text server");
+        let documents = DocumentStore::from_dir(dir).unwrap();
+        assert_eq!(documents.list(), vec!["benvenuto.txt".to_string(), "regole.txt".to_string()]);
+    }
+
     #[test]
     fn test_is_connected_empty_graph() {
         let config = Config {
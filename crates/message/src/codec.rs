@@ -0,0 +1,80 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+// ------------------------------ ENVELOPE
+// | magic | version | codec | body ... |
+//
+// `ENVELOPE_MAGIC` can never be the first byte of a UTF-8 string, so payloads produced
+// before the envelope existed (plain JSON text) are still recognised and decoded as JSON.
+pub const ENVELOPE_MAGIC: u8 = 0xFF;
+pub const ENVELOPE_VERSION: u8 = 1;
+pub const ENVELOPE_HEADER_LEN: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Codec {
+    /// Human readable, useful to inspect fragments while debugging
+    Json,
+    /// Compact encoding, used by default on the wire
+    #[default]
+    Binary,
+}
+
+impl Codec {
+    fn id(self) -> u8 {
+        match self {
+            Codec::Json => 0,
+            Codec::Binary => 1,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Codec::Json),
+            1 => Some(Codec::Binary),
+            _ => None,
+        }
+    }
+
+    /// Serialize `value` and wrap it inside an envelope
+    pub fn encode<T: Serialize>(self, value: &T) -> Vec<u8> {
+        let body = match self {
+            Codec::Json => serde_json::to_vec(value).unwrap(),
+            Codec::Binary => bincode::serialize(value).unwrap(),
+        };
+        let mut payload = Vec::with_capacity(ENVELOPE_HEADER_LEN + body.len());
+        payload.extend_from_slice(&[ENVELOPE_MAGIC, ENVELOPE_VERSION, self.id()]);
+        payload.extend_from_slice(&body);
+        payload
+    }
+
+    fn decode_body<T: DeserializeOwned>(self, body: &[u8]) -> Result<T, String> {
+        match self {
+            Codec::Json => serde_json::from_slice(body).map_err(|e| e.to_string()),
+            Codec::Binary => bincode::deserialize(body).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// Split a payload in the codec that produced it and its body
+///
+/// Payloads without envelope are treated as JSON.
+pub fn open_envelope(payload: &[u8]) -> Result<(Codec, &[u8]), String> {
+    if payload.first() != Some(&ENVELOPE_MAGIC) {
+        return Ok((Codec::Json, payload));
+    }
+    if payload.len() < ENVELOPE_HEADER_LEN {
+        return Err("Truncated envelope".to_string());
+    }
+    if payload[1] != ENVELOPE_VERSION {
+        return Err(format!("Unsupported envelope version {}", payload[1]));
+    }
+    let codec =
+        Codec::from_id(payload[2]).ok_or_else(|| format!("Unknown codec id {}", payload[2]))?;
+    Ok((codec, &payload[ENVELOPE_HEADER_LEN..]))
+}
+
+/// Decode an enveloped (or legacy JSON) payload
+pub fn decode<T: DeserializeOwned>(payload: &[u8]) -> Result<T, String> {
+    let (codec, body) = open_envelope(payload)?;
+    codec.decode_body(body)
+}
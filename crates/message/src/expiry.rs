@@ -0,0 +1,73 @@
+use std::time::{Duration, SystemTime};
+
+/// Limits of the buffers holding messages in progress, past them a session is abandoned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferLimits {
+    /// A partial received message without new fragments for this long is dropped
+    pub recv_timeout: Duration,
+    /// A sent message without new acks or nacks for this long is dropped
+    pub sent_timeout: Duration,
+    /// Partial received messages kept at most, the least recently updated go first
+    pub max_recv: usize,
+    /// Sent messages waiting for acks kept at most, the least recently updated go first
+    pub max_sent: usize,
+}
+
+impl Default for BufferLimits {
+    fn default() -> Self {
+        Self {
+            recv_timeout: Duration::from_secs(60),
+            sent_timeout: Duration::from_secs(120),
+            max_recv: 1024,
+            max_sent: 1024,
+        }
+    }
+}
+
+/// How many sessions were dropped before completion
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AbandonedSessions {
+    pub recv: u64,
+    pub sent: u64,
+}
+
+pub trait Timestamped {
+    fn last_activity(&self) -> SystemTime;
+}
+
+impl Timestamped for SystemTime {
+    fn last_activity(&self) -> SystemTime {
+        *self
+    }
+}
+
+/// Keys of the entries to evict: the expired ones and the oldest ones above `cap`
+pub fn stale_keys<'a, K, W>(
+    entries: impl IntoIterator<Item = (&'a K, &'a W)>,
+    timeout: Duration,
+    cap: usize,
+    now: SystemTime,
+) -> Vec<K>
+where
+    K: Clone + 'a,
+    W: Timestamped + 'a,
+{
+    let mut entries: Vec<(&K, SystemTime)> = entries
+        .into_iter()
+        .map(|(key, wrapper)| (key, wrapper.last_activity()))
+        .collect();
+    entries.sort_by_key(|(_, last_activity)| *last_activity);
+
+    let over_cap = entries.len().saturating_sub(cap);
+    entries
+        .into_iter()
+        .enumerate()
+        .filter(|(i, (_, last_activity))| {
+            let idle = now
+                .duration_since(*last_activity)
+                .unwrap_or(Duration::from_secs(0));
+            *i < over_cap || idle >= timeout
+        })
+        .map(|(_, (key, _))| key.clone())
+        .collect()
+}
@@ -0,0 +1,47 @@
+use std::ops::Range;
+use wg_2024::packet::Fragment;
+
+use crate::FRAGMENT_DSIZE;
+
+// ------------------------------ PARITY
+// Data fragments are split in groups of `group_size`, each group is protected by one
+// parity fragment holding the XOR of the group data: any single missing fragment of
+// the group can be rebuilt from the others.
+//
+// Parity fragments are sent after the data ones, the parity of group `g` has
+// `fragment_index = total_n_fragments + g` and `length = group_size`. Nodes that don't
+// know parity discard them, the index is out of range.
+
+/// Data fragment indices protected by the parity fragment of `group`
+pub fn group_members(group: u64, group_size: u64, total_n_fragments: u64) -> Range<u64> {
+    let start = group * group_size;
+    start..(start + group_size).min(total_n_fragments)
+}
+
+pub fn n_groups(group_size: u64, total_n_fragments: u64) -> u64 {
+    total_n_fragments.div_ceil(group_size)
+}
+
+pub fn xor_into(acc: &mut [u8; FRAGMENT_DSIZE], data: &[u8; FRAGMENT_DSIZE]) {
+    acc.iter_mut().zip(data).for_each(|(a, d)| *a ^= d);
+}
+
+/// Build the parity fragments for `data`, the data fragments of a message
+pub fn parity_fragments(data: &[Fragment], group_size: usize) -> Vec<Fragment> {
+    let total_n_fragments = data.len() as u64;
+    data.chunks(group_size)
+        .enumerate()
+        .map(|(group, members)| {
+            let mut parity = [0; FRAGMENT_DSIZE];
+            for fragment in members {
+                xor_into(&mut parity, &fragment.data);
+            }
+            Fragment {
+                fragment_index: total_n_fragments + group as u64,
+                total_n_fragments,
+                length: group_size as u8,
+                data: parity,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,125 @@
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+
+use crate::codec::ENVELOPE_MAGIC;
+use crate::ReassemblyError;
+
+// ------------------------------ FRAME
+// | magic | version | flags | body_len (u32 BE) | crc32 (u32 BE) | body ... |
+//
+// The frame wraps the whole payload before fragmentation. Like the codec envelope,
+// `FRAME_MAGIC` is not a valid first byte of UTF-8 text, so unframed payloads
+// (plain JSON produced by older nodes) are passed through untouched.
+//
+// The crc32 covers flags, body_len and body: drones never look inside fragments,
+// so a mismatch after reassembly means the data changed on the way.
+pub const FRAME_MAGIC: u8 = 0xFE;
+pub const FRAME_VERSION: u8 = 1;
+pub const FRAME_HEADER_LEN: usize = 11;
+
+pub const FLAG_COMPRESSED: u8 = 0b0000_0001;
+
+/// Payloads shorter than this fit in a few fragments, compressing them is not worth it
+pub const COMPRESSION_THRESHOLD: usize = 256;
+
+fn deflate(payload: &[u8]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(payload).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn decompress(body: &[u8]) -> Result<Vec<u8>, ReassemblyError> {
+    let mut payload = Vec::new();
+    DeflateDecoder::new(body)
+        .read_to_end(&mut payload)
+        .map_err(|e| ReassemblyError::Malformed(e.to_string()))?;
+    Ok(payload)
+}
+
+fn checksum(flags: u8, body_len: [u8; 4], body: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&[flags]);
+    hasher.update(&body_len);
+    hasher.update(body);
+    hasher.finalize()
+}
+
+/// Put `payload` inside a frame, compressing it if asked and if it is worth it
+pub fn seal(payload: &[u8], compress: bool) -> Vec<u8> {
+    let mut flags = 0;
+    let mut body = None;
+    if compress && payload.len() >= COMPRESSION_THRESHOLD {
+        let compressed = deflate(payload);
+        if compressed.len() < payload.len() {
+            flags |= FLAG_COMPRESSED;
+            body = Some(compressed);
+        }
+    }
+    let body = body.as_deref().unwrap_or(payload);
+
+    let body_len = (body.len() as u32).to_be_bytes();
+
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + body.len());
+    frame.extend_from_slice(&[FRAME_MAGIC, FRAME_VERSION, flags]);
+    frame.extend_from_slice(&body_len);
+    frame.extend_from_slice(&checksum(flags, body_len, body).to_be_bytes());
+    frame.extend_from_slice(body);
+    frame
+}
+
+/// Whether `data` can be an unframed payload: an envelope or plain JSON text
+fn is_legacy(data: &[u8]) -> bool {
+    match data.iter().find(|byte| !byte.is_ascii_whitespace()) {
+        Some(&ENVELOPE_MAGIC) | None => true,
+        Some(byte) => matches!(
+            byte,
+            b'{' | b'[' | b'"' | b'-' | b'0'..=b'9' | b't' | b'f' | b'n'
+        ),
+    }
+}
+
+/// Extract the original payload from a reassembled frame, verifying its checksum
+///
+/// Data without frame is returned as it is. Any other first byte is a damaged magic byte,
+/// reported as `Corrupted` so the message is asked again.
+pub fn open(data: &[u8]) -> Result<Vec<u8>, ReassemblyError> {
+    match data.first() {
+        Some(&FRAME_MAGIC) => {}
+        Some(&found) if !is_legacy(data) => {
+            return Err(ReassemblyError::Corrupted {
+                expected: FRAME_MAGIC as u32,
+                found: found as u32,
+            });
+        }
+        _ => return Ok(data.to_vec()),
+    }
+    if data.len() < FRAME_HEADER_LEN {
+        return Err(ReassemblyError::Malformed("Truncated frame".to_string()));
+    }
+    if data[1] != FRAME_VERSION {
+        return Err(ReassemblyError::Malformed(format!(
+            "Unsupported frame version {}",
+            data[1]
+        )));
+    }
+    let flags = data[2];
+    let body_len = [data[3], data[4], data[5], data[6]];
+    let expected = u32::from_be_bytes([data[7], data[8], data[9], data[10]]);
+    let body = &data[FRAME_HEADER_LEN..];
+    let body = body
+        .get(..u32::from_be_bytes(body_len) as usize)
+        .unwrap_or(body);
+
+    let found = checksum(flags, body_len, body);
+    if found != expected {
+        return Err(ReassemblyError::Corrupted { expected, found });
+    }
+
+    if flags & FLAG_COMPRESSED != 0 {
+        decompress(body)
+    } else {
+        Ok(body.to_vec())
+    }
+}
@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+use std::ops::BitOr;
+
+use crate::FragmentationOptions;
+
+// ------------------------------ HANDSHAKE
+// After discovering a server, a client sends `ChatRequest::Hello` with its `Capabilities`.
+// The server answers `ChatResponse::Welcome` with the agreed ones: the highest version
+// spoken by both and the features supported by both. When the version ranges don't
+// overlap it answers `ChatResponse::ErrorIncompatible` with its own capabilities.
+//
+// Nodes that never sent `Hello` are treated as version 1 peers with every feature they
+// used before the handshake existed.
+pub const PROTOCOL_VERSION: u16 = 1;
+pub const MIN_PROTOCOL_VERSION: u16 = 1;
+
+/// Optional features as bit flags, unknown bits are kept and never agreed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Features(pub u32);
+
+impl Features {
+    pub const NONE: Features = Features(0);
+    pub const COMPRESSION: Features = Features(1);
+    pub const RECEIPTS: Features = Features(1 << 1);
+    pub const GROUPS: Features = Features(1 << 2);
+
+    pub fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersection(self, other: Features) -> Features {
+        Features(self.0 & other.0)
+    }
+}
+
+impl BitOr for Features {
+    type Output = Features;
+
+    fn bitor(self, rhs: Features) -> Features {
+        Features(self.0 | rhs.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Highest protocol version spoken
+    pub version: u16,
+    /// Lowest protocol version still spoken
+    pub min_version: u16,
+    pub features: Features,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::new(Features::COMPRESSION | Features::RECEIPTS | Features::GROUPS)
+    }
+}
+
+impl Capabilities {
+    pub fn new(features: Features) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+            features,
+        }
+    }
+
+    /// What `self` and `peer` can use together, `None` if no version is spoken by both
+    pub fn negotiate(&self, peer: &Capabilities) -> Option<Capabilities> {
+        let version = self.version.min(peer.version);
+        if version < self.min_version.max(peer.min_version) {
+            return None;
+        }
+        Some(Capabilities {
+            version,
+            min_version: version,
+            features: self.features.intersection(peer.features),
+        })
+    }
+
+    pub fn supports(&self, feature: Features) -> bool {
+        self.features.contains(feature)
+    }
+
+    /// `options` restricted to the agreed features
+    pub fn restrict(&self, options: &FragmentationOptions) -> FragmentationOptions {
+        FragmentationOptions {
+            compress: options.compress && self.supports(Features::COMPRESSION),
+            ..*options
+        }
+    }
+}
@@ -1,6 +1,21 @@
+pub mod codec;
+pub mod expiry;
+pub mod fec;
+pub mod frame;
+pub mod handshake;
+pub mod record;
+mod tests;
+
+pub use codec::Codec;
+use expiry::Timestamped;
+pub use expiry::{AbandonedSessions, BufferLimits};
+pub use handshake::{Capabilities, Features, PROTOCOL_VERSION};
+pub use record::{CommandRecord, EventRecord, RecordedCommand};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::SystemTime;
 use wg_2024::network::NodeId;
 use wg_2024::packet::{Fragment, Packet};
 
@@ -8,46 +23,155 @@ use crossbeam_channel::Sender;
 
 pub const FRAGMENT_DSIZE: usize = 128;
 
+/// X25519 public key of a client, for end-to-end encrypted messages
+pub type PublicKey = [u8; 32];
+/// Nonce of an end-to-end encrypted message
+pub type Nonce = [u8; 24];
+/// Name of a chat room, unique in the server that hosts it
+pub type RoomName = String;
+/// Name of a file, unique in the media server that stores it
+pub type FileName = String;
+/// Id chosen by the sender of a direct message to match its receipts, unique for that sender
+pub type MessageId = u64;
+
 // ------------------------------ CONTROLLER EVENTS
+#[derive(Debug, Clone)]
 pub enum NodeCommand {
     RemoveSender(NodeId),
     AddSender(NodeId, Sender<Packet>),
     FromShortcut(Packet),
 }
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum NodeEvent {
     PacketSent(Packet),
     CreateMessage(SentMessageWrapper), // try send message (every times is sended a stream of fragment)
     MessageRecv(RecvMessageWrapper),   // received full message
+    MessageCorrupted(RecvMessageWrapper), // full message failed the integrity check, resend requested
+    MessageRecvAbandoned(RecvMessageWrapper), // partial message evicted, expired or over the buffer cap
+    MessageSentAbandoned(SentMessageWrapper), // sent message evicted before every fragment was acked
     ControllerShortcut(Packet),
 }
 
 // ------------------------------ HIGH MESSAGE
+/// How a payload is turned into fragments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FragmentationOptions {
+    /// Compress the payload when it is above `frame::COMPRESSION_THRESHOLD` and it gets smaller
+    pub compress: bool,
+    /// Add one parity fragment every `parity_group` data fragments (from 2 to 255), 0 disables it
+    pub parity_group: usize,
+    /// Bytes of payload in each fragment, from 1 to `FRAGMENT_DSIZE`
+    pub fragment_size: usize,
+}
+
+impl Default for FragmentationOptions {
+    fn default() -> Self {
+        Self {
+            compress: true,
+            parity_group: 0,
+            fragment_size: FRAGMENT_DSIZE,
+        }
+    }
+}
+
+impl FragmentationOptions {
+    /// `fragment_size` clamped to the sizes allowed by the protocol
+    pub fn fragment_size(&self) -> usize {
+        self.fragment_size.clamp(1, FRAGMENT_DSIZE)
+    }
+
+    /// `parity_group` clamped to the sizes that can be rebuilt, 0 if parity is disabled
+    pub fn parity_group(&self) -> usize {
+        match self.parity_group {
+            0 => 0,
+            group => group.clamp(2, u8::MAX as usize),
+        }
+    }
+}
+
 // use this to store message and message State
-#[derive(Debug, Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct SentMessageWrapper {
     pub session_id: u64,
     pub destination: NodeId,
-    pub total_n_fragments: u64,
+    pub total_n_fragments: u64, // data fragments, parity fragments are not counted
     pub acked: HashSet<u64>,
-    pub fragments: Vec<Fragment>,
+    pub fragments: Vec<Fragment>, // data fragments followed by parity fragments
+    pub parity_group: usize,
+    pub lost: HashSet<u64>,
+    pub last_activity: SystemTime, // creation, last ack or nack
 
-    pub raw_data: String,
+    pub raw_data: Vec<u8>,
+    /// Readable form of `raw_data` (JSON of the message), shown in logs instead of its bytes
+    #[serde(default)]
+    pub summary: String,
+}
+
+/// Readable form of an arbitrary payload: JSON text as it is, other data by its size
+fn summarize(payload: &[u8]) -> String {
+    let text = match codec::open_envelope(payload) {
+        Ok((Codec::Json, body)) => std::str::from_utf8(body).ok(),
+        _ => None,
+    };
+    match text {
+        Some(text) => text.to_string(),
+        None => format!("<{} bytes>", payload.len()),
+    }
+}
+
+impl fmt::Debug for SentMessageWrapper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SentMessageWrapper")
+            .field("session_id", &self.session_id)
+            .field("destination", &self.destination)
+            .field("total_n_fragments", &self.total_n_fragments)
+            .field("acked", &self.acked)
+            .field("lost", &self.lost)
+            .field("last_activity", &self.last_activity)
+            .field("message", &self.summary)
+            .finish_non_exhaustive()
+    }
 }
 
 impl SentMessageWrapper {
-    pub fn new_from_raw_data(session_id: u64, destination: NodeId, raw_data: String) -> Self {
-        let (fragments, total_n_fragments) = SentMessageWrapper::fragmentation(raw_data.clone());
+    /// Create `Wrapper` from an arbitrary payload, using the default `FragmentationOptions`
+    pub fn new_from_bytes(session_id: u64, destination: NodeId, raw_data: Vec<u8>) -> Self {
+        Self::new_with_options(
+            session_id,
+            destination,
+            raw_data,
+            &FragmentationOptions::default(),
+        )
+    }
+
+    /// Create `Wrapper` from an arbitrary payload, fragmented as described by `options`
+    pub fn new_with_options(
+        session_id: u64,
+        destination: NodeId,
+        raw_data: Vec<u8>,
+        options: &FragmentationOptions,
+    ) -> Self {
+        let (fragments, total_n_fragments) = SentMessageWrapper::fragmentation(&raw_data, options);
+        let summary = summarize(&raw_data);
         Self {
             session_id,
             destination,
             total_n_fragments,
             acked: HashSet::new(),
             fragments,
+            parity_group: options.parity_group(),
+            lost: HashSet::new(),
+            last_activity: SystemTime::now(),
             raw_data,
+            summary,
         }
     }
 
-    /// Create `Wrapper` from a serializable message
+    pub fn new_from_raw_data(session_id: u64, destination: NodeId, raw_data: String) -> Self {
+        Self::new_from_bytes(session_id, destination, raw_data.into_bytes())
+    }
+
+    /// Create `Wrapper` from a serializable message, encoded with the default `Codec`
     ///
     /// # Example
     ///
@@ -58,8 +182,37 @@ impl SentMessageWrapper {
     /// let wrapper = SentMessageWrapper::from_message(1, nid, &msg);
     /// ```
     pub fn from_message<T: DroneSend>(session_id: u64, destination: NodeId, message: &T) -> Self {
-        let raw_data = message.stringify();
-        Self::new_from_raw_data(session_id, destination, raw_data)
+        Self::from_message_with_codec(session_id, destination, message, Codec::default())
+    }
+
+    /// Create `Wrapper` from a serializable message, encoded with the default `Codec` and
+    /// fragmented as described by `options`
+    pub fn from_message_with_options<T: DroneSend>(
+        session_id: u64,
+        destination: NodeId,
+        message: &T,
+        options: &FragmentationOptions,
+    ) -> Self {
+        let mut wrapper = Self::new_with_options(
+            session_id,
+            destination,
+            message.encode(Codec::default()),
+            options,
+        );
+        wrapper.summary = message.stringify();
+        wrapper
+    }
+
+    /// Create `Wrapper` from a serializable message, encoded with `codec`
+    pub fn from_message_with_codec<T: DroneSend>(
+        session_id: u64,
+        destination: NodeId,
+        message: &T,
+        codec: Codec,
+    ) -> Self {
+        let mut wrapper = Self::new_from_bytes(session_id, destination, message.encode(codec));
+        wrapper.summary = message.stringify();
+        wrapper
     }
 
     pub fn is_all_fragment_acked(&self) -> bool {
@@ -77,40 +230,128 @@ impl SentMessageWrapper {
     pub fn add_acked(&mut self, index: u64) {
         if index < self.total_n_fragments {
             self.acked.insert(index);
+            self.last_activity = SystemTime::now();
+        }
+    }
+
+    /// Record that a drone dropped fragment `index`, returns `false` when it is not worth
+    /// sending it again because the destination can rebuild it from parity
+    ///
+    /// A parity group survives one loss, from the second one every lost fragment of the
+    /// group is sent again.
+    pub fn fragment_lost(&mut self, index: u64) -> bool {
+        self.lost.insert(index);
+        self.last_activity = SystemTime::now();
+        if self.parity_group == 0 {
+            return true;
         }
+
+        let group_size = self.parity_group as u64;
+        let group = if index < self.total_n_fragments {
+            index / group_size
+        } else {
+            index - self.total_n_fragments
+        };
+        let parity_index = self.total_n_fragments + group;
+        let lost_in_group = fec::group_members(group, group_size, self.total_n_fragments)
+            .chain([parity_index])
+            .filter(|i| self.lost.contains(i))
+            .count();
+        lost_in_group > 1
     }
 
-    pub fn fragmentation(raw_data: String) -> (Vec<Fragment>, u64) {
-        let raw_bytes = raw_data.clone().into_bytes();
-        let total_n_fragments = raw_bytes.len().div_ceil(FRAGMENT_DSIZE) as u64;
-        let fragments = raw_bytes
-            .chunks(FRAGMENT_DSIZE)
+    /// Frame (and maybe compress) `raw_bytes`, then split the frame in fragments
+    ///
+    /// The returned count is the number of data fragments, parity fragments are appended after them.
+    ///
+    /// With parity the last data fragment is padded to the fragment size: every data fragment
+    /// has the same `length`, so the receiver knows the length of the ones it rebuilds.
+    pub fn fragmentation(raw_bytes: &[u8], options: &FragmentationOptions) -> (Vec<Fragment>, u64) {
+        let raw_bytes = frame::seal(raw_bytes, options.compress);
+        let fragment_size = options.fragment_size();
+        let parity_group = options.parity_group();
+        let total_n_fragments = raw_bytes.len().div_ceil(fragment_size) as u64;
+        let mut fragments = raw_bytes
+            .chunks(fragment_size)
             .enumerate()
             .map(|(i, chunk)| {
                 let mut data = [0; FRAGMENT_DSIZE];
                 data[..chunk.len()].copy_from_slice(chunk);
+                let length = if parity_group > 0 {
+                    fragment_size
+                } else {
+                    chunk.len()
+                };
                 Fragment {
                     fragment_index: i as u64,
                     total_n_fragments,
-                    length: chunk.len() as u8,
+                    length: length as u8,
                     data,
                 }
             })
-            .collect();
+            .collect::<Vec<_>>();
+
+        if parity_group > 0 {
+            let parity = fec::parity_fragments(&fragments, parity_group);
+            fragments.extend(parity);
+        }
         (fragments, total_n_fragments)
     }
 }
 
+/// Why a `RecvMessageWrapper` could not produce its message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReassemblyError {
+    /// Some fragments are still missing
+    Incomplete,
+    /// The reassembled frame cannot be read (truncated, unknown version, bad compression)
+    Malformed(String),
+    /// The checksum of the reassembled frame does not match the one computed by the sender,
+    /// or its magic byte was damaged (`expected` is then `FRAME_MAGIC`)
+    Corrupted { expected: u32, found: u32 },
+    /// The payload is intact but it is not the requested message type
+    Decode(String),
+}
+
+impl ReassemblyError {
+    /// The data was damaged on the way, asking the sender to transmit it again can fix it
+    pub fn is_corruption(&self) -> bool {
+        matches!(
+            self,
+            ReassemblyError::Malformed(_) | ReassemblyError::Corrupted { .. }
+        )
+    }
+}
+
 // use this to save the arriving fragments
-#[derive(Debug, Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct RecvMessageWrapper {
     pub session_id: u64,
     pub source: NodeId,
     pub total_n_fragments: u64,
     pub arrived: HashSet<u64>,
     pub fragments: Vec<Option<Fragment>>,
+    pub parity: HashMap<u64, Fragment>, // group -> parity fragment
+    recovered: Vec<u64>,
+    pub last_activity: SystemTime, // creation or last new fragment
 
-    pub raw_data: String,
+    pub raw_data: Vec<u8>,
+    /// Readable form of `raw_data` once it is reassembled, see `SentMessageWrapper::summary`
+    #[serde(default)]
+    pub summary: String,
+}
+
+impl fmt::Debug for RecvMessageWrapper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecvMessageWrapper")
+            .field("session_id", &self.session_id)
+            .field("source", &self.source)
+            .field("total_n_fragments", &self.total_n_fragments)
+            .field("arrived", &self.arrived)
+            .field("last_activity", &self.last_activity)
+            .field("message", &self.summary)
+            .finish_non_exhaustive()
+    }
 }
 
 impl RecvMessageWrapper {
@@ -121,7 +362,11 @@ impl RecvMessageWrapper {
             total_n_fragments,
             arrived: HashSet::new(),
             fragments: vec![None; total_n_fragments as usize],
-            raw_data: "".to_string(),
+            parity: HashMap::new(),
+            recovered: Vec::new(),
+            last_activity: SystemTime::now(),
+            raw_data: Vec::new(),
+            summary: String::new(),
         }
     }
 
@@ -135,23 +380,85 @@ impl RecvMessageWrapper {
         self.arrived.len() as u64 == self.total_n_fragments
     }
 
+    /// Store a data or parity fragment, returns `false` if it was already known or invalid
+    ///
+    /// Missing data fragments are rebuilt from parity as soon as possible, see [`fec`].
     pub fn add_fragment(&mut self, fragment: Fragment) -> bool {
-        if fragment.fragment_index < self.total_n_fragments {
-            let index = fragment.fragment_index;
-            if self.arrived.contains(&index) {
+        let index = fragment.fragment_index;
+        let now = SystemTime::now();
+        if index < self.total_n_fragments {
+            if self.arrived.contains(&index) || fragment.length as usize > FRAGMENT_DSIZE {
                 return false;
             }
 
             self.arrived.insert(index);
             self.fragments[index as usize] = Some(fragment);
+            self.last_activity = now;
+            if let Some(group_size) = self.parity_group_size() {
+                self.try_recover(index / group_size, group_size);
+            }
             true
         } else {
-            false
+            let group_size = fragment.length as u64;
+            let group = index - self.total_n_fragments;
+            if group_size < 2
+                || group >= fec::n_groups(group_size, self.total_n_fragments)
+                || self.parity.contains_key(&group)
+            {
+                return false;
+            }
+
+            self.parity.insert(group, fragment);
+            self.last_activity = now;
+            self.try_recover(group, group_size);
+            true
+        }
+    }
+
+    /// Data fragments rebuilt from parity since the last call, they have to be acked
+    pub fn take_recovered(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.recovered)
+    }
+
+    fn parity_group_size(&self) -> Option<u64> {
+        self.parity.values().next().map(|p| p.length as u64)
+    }
+
+    fn try_recover(&mut self, group: u64, group_size: u64) {
+        let Some(parity) = self.parity.get(&group) else {
+            return;
+        };
+        let members = fec::group_members(group, group_size, self.total_n_fragments);
+        let mut missing = members.clone().filter(|i| !self.arrived.contains(i));
+        let (Some(index), None) = (missing.next(), missing.next()) else {
+            return;
+        };
+
+        let mut data = parity.data;
+        let mut length = 0;
+        for fragment in members.filter_map(|i| self.fragments[i as usize].as_ref()) {
+            fec::xor_into(&mut data, &fragment.data);
+            length = length.max(fragment.length);
+        }
+        // groups of at least 2 fragments: a group member is always there to tell the length,
+        // except for a lonely last fragment, whose padding is cut by the frame anyway
+        if length == 0 {
+            length = FRAGMENT_DSIZE as u8;
         }
+        self.arrived.insert(index);
+        self.fragments[index as usize] = Some(Fragment {
+            fragment_index: index,
+            total_n_fragments: self.total_n_fragments,
+            length,
+            data,
+        });
+        self.recovered.push(index);
     }
 
     /// Try to deserialize received fragments in specified message type
     ///
+    /// The codec is read from the payload envelope, see [`codec::open_envelope`].
+    ///
     /// # Example
     ///
     /// ```
@@ -165,19 +472,31 @@ impl RecvMessageWrapper {
     /// }
     /// ```
     pub fn try_deserialize<T: DroneSend>(&mut self) -> Option<T> {
-        if !self.try_generate_raw_data() {
-            return None;
-        }
-        DroneSend::from_string(self.raw_data.clone()).ok()
+        self.deserialize().ok()
+    }
+
+    /// Like `try_deserialize`, but tells why the message is not available
+    pub fn deserialize<T: DroneSend>(&mut self) -> Result<T, ReassemblyError> {
+        self.reassemble()?;
+        let message = T::decode(&self.raw_data).map_err(ReassemblyError::Decode)?;
+        self.summary = message.stringify();
+        Ok(message)
     }
 
     /// Generate self.raw_data if is possible
     pub fn try_generate_raw_data(&mut self) -> bool {
+        self.reassemble().is_ok()
+    }
+
+    /// Generate self.raw_data, verifying the integrity of the message
+    ///
+    /// The reassembled frame is checked and decompressed, see [`frame::open`].
+    pub fn reassemble(&mut self) -> Result<(), ReassemblyError> {
         if !self.is_all_fragments_arrived() {
-            return false;
+            return Err(ReassemblyError::Incomplete);
         }
 
-        let full_message: Vec<u8> = self
+        let data: Vec<u8> = self
             .fragments
             .iter()
             .flat_map(|frag| {
@@ -186,14 +505,39 @@ impl RecvMessageWrapper {
                     .unwrap_or_default()
             })
             .collect();
+        self.raw_data = frame::open(&data)?;
+        self.summary = summarize(&self.raw_data);
+        Ok(())
+    }
 
-        match String::from_utf8(full_message) {
-            Ok(raw_data) => {
-                self.raw_data = raw_data;
-                true
-            }
-            Err(_) => false,
+    /// Forget every fragment, so that the whole message can be received again
+    pub fn reset(&mut self) {
+        self.arrived.clear();
+        self.fragments = vec![None; self.total_n_fragments as usize];
+        self.parity.clear();
+        self.recovered.clear();
+        self.raw_data.clear();
+        self.summary.clear();
+    }
+
+    /// Generate self.raw_data and read it as UTF-8 text
+    pub fn try_generate_string(&mut self) -> Option<String> {
+        if !self.try_generate_raw_data() {
+            return None;
         }
+        String::from_utf8(self.raw_data.clone()).ok()
+    }
+}
+
+impl Timestamped for SentMessageWrapper {
+    fn last_activity(&self) -> SystemTime {
+        self.last_activity
+    }
+}
+
+impl Timestamped for RecvMessageWrapper {
+    fn last_activity(&self) -> SystemTime {
+        self.last_activity
     }
 }
 
@@ -205,6 +549,12 @@ pub trait DroneSend: Serialize + DeserializeOwned {
     fn from_string(raw: String) -> Result<Self, String> {
         serde_json::from_str(raw.as_str()).map_err(|e| e.to_string())
     }
+    fn encode(&self, codec: Codec) -> Vec<u8> {
+        codec.encode(self)
+    }
+    fn decode(payload: &[u8]) -> Result<Self, String> {
+        codec::decode(payload)
+    }
 }
 
 pub trait Request: DroneSend {}
@@ -219,6 +569,80 @@ pub enum ChatRequest {
         from: NodeId,
         to: NodeId,
         message: String,
+        #[serde(default)]
+        id: MessageId, // set by the sender, its edits and deletions refer to it
+    },
+    Hello(Capabilities), // first request to a server, answered by Welcome or ErrorIncompatible
+    PublishKey(PublicKey), // public key of the source, handed to the other clients
+    ClientKeys,          // public keys of the registered clients
+    SendEncrypted {
+        from: NodeId,
+        to: NodeId,
+        nonce: Nonce,
+        message: Vec<u8>, // sealed for `to`, the server relays it as it is
+        #[serde(default)]
+        id: MessageId,
+    },
+    Unregister,           // leave the server, the other clients are told with ClientLeft
+    CreateRoom(RoomName), // the source is the first member
+    JoinRoom(RoomName),
+    LeaveRoom(RoomName), // empty rooms are deleted
+    RoomList,            // rooms of the server and their members
+    SendToRoom {
+        from: NodeId,
+        room: RoomName,
+        message: String,
+    },
+    Broadcast {
+        from: NodeId,
+        message: String, // to every other registered client, answered by BroadcastReport
+    },
+    WithReceipt {
+        id: MessageId,
+        request: Box<ChatRequest>, // SendMessage or SendEncrypted, the sender wants receipts
+    },
+    Receipt {
+        from: NodeId,
+        to: NodeId, // sender of message `id`
+        id: MessageId,
+        status: ReceiptStatus,
+    },
+    GetHistory {
+        with: NodeId,
+        before: Option<u64>, // `seq` of the oldest entry already known, `None` for the latest ones
+        limit: u32,
+    },
+    SetProfile(Profile), // profile of the source, the registered clients get the new Directory
+    Directory,           // profiles of the registered clients
+    SetPresence(Presence), // Online or Away, the watchers get the change
+    WatchPresence,       // presence of the known clients, the changes are pushed to the source
+    Typing {
+        from: NodeId,
+        to: NodeId, // told only if reachable, never queued
+    },
+    ServerClients(Vec<HostedClient>), // between servers, the clients known by the source
+    Forwarded(Box<ChatRequest>),      // between servers, a request of a client hosted by the source
+    PeerKind(ServerType),             // between servers, the kind of the source, told once to every server found
+    UploadFile {
+        name: FileName,
+        data: Vec<u8>, // replaces the file with the same name
+    },
+    FileList,               // files stored by a media server
+    DownloadFile(FileName), // answered by File or ErrorWrongFile
+    DocumentList,           // documents served by a text server
+    GetDocument(FileName),  // answered by Document or ErrorWrongDocument
+    ServerType,             // asked first to every server found, answered by any kind
+    EditMessage {
+        from: NodeId,
+        to: NodeId, // recipient of message `id`
+        id: MessageId,
+        nonce: Option<Nonce>, // set if the new text is sealed for `to`
+        message: Vec<u8>,
+    },
+    DeleteMessage {
+        from: NodeId,
+        to: NodeId, // recipient of message `id`
+        id: MessageId,
     },
 }
 impl DroneSend for ChatRequest {}
@@ -227,8 +651,156 @@ impl Request for ChatRequest {}
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ChatResponse {
     ClientList(Vec<NodeId>),
-    MessageFrom { from: NodeId, message: Vec<u8> },
+    MessageFrom {
+        from: NodeId,
+        message: Vec<u8>,
+        #[serde(default)]
+        id: MessageId, // given by `from`
+    },
     ErrorWrongClientId(NodeId),
+    Welcome(Capabilities),           // agreed version and features
+    ErrorIncompatible(Capabilities), // no common version, capabilities of the server
+    ErrorWrongSender(NodeId),        // SendMessage with a `from` different from its source
+    ClientKeys(Vec<(NodeId, PublicKey)>),
+    EncryptedFrom {
+        from: NodeId,
+        nonce: Nonce,
+        message: Vec<u8>,
+        #[serde(default)]
+        id: MessageId,
+    },
+    ClientLeft(NodeId), // unregistered or no longer in the topology of the server
+    RoomList(Vec<(RoomName, Vec<NodeId>)>),
+    MessageFromRoom {
+        room: RoomName,
+        from: NodeId,
+        message: Vec<u8>,
+    },
+    ErrorWrongRoom(RoomName), // the room doesn't exist, already exists or the source is not a member
+    BroadcastFrom {
+        from: NodeId,
+        message: Vec<u8>,
+    },
+    BroadcastReport {
+        reached: Vec<NodeId>,     // a route was found and the message sent
+        unreachable: Vec<NodeId>, // no route, the message was not sent
+    },
+    MessageQueued {
+        to: NodeId, // away, the server delivers the message when it is back
+        id: MessageId,
+    },
+    WithReceipt {
+        id: MessageId,
+        response: Box<ChatResponse>, // MessageFrom or EncryptedFrom, receipts are expected
+    },
+    Receipt {
+        from: NodeId, // recipient of message `id`
+        id: MessageId,
+        status: ReceiptStatus,
+    },
+    History {
+        with: NodeId,
+        entries: Vec<HistoryEntry>, // oldest first
+        more: bool,                 // older entries are available
+    },
+    Directory(Vec<(NodeId, Profile)>),
+    Presence(Vec<(NodeId, Presence, SystemTime)>), // with the last request of each client
+    Typing(NodeId),
+    FileList(Vec<FileInfo>), // also the answer of a media server to Register
+    FileStored(FileInfo),
+    File {
+        name: FileName,
+        data: Vec<u8>,
+    },
+    ErrorWrongFile(FileName),    // the file doesn't exist or can't be stored
+    DocumentList(Vec<FileName>), // also the answer of a text server to Register
+    Document {
+        name: FileName,
+        text: String,
+    },
+    ErrorWrongDocument(FileName),
+    ServerType(ServerType),
+    MessageEdited {
+        from: NodeId, // sender of message `id`
+        id: MessageId,
+        nonce: Option<Nonce>, // set if the new text is sealed, the client opens it like EncryptedFrom
+        message: Vec<u8>,
+    },
+    MessageDeleted {
+        from: NodeId, // sender of message `id`
+        id: MessageId,
+    },
+}
+
+/// Service provided by a server, chosen with `kind` in the configuration file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ServerType {
+    #[default]
+    #[serde(rename = "chat")]
+    ChatServer,
+    #[serde(rename = "media")]
+    MediaServer,
+    #[serde(rename = "text")]
+    TextServer,
+}
+
+/// A file stored by a media server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileInfo {
+    pub name: FileName,
+    pub size: u64,
+    /// Client that uploaded it
+    pub owner: NodeId,
+}
+
+/// A client known by a server, sent to the other servers so they can forward messages to it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostedClient {
+    pub id: NodeId,
+    /// `false` while it is away, the hosting server queues its messages
+    pub registered: bool,
+    pub public_key: Option<PublicKey>,
+}
+
+/// Whether a client can answer, `Offline` is set by the server when it leaves
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Presence {
+    #[default]
+    Online,
+    Away,
+    Offline,
+}
+
+/// How a client is shown to the others, set with `SetProfile`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    /// Display name, clients without one are shown by their id
+    pub name: String,
+    pub status: String,
+}
+
+/// A direct message as relayed by the server, kept to be asked again with `GetHistory`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Position in the history of the server, pages are asked by it
+    pub seq: u64,
+    pub timestamp: SystemTime,
+    pub to: NodeId,
+    /// Given by the sender, like in `message`
+    pub id: MessageId,
+    /// `MessageFrom` or `EncryptedFrom`, with the last text if it was edited
+    pub message: ChatResponse,
+    pub edited: bool,
+    /// Deleted by the sender, `message` has no text left
+    pub deleted: bool,
+}
+
+/// How far a direct message got, each status implies the previous ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ReceiptStatus {
+    Accepted,  // by the server
+    Delivered, // to the worker of the recipient
+    Read,      // shown in the UI of the recipient
 }
 
 impl DroneSend for ChatResponse {}
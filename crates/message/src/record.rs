@@ -0,0 +1,79 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, Write};
+use std::time::SystemTime;
+use wg_2024::network::NodeId;
+use wg_2024::packet::Packet;
+
+use crate::{NodeCommand, NodeEvent};
+
+// ------------------------------ RECORDS
+// Events and commands are written as JSON lines, one `Record` per line, so a run can be
+// saved, diffed and loaded again. `NodeEvent` is serialized as it is, `NodeCommand` goes
+// through `RecordedCommand` because the channel of `AddSender` can't leave the process.
+
+/// `NodeCommand` without the channel of `AddSender`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RecordedCommand {
+    RemoveSender(NodeId),
+    AddSender(NodeId),
+    FromShortcut(Packet),
+}
+
+impl From<&NodeCommand> for RecordedCommand {
+    fn from(command: &NodeCommand) -> Self {
+        match command {
+            NodeCommand::RemoveSender(id) => RecordedCommand::RemoveSender(*id),
+            NodeCommand::AddSender(id, _) => RecordedCommand::AddSender(*id),
+            NodeCommand::FromShortcut(packet) => RecordedCommand::FromShortcut(packet.clone()),
+        }
+    }
+}
+
+/// An event or a command with the node that emitted (or received) it and when
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record<T> {
+    pub timestamp: SystemTime,
+    pub node_id: NodeId,
+    pub entry: T,
+}
+
+pub type EventRecord = Record<NodeEvent>;
+pub type CommandRecord = Record<RecordedCommand>;
+
+impl<T> Record<T> {
+    pub fn new(node_id: NodeId, entry: T) -> Self {
+        Self {
+            timestamp: SystemTime::now(),
+            node_id,
+            entry,
+        }
+    }
+}
+
+impl EventRecord {
+    pub fn from_event(node_id: NodeId, event: &NodeEvent) -> Self {
+        Self::new(node_id, event.clone())
+    }
+}
+
+impl CommandRecord {
+    pub fn from_command(node_id: NodeId, command: &NodeCommand) -> Self {
+        Self::new(node_id, command.into())
+    }
+}
+
+/// Append `record` to `writer` as one JSON line
+pub fn write_record<T: Serialize>(writer: &mut impl Write, record: &Record<T>) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, record)?;
+    writeln!(writer)
+}
+
+/// Load the records written by `write_record`, empty lines are skipped
+pub fn read_records<T: DeserializeOwned>(reader: impl BufRead) -> io::Result<Vec<Record<T>>> {
+    reader
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
@@ -0,0 +1,621 @@
+#[cfg(test)]
+mod tests {
+    use crate::codec::{open_envelope, ENVELOPE_MAGIC};
+    use crate::expiry::stale_keys;
+    use crate::frame::{self, FLAG_COMPRESSED, FRAME_MAGIC};
+    use crate::record;
+    use crate::{
+        Capabilities, ChatRequest, ChatResponse, Codec, CommandRecord, DroneSend, EventRecord,
+        Features, FragmentationOptions, NodeCommand, NodeEvent, ReassemblyError, RecordedCommand,
+        RecvMessageWrapper, SentMessageWrapper, FRAGMENT_DSIZE, PROTOCOL_VERSION,
+    };
+    use std::collections::HashMap;
+    use std::time::{Duration, SystemTime};
+
+    fn transmit(wrapper: &SentMessageWrapper) -> RecvMessageWrapper {
+        let mut recv = RecvMessageWrapper::new(wrapper.session_id, 1, wrapper.total_n_fragments);
+        for fragment in &wrapper.fragments {
+            recv.add_fragment(fragment.clone());
+        }
+        recv
+    }
+
+    #[test]
+    fn test_codec_round_trip() {
+        let msg = ChatRequest::SendMessage {
+            from: 1,
+            to: 2,
+            message: "Hello".to_string(),
+            id: 3,
+        };
+        for codec in [Codec::Json, Codec::Binary] {
+            let wrapper = SentMessageWrapper::from_message_with_codec(7, 2, &msg, codec);
+            let mut recv = transmit(&wrapper);
+            match recv.try_deserialize::<ChatRequest>() {
+                Some(ChatRequest::SendMessage {
+                    from,
+                    to,
+                    message,
+                    id,
+                }) => {
+                    assert_eq!((from, to, message.as_str(), id), (1, 2, "Hello", 3));
+                }
+                other => panic!("Unexpected message {:?} with codec {:?}", other, codec),
+            }
+        }
+    }
+
+    #[test]
+    fn test_envelope_tells_codec() {
+        let msg = ChatRequest::ClientList;
+        for codec in [Codec::Json, Codec::Binary] {
+            let payload = msg.encode(codec);
+            assert_eq!(payload[0], ENVELOPE_MAGIC);
+            assert_eq!(open_envelope(&payload).unwrap().0, codec);
+        }
+    }
+
+    #[test]
+    fn test_legacy_json_payload() {
+        let raw = ChatRequest::Register(4).stringify();
+        assert_eq!(open_envelope(raw.as_bytes()).unwrap().0, Codec::Json);
+        assert!(matches!(
+            ChatRequest::decode(raw.as_bytes()),
+            Ok(ChatRequest::Register(4))
+        ));
+    }
+
+    #[test]
+    fn test_legacy_message_without_id() {
+        let raw = r#"{"SendMessage":{"from":1,"to":2,"message":"Hello"}}"#;
+        assert!(matches!(
+            ChatRequest::decode(raw.as_bytes()),
+            Ok(ChatRequest::SendMessage { id: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn test_binary_uses_less_fragments() {
+        let msg = ChatResponse::MessageFrom {
+            from: 1,
+            message: "a short chat line, repeated. ".repeat(10).into_bytes(),
+            id: 0,
+        };
+        let options = FragmentationOptions {
+            compress: false,
+            ..Default::default()
+        };
+        let json = SentMessageWrapper::new_with_options(1, 2, msg.encode(Codec::Json), &options);
+        let binary =
+            SentMessageWrapper::new_with_options(1, 2, msg.encode(Codec::Binary), &options);
+        assert!(binary.total_n_fragments < json.total_n_fragments);
+    }
+
+    #[test]
+    fn test_unknown_envelope_is_rejected() {
+        let mut payload = ChatRequest::ClientList.encode(Codec::Binary);
+        payload[2] = 42;
+        assert!(ChatRequest::decode(&payload).is_err());
+    }
+
+    #[test]
+    fn test_binary_payload_round_trip() {
+        let payload: Vec<u8> = (0..=255u8).cycle().take(300).collect();
+        assert!(String::from_utf8(payload.clone()).is_err());
+
+        let options = FragmentationOptions {
+            compress: false,
+            ..Default::default()
+        };
+        let wrapper = SentMessageWrapper::new_with_options(3, 2, payload.clone(), &options);
+        assert_eq!(wrapper.total_n_fragments, 3);
+        let mut recv = transmit(&wrapper);
+        assert!(recv.try_generate_raw_data());
+        assert_eq!(recv.raw_data, payload);
+        assert_eq!(recv.try_generate_string(), None);
+    }
+
+    #[test]
+    fn test_string_helpers() {
+        let wrapper = SentMessageWrapper::new_from_raw_data(3, 2, "plain text".to_string());
+        let mut recv = transmit(&wrapper);
+        assert_eq!(recv.try_generate_string().as_deref(), Some("plain text"));
+    }
+
+    #[test]
+    fn test_binary_messages_are_shown_readable() {
+        let msg = ChatRequest::Register(4);
+        let wrapper = SentMessageWrapper::from_message_with_codec(1, 2, &msg, Codec::Binary);
+        assert_eq!(wrapper.summary, r#"{"Register":4}"#);
+        assert!(format!("{:?}", wrapper).contains(r#"{\"Register\":4}"#));
+
+        let mut recv = transmit(&wrapper);
+        assert!(recv.try_generate_raw_data());
+        assert_eq!(recv.summary, format!("<{} bytes>", recv.raw_data.len()));
+        assert!(recv.try_deserialize::<ChatRequest>().is_some());
+        assert_eq!(recv.summary, wrapper.summary);
+
+        let wrapper = SentMessageWrapper::new_from_raw_data(3, 2, "plain text".to_string());
+        assert_eq!(wrapper.summary, "plain text");
+    }
+
+    #[test]
+    fn test_incomplete_message_has_no_raw_data() {
+        let wrapper = SentMessageWrapper::new_from_bytes(3, 2, vec![7; 200]);
+        let mut recv = RecvMessageWrapper::new(3, 1, wrapper.total_n_fragments);
+        recv.add_fragment(wrapper.fragments[0].clone());
+        assert!(!recv.try_generate_raw_data());
+        assert!(recv.raw_data.is_empty());
+    }
+
+    #[test]
+    fn test_compression_reduces_fragments() {
+        let msg = ChatRequest::SendMessage {
+            from: 1,
+            to: 2,
+            message: "the same long line, again and again. ".repeat(40),
+            id: 0,
+        };
+        let payload = msg.encode(Codec::Json);
+        let plain = SentMessageWrapper::new_with_options(
+            1,
+            2,
+            payload.clone(),
+            &FragmentationOptions {
+                compress: false,
+                ..Default::default()
+            },
+        );
+        let compressed = SentMessageWrapper::new_from_bytes(1, 2, payload);
+        assert_eq!(
+            compressed.fragments[0].data[2] & FLAG_COMPRESSED,
+            FLAG_COMPRESSED
+        );
+        assert!(compressed.total_n_fragments < plain.total_n_fragments);
+
+        let mut recv = transmit(&compressed);
+        match recv.try_deserialize::<ChatRequest>() {
+            Some(ChatRequest::SendMessage { message, .. }) => {
+                assert_eq!(message, "the same long line, again and again. ".repeat(40));
+            }
+            other => panic!("Unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_small_payload_is_not_compressed() {
+        let wrapper = SentMessageWrapper::from_message(1, 2, &ChatRequest::Register(1));
+        assert_eq!(wrapper.fragments[0].data[0], FRAME_MAGIC);
+        assert_eq!(wrapper.fragments[0].data[2] & FLAG_COMPRESSED, 0);
+    }
+
+    #[test]
+    fn test_incompressible_payload_is_sent_plain() {
+        let payload: Vec<u8> = (0..1024u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let frame = frame::seal(&payload, true);
+        if frame[2] & FLAG_COMPRESSED == 0 {
+            assert_eq!(frame.len(), frame::FRAME_HEADER_LEN + payload.len());
+        } else {
+            assert!(frame.len() < frame::FRAME_HEADER_LEN + payload.len());
+        }
+        assert_eq!(frame::open(&frame).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_corrupted_compressed_frame_is_rejected() {
+        let payload = "x".repeat(1000).into_bytes();
+        let mut frame = frame::seal(&payload, true);
+        assert_eq!(frame[2] & FLAG_COMPRESSED, FLAG_COMPRESSED);
+        frame.truncate(frame.len() - 2);
+        assert!(frame::open(&frame).is_err());
+    }
+
+    #[test]
+    fn test_corrupted_fragment_is_detected() {
+        let msg = ChatRequest::SendMessage {
+            from: 1,
+            to: 2,
+            message: (0..200).map(|i| char::from(b'a' + i % 26)).collect(),
+            id: 0,
+        };
+        let mut wrapper = SentMessageWrapper::new_with_options(
+            1,
+            2,
+            msg.encode(Codec::Binary),
+            &FragmentationOptions {
+                compress: false,
+                ..Default::default()
+            },
+        );
+        assert!(wrapper.total_n_fragments > 1);
+        wrapper.fragments[1].data[5] ^= 0x01;
+
+        let mut recv = transmit(&wrapper);
+        match recv.deserialize::<ChatRequest>() {
+            Err(e @ ReassemblyError::Corrupted { .. }) => assert!(e.is_corruption()),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_damaged_magic_byte_is_corruption() {
+        let wrapper = SentMessageWrapper::from_message(1, 2, &ChatRequest::Register(4));
+        let mut damaged = wrapper.fragments[0].clone();
+        damaged.data[0] ^= 0x10;
+
+        let mut recv = RecvMessageWrapper::new_from_fragment(1, 1, damaged);
+        match recv.deserialize::<ChatRequest>() {
+            Err(e @ ReassemblyError::Corrupted { .. }) => assert!(e.is_corruption()),
+            other => panic!("Unexpected result {:?}", other),
+        }
+
+        // unframed payloads of older nodes are still read
+        let legacy = ChatRequest::Register(4).encode(Codec::Json);
+        assert_eq!(frame::open(&legacy).unwrap(), legacy);
+        let json = serde_json::to_vec(&ChatRequest::Register(4)).unwrap();
+        assert_eq!(frame::open(&json).unwrap(), json);
+    }
+
+    #[test]
+    fn test_reassembly_errors() {
+        let wrapper = SentMessageWrapper::from_message(1, 2, &ChatRequest::ClientList);
+        let mut recv = RecvMessageWrapper::new(1, 1, wrapper.total_n_fragments + 1);
+        recv.add_fragment(wrapper.fragments[0].clone());
+        assert_eq!(
+            recv.deserialize::<ChatRequest>().unwrap_err(),
+            ReassemblyError::Incomplete
+        );
+
+        let mut recv = transmit(&wrapper);
+        assert!(matches!(
+            recv.deserialize::<ChatResponse>(),
+            Err(ReassemblyError::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn test_reset_after_corruption() {
+        let wrapper = SentMessageWrapper::from_message(1, 2, &ChatRequest::Register(4));
+        let mut corrupted = wrapper.fragments[0].clone();
+        corrupted.data[frame::FRAME_HEADER_LEN] ^= 0x80;
+
+        let mut recv = RecvMessageWrapper::new_from_fragment(1, 1, corrupted);
+        assert!(recv.reassemble().unwrap_err().is_corruption());
+        recv.reset();
+        assert!(!recv.is_all_fragments_arrived());
+        assert!(recv.add_fragment(wrapper.fragments[0].clone()));
+        assert!(matches!(
+            recv.try_deserialize::<ChatRequest>(),
+            Some(ChatRequest::Register(4))
+        ));
+    }
+
+    fn with_parity(payload: Vec<u8>, parity_group: usize) -> SentMessageWrapper {
+        let options = FragmentationOptions {
+            compress: false,
+            parity_group,
+            ..Default::default()
+        };
+        SentMessageWrapper::new_with_options(1, 2, payload, &options)
+    }
+
+    #[test]
+    fn test_parity_fragments_layout() {
+        let wrapper = with_parity(vec![1; 1000], 3);
+        assert_eq!(wrapper.total_n_fragments, 8);
+        assert_eq!(wrapper.fragments.len(), 11);
+        for (group, parity) in wrapper.fragments[8..].iter().enumerate() {
+            assert_eq!(parity.fragment_index, 8 + group as u64);
+            assert_eq!(parity.total_n_fragments, 8);
+            assert_eq!(parity.length, 3);
+        }
+    }
+
+    #[test]
+    fn test_parity_rebuilds_missing_fragments() {
+        let payload: Vec<u8> = (0..1000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let wrapper = with_parity(payload.clone(), 4);
+        let n = wrapper.total_n_fragments;
+
+        // one loss in each group, the last fragment is one of them
+        let mut recv = RecvMessageWrapper::new(1, 1, n);
+        for fragment in &wrapper.fragments {
+            if fragment.fragment_index != 1 && fragment.fragment_index != n - 1 {
+                recv.add_fragment(fragment.clone());
+            }
+        }
+        assert!(recv.is_all_fragments_arrived());
+        let mut recovered = recv.take_recovered();
+        recovered.sort();
+        assert_eq!(recovered, vec![1, n - 1]);
+        assert!(recv.take_recovered().is_empty());
+        assert!(recv.try_generate_raw_data());
+        assert_eq!(recv.raw_data, payload);
+    }
+
+    #[test]
+    fn test_parity_arriving_before_data() {
+        // 3 data fragments, parity 3 covers 0 and 1, parity 4 covers 2
+        let wrapper = with_parity(vec![9; 300], 2);
+        assert_eq!(wrapper.fragments.len(), 5);
+
+        let mut recv = RecvMessageWrapper::new(1, 1, wrapper.total_n_fragments);
+        for index in [3, 2, 1] {
+            recv.add_fragment(wrapper.fragments[index].clone());
+        }
+        assert_eq!(recv.take_recovered(), vec![0]);
+        assert!(recv.try_generate_raw_data());
+        assert_eq!(recv.raw_data, vec![9; 300]);
+    }
+
+    #[test]
+    fn test_two_losses_in_a_group_are_not_rebuilt() {
+        let wrapper = with_parity(vec![5; 1000], 4);
+        let mut recv = RecvMessageWrapper::new(1, 1, wrapper.total_n_fragments);
+        for fragment in &wrapper.fragments {
+            if fragment.fragment_index != 0 && fragment.fragment_index != 3 {
+                recv.add_fragment(fragment.clone());
+            }
+        }
+        assert!(!recv.is_all_fragments_arrived());
+        assert!(recv.take_recovered().is_empty());
+        assert!(!recv.add_fragment(wrapper.fragments[wrapper.fragments.len() - 1].clone()));
+
+        recv.add_fragment(wrapper.fragments[3].clone());
+        assert_eq!(recv.take_recovered(), vec![0]);
+        assert!(recv.is_all_fragments_arrived());
+    }
+
+    #[test]
+    fn test_parity_is_rejected_when_invalid() {
+        let wrapper = with_parity(vec![5; 500], 2);
+        let mut recv = RecvMessageWrapper::new(1, 1, wrapper.total_n_fragments);
+        let mut parity = wrapper.fragments[wrapper.total_n_fragments as usize].clone();
+        parity.length = 0;
+        assert!(!recv.add_fragment(parity.clone()));
+        parity.length = 1;
+        assert!(!recv.add_fragment(parity.clone()));
+        parity.length = 2;
+        parity.fragment_index += 10;
+        assert!(!recv.add_fragment(parity));
+    }
+
+    #[test]
+    fn test_fragment_lost_policy() {
+        let mut plain = SentMessageWrapper::new_from_bytes(1, 2, vec![5; 1000]);
+        assert!(plain.fragment_lost(0));
+
+        let mut wrapper = with_parity(vec![5; 1000], 4);
+        // group 0: fragments 0..4 and parity 8
+        assert!(!wrapper.fragment_lost(1));
+        assert!(wrapper.fragment_lost(8));
+        assert!(wrapper.fragment_lost(1));
+        // group 1 is untouched by group 0 losses
+        assert!(!wrapper.fragment_lost(5));
+    }
+
+    fn with_size(
+        payload: Vec<u8>,
+        fragment_size: usize,
+        parity_group: usize,
+    ) -> SentMessageWrapper {
+        let options = FragmentationOptions {
+            compress: false,
+            parity_group,
+            fragment_size,
+        };
+        SentMessageWrapper::new_with_options(1, 2, payload, &options)
+    }
+
+    #[test]
+    fn test_fragment_size_boundaries() {
+        let payload: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+        let framed = payload.len() + frame::FRAME_HEADER_LEN;
+        // 0 and sizes above the protocol maximum are clamped
+        for (size, expected) in [(0, 1), (1, 1), (2, 2), (127, 127), (128, 128), (129, 128)] {
+            let wrapper = with_size(payload.clone(), size, 0);
+            assert_eq!(
+                wrapper.total_n_fragments,
+                framed.div_ceil(expected) as u64,
+                "size {}",
+                size
+            );
+            assert!(wrapper
+                .fragments
+                .iter()
+                .all(|f| f.length as usize <= expected));
+
+            let mut recv = transmit(&wrapper);
+            assert!(recv.try_generate_raw_data(), "size {}", size);
+            assert_eq!(recv.raw_data, payload);
+        }
+    }
+
+    #[test]
+    fn test_fragment_size_exact_multiple() {
+        // the frame fills exactly 4 fragments of 100 bytes
+        let wrapper = with_size(vec![3; 400 - frame::FRAME_HEADER_LEN], 100, 0);
+        assert_eq!(wrapper.total_n_fragments, 4);
+        assert!(wrapper.fragments.iter().all(|f| f.length == 100));
+        let mut recv = transmit(&wrapper);
+        assert!(recv.try_generate_raw_data());
+        assert_eq!(recv.raw_data, vec![3; 400 - frame::FRAME_HEADER_LEN]);
+    }
+
+    #[test]
+    fn test_small_fragments_rebuilt_from_parity() {
+        let payload: Vec<u8> = (0..500u32).map(|i| (i * 13 % 256) as u8).collect();
+        for size in [1, 37, 127] {
+            let wrapper = with_size(payload.clone(), size, 3);
+            let n = wrapper.total_n_fragments;
+            assert!(wrapper.fragments[..n as usize]
+                .iter()
+                .all(|f| f.length as usize == size));
+
+            // the first and the last data fragments are dropped
+            let mut recv = RecvMessageWrapper::new(1, 1, n);
+            for fragment in &wrapper.fragments {
+                if fragment.fragment_index != 0 && fragment.fragment_index != n - 1 {
+                    recv.add_fragment(fragment.clone());
+                }
+            }
+            assert!(recv.is_all_fragments_arrived(), "size {}", size);
+            assert!(recv.try_generate_raw_data(), "size {}", size);
+            assert_eq!(recv.raw_data, payload);
+        }
+    }
+
+    #[test]
+    fn test_parity_group_is_clamped() {
+        let wrapper = with_parity(vec![5; 500], 1);
+        assert_eq!(wrapper.parity_group, 2);
+        let wrapper = with_parity(vec![5; 500], 1000);
+        assert_eq!(wrapper.parity_group, u8::MAX as usize);
+    }
+
+    #[test]
+    fn test_oversized_fragment_is_rejected() {
+        let wrapper = SentMessageWrapper::from_message(1, 2, &ChatRequest::ClientList);
+        let mut fragment = wrapper.fragments[0].clone();
+        fragment.length = FRAGMENT_DSIZE as u8 + 1;
+        let mut recv = RecvMessageWrapper::new(1, 1, wrapper.total_n_fragments);
+        assert!(!recv.add_fragment(fragment));
+        assert!(recv.add_fragment(wrapper.fragments[0].clone()));
+    }
+
+    #[test]
+    fn test_stale_keys_by_timeout_and_cap() {
+        let now = SystemTime::now();
+        let entries: HashMap<u64, SystemTime> = [
+            (1, now - Duration::from_secs(100)),
+            (2, now - Duration::from_secs(30)),
+            (3, now - Duration::from_secs(20)),
+            (4, now),
+        ]
+        .into();
+
+        let mut stale = stale_keys(&entries, Duration::from_secs(60), usize::MAX, now);
+        stale.sort();
+        assert_eq!(stale, vec![1]);
+
+        let mut stale = stale_keys(&entries, Duration::from_secs(60), 2, now);
+        stale.sort();
+        assert_eq!(stale, vec![1, 2]);
+
+        assert!(stale_keys(&entries, Duration::from_secs(600), 4, now).is_empty());
+    }
+
+    #[test]
+    fn test_wrappers_track_activity() {
+        let mut sent = SentMessageWrapper::new_from_bytes(1, 2, vec![1; 300]);
+        let created = sent.last_activity;
+        sent.add_acked(0);
+        assert!(sent.last_activity >= created);
+
+        let mut recv = RecvMessageWrapper::new(1, 1, sent.total_n_fragments);
+        recv.last_activity = created - Duration::from_secs(10);
+        assert!(recv.add_fragment(sent.fragments[0].clone()));
+        assert!(recv.last_activity >= created);
+
+        // duplicates are not activity
+        recv.last_activity = created - Duration::from_secs(10);
+        recv.add_fragment(sent.fragments[0].clone());
+        assert_eq!(recv.last_activity, created - Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_records_round_trip() {
+        let wrapper = SentMessageWrapper::from_message(3, 2, &ChatRequest::Register(1));
+        let recv = transmit(&wrapper);
+        let events = [
+            NodeEvent::CreateMessage(wrapper.clone()),
+            NodeEvent::MessageRecv(recv),
+        ];
+        let (tx, _rx) = crossbeam_channel::unbounded();
+        let commands = [NodeCommand::AddSender(4, tx), NodeCommand::RemoveSender(4)];
+
+        let mut file = Vec::new();
+        for event in &events {
+            record::write_record(&mut file, &EventRecord::from_event(1, event)).unwrap();
+        }
+        let events: Vec<EventRecord> = record::read_records(file.as_slice()).unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|record| record.node_id == 1));
+        match &events[1].entry {
+            NodeEvent::MessageRecv(recv) => {
+                assert_eq!(recv.session_id, 3);
+                assert_eq!(recv.arrived.len(), wrapper.fragments.len());
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+
+        let mut file = Vec::new();
+        for command in &commands {
+            record::write_record(&mut file, &CommandRecord::from_command(2, command)).unwrap();
+        }
+        file.extend_from_slice(b"\n");
+        let commands: Vec<CommandRecord> = record::read_records(file.as_slice()).unwrap();
+        assert!(matches!(
+            commands.as_slice(),
+            [
+                CommandRecord {
+                    entry: RecordedCommand::AddSender(4),
+                    ..
+                },
+                CommandRecord {
+                    entry: RecordedCommand::RemoveSender(4),
+                    ..
+                }
+            ]
+        ));
+        assert!(record::read_records::<NodeEvent>(b"not json".as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_capabilities_negotiation() {
+        let ours = Capabilities::new(Features::COMPRESSION | Features::RECEIPTS);
+        let peer = Capabilities::new(Features::COMPRESSION | Features::GROUPS);
+        let agreed = ours.negotiate(&peer).unwrap();
+        assert_eq!(agreed.version, PROTOCOL_VERSION);
+        assert_eq!(agreed.features, Features::COMPRESSION);
+        assert!(!agreed.supports(Features::RECEIPTS));
+
+        // a newer peer still speaking our version
+        let newer = Capabilities {
+            version: PROTOCOL_VERSION + 2,
+            min_version: PROTOCOL_VERSION,
+            features: Features(0xFF),
+        };
+        let agreed = ours.negotiate(&newer).unwrap();
+        assert_eq!(agreed.version, PROTOCOL_VERSION);
+        assert_eq!(agreed.features, ours.features);
+        assert_eq!(newer.negotiate(&ours), Some(agreed));
+
+        let too_new = Capabilities {
+            min_version: PROTOCOL_VERSION + 1,
+            ..newer
+        };
+        assert_eq!(ours.negotiate(&too_new), None);
+        assert_eq!(too_new.negotiate(&ours), None);
+    }
+
+    #[test]
+    fn test_agreed_features_restrict_fragmentation() {
+        let options = FragmentationOptions::default();
+        let plain = Capabilities::new(Features::NONE);
+        assert!(!plain.restrict(&options).compress);
+        assert!(Capabilities::default().restrict(&options).compress);
+
+        let hello = ChatRequest::Hello(Capabilities::default());
+        for codec in [Codec::Json, Codec::Binary] {
+            match ChatRequest::decode(&hello.encode(codec)) {
+                Ok(ChatRequest::Hello(capabilities)) => {
+                    assert_eq!(capabilities, Capabilities::default())
+                }
+                other => panic!("Unexpected result {:?}", other),
+            }
+        }
+    }
+}
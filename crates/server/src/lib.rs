@@ -1,18 +1,24 @@
+mod media;
 mod message;
 mod network;
+mod text;
 mod test;
 
 use crate::message::ServerMessageManager;
 use crate::network::NetworkManager;
+pub use crate::text::DocumentStore;
 use ::message::NodeEvent::{ControllerShortcut, PacketSent};
-use ::message::{NodeCommand, NodeEvent};
+use ::message::{Capabilities, Features, NodeCommand, NodeEvent, SentMessageWrapper, ServerType};
 use crossbeam_channel::select_biased;
 use crossbeam_channel::{Receiver, Sender};
 use log::{info, warn};
 use std::collections::HashMap;
 use std::time::Duration;
 use wg_2024::network::*;
-use wg_2024::packet::{Ack, FloodRequest, FloodResponse, NodeType, Packet, PacketType};
+use wg_2024::packet::{Ack, FloodRequest, FloodResponse, Nack, NackType, NodeType, Packet, PacketType};
+
+/// Longest wait of `run` for a packet or a command, the timers are checked at least this often
+const TICK: Duration = Duration::from_millis(100);
 
 #[derive(Clone, Debug)]
 pub struct ChatServer {
@@ -50,14 +56,31 @@ impl ChatServer {
         }
     }
 
+    /// Serve `kind` instead of the chat, media and text servers only agree on compression
+    pub fn with_kind(mut self, kind: ServerType) -> Self {
+        self.server_message_manager.kind = kind;
+        if kind != ServerType::ChatServer {
+            self.server_message_manager.capabilities = Capabilities::new(Features::COMPRESSION);
+        }
+        self
+    }
+
+    /// Documents served as a text server
+    pub fn with_documents(mut self, documents: DocumentStore) -> Self {
+        self.server_message_manager.documents = documents;
+        self
+    }
+
     pub fn run(&mut self) {
         self.flood_initializer();
 
         loop {
             select_biased! {
                 recv(self.controller_recv) -> packet =>{
-                    if let Ok(packet) = packet {
-                        self.command_handler(packet);
+                    match packet {
+                        Ok(packet) => self.command_handler(packet),
+                        // the controller is gone, so is the simulation
+                        Err(_) => return,
                     }
                 },
                 recv(self.packet_recv) -> packet =>{
@@ -65,12 +88,19 @@ impl ChatServer {
                         self.packet_handler(packet);
                     }
                 }
+                default(TICK) => {}
             }
             
             self.try_resend();
             if self.network_manager.should_flood_request(){
                 self.flood_initializer();
             }
+            self.collect_garbage();
+            self.expire_registrations();
+            self.deliver_pending();
+            if self.server_message_manager.should_sync_peers() {
+                self.sync_peers();
+            }
         }
     }
 
@@ -96,30 +126,47 @@ impl ChatServer {
         match packet.pack_type {
             //da completare
             PacketType::MsgFragment(fragment) => {
-                let mut ack = Packet{
-                    routing_header: {
-                        if let Some(route) = self.network_manager.get_route(&packet.routing_header.destination().unwrap()){
-                            SourceRoutingHeader{
-                                hop_index: 0,
-                                hops: route,
-                            }
-                        }
-                        else{
-                            packet.routing_header.reset_hop_index();
-                            packet.routing_header.get_reversed()
+                let key = &(packet.session_id, packet.routing_header.source().unwrap());
+
+                let recovered = self.server_message_manager.store_fragment(key, fragment.clone());
+
+                // the fragment that completes a corrupted message is not acked, every fragment is asked again
+                if self.server_message_manager.are_all_fragment_arrived(key) {
+                    let recv_msg = self
+                        .server_message_manager
+                        .get_incoming_fragments(key)
+                        .unwrap();
+                    let corruption = self.server_message_manager.verify_message(key).err().filter(|e| e.is_corruption());
+                    if let Some(e) = corruption {
+                        warn!("Message {:?} is corrupted ({:?}), asking it again", recv_msg, e);
+                        self.send_event(NodeEvent::MessageCorrupted(recv_msg.clone()));
+                        for fragment_index in 0..recv_msg.total_n_fragments {
+                            let mut nack = Packet{
+                                routing_header: self.reply_header(&mut packet.routing_header),
+                                session_id: packet.session_id,
+                                pack_type: PacketType::Nack(Nack{
+                                    fragment_index,
+                                    nack_type: NackType::Dropped,
+                                }),
+                            };
+                            self.send_packet(&mut nack);
                         }
-                    },
-                    session_id: packet.session_id,
-                    pack_type: PacketType::Ack(Ack{
-                        fragment_index: fragment.fragment_index,
-                    }),
-                };
+                        return;
+                    }
+                }
 
-                self.send_packet(&mut ack);
+                // data fragments rebuilt from parity are acked as if they arrived
+                for fragment_index in std::iter::once(fragment.fragment_index).chain(recovered) {
+                    let mut ack = Packet{
+                        routing_header: self.reply_header(&mut packet.routing_header),
+                        session_id: packet.session_id,
+                        pack_type: PacketType::Ack(Ack{
+                            fragment_index,
+                        }),
+                    };
 
-                let key = &(packet.session_id, packet.routing_header.source().unwrap());
-                
-                self.server_message_manager.store_fragment(key, fragment.clone());
+                    self.send_packet(&mut ack);
+                }
 
                 if self.server_message_manager.are_all_fragment_arrived(key) {
                     let recv_msg = self
@@ -129,33 +176,10 @@ impl ChatServer {
                     info!("Complete message {:?} received", recv_msg);
                     self.send_event(NodeEvent::MessageRecv(recv_msg));
 
-                    if let Some(wrapper) =
-                        self.server_message_manager.message_handling(key, self.last_session_id + 1, &self.network_manager.get_client_list())
-                    {
-                        self.last_session_id += 1;
-                        self.send_event(NodeEvent::CreateMessage(wrapper.clone()));
-                        if let Some(route) = self.network_manager.get_route(&wrapper.destination){
-                            for frag in wrapper.fragments {
-                                self.send_packet(&mut Packet {
-                                    routing_header: SourceRoutingHeader::initialize(
-                                        route.clone()
-                                    ),
-                                    session_id: wrapper.session_id,
-                                    pack_type: PacketType::MsgFragment(frag),
-                                })
-                            }
-                        }
-                        else{
-                            for frag in wrapper.fragments {
-                                self.add_to_buffer(Packet {
-                                    routing_header: SourceRoutingHeader::initialize(
-                                        vec![wrapper.destination],
-                                    ),
-                                    session_id: wrapper.session_id,
-                                    pack_type: PacketType::MsgFragment(frag),
-                                })
-                            }
-                        }
+                    let wrappers =
+                        self.server_message_manager.message_handling(key, &mut self.last_session_id, &self.network_manager);
+                    for wrapper in wrappers {
+                        self.send_message(wrapper);
                     }
                 }
             }
@@ -169,10 +193,19 @@ impl ChatServer {
             //da controllare
             PacketType::Nack(nack) => {
                 info!("Nack retrieved: {:?}", nack);
-                self.network_manager
-                    .update_from_nack(&packet.routing_header.hops, nack.clone());
+                let nack_source = packet.routing_header.source().unwrap();
+                // the destination asks a corrupted message again, no drone dropped it
+                let corrupted = matches!(nack.nack_type, NackType::Dropped)
+                    && self.server_message_manager.get_outgoing_packet(&packet.session_id).is_some_and(|wrapper| wrapper.destination == nack_source);
+                if !corrupted {
+                    self.network_manager
+                        .update_from_nack(&packet.routing_header.hops, nack.clone());
+                }
 
-                if let Some(wrapper) = self.server_message_manager.get_outgoing_packet(&packet.session_id){
+                if !self.server_message_manager.fragment_lost(&packet.session_id, nack.fragment_index, nack_source) {
+                    info!("Fragment {} with session id {} can be rebuilt from parity, not sent again", nack.fragment_index, packet.session_id);
+                }
+                else if let Some(wrapper) = self.server_message_manager.get_outgoing_packet(&packet.session_id){
                     let fragment_to_resend =
                         wrapper.get_fragment(nack.fragment_index as usize).unwrap();
                     let mut packet_to_send = Packet {
@@ -228,6 +261,80 @@ impl ChatServer {
             }
         }
     }
+    fn reply_header(&self, routing_header: &mut SourceRoutingHeader) -> SourceRoutingHeader {
+        if let Some(route) = self.network_manager.get_route(&routing_header.destination().unwrap()){
+            SourceRoutingHeader{
+                hop_index: 0,
+                hops: route,
+            }
+        }
+        else{
+            routing_header.reset_hop_index();
+            routing_header.get_reversed()
+        }
+    }
+    fn send_message(&mut self, wrapper: SentMessageWrapper) {
+        self.send_event(NodeEvent::CreateMessage(wrapper.clone()));
+        if let Some(route) = self.network_manager.get_route(&wrapper.destination){
+            for frag in wrapper.fragments {
+                self.send_packet(&mut Packet {
+                    routing_header: SourceRoutingHeader::initialize(
+                        route.clone()
+                    ),
+                    session_id: wrapper.session_id,
+                    pack_type: PacketType::MsgFragment(frag),
+                })
+            }
+        }
+        else{
+            for frag in wrapper.fragments {
+                self.add_to_buffer(Packet {
+                    routing_header: SourceRoutingHeader::initialize(
+                        vec![wrapper.destination],
+                    ),
+                    session_id: wrapper.session_id,
+                    pack_type: PacketType::MsgFragment(frag),
+                })
+            }
+        }
+    }
+    /// Unregister the clients that left the topology and tell the others
+    fn expire_registrations(&mut self) {
+        let departed = self.server_message_manager.expire_registrations(&self.network_manager.get_client_list());
+        if departed.is_empty() {
+            return;
+        }
+        let notices = self.server_message_manager.departure_notices(&departed, &mut self.last_session_id);
+        for wrapper in notices {
+            self.send_message(wrapper);
+        }
+    }
+    /// Send the messages queued for clients that are reachable again
+    fn deliver_pending(&mut self) {
+        let wrappers = self.server_message_manager.deliver_pending(&self.network_manager, &mut self.last_session_id);
+        for wrapper in wrappers {
+            self.send_message(wrapper);
+        }
+    }
+    /// Tell our kind to the other servers and send our clients to the chat servers that don't know them yet
+    fn sync_peers(&mut self) {
+        let servers = self.network_manager.get_server_list();
+        let wrappers = self.server_message_manager.sync_peers(&servers, &self.network_manager, &mut self.last_session_id);
+        for wrapper in wrappers {
+            self.send_message(wrapper);
+        }
+    }
+    fn collect_garbage(&mut self) {
+        let (incoming, outgoing) = self.server_message_manager.collect_garbage();
+        for wrapper in incoming {
+            warn!("Abandoned partial message with session id {} from {}, {}/{} fragments arrived", wrapper.session_id, wrapper.source, wrapper.arrived.len(), wrapper.total_n_fragments);
+            self.send_event(NodeEvent::MessageRecvAbandoned(wrapper));
+        }
+        for wrapper in outgoing {
+            warn!("Abandoned message with session id {} to {}, {}/{} fragments acked", wrapper.session_id, wrapper.destination, wrapper.acked.len(), wrapper.total_n_fragments);
+            self.send_event(NodeEvent::MessageSentAbandoned(wrapper));
+        }
+    }
     fn try_resend(&mut self) {
         if !self.server_buffer.is_empty(){
             let keys = self.server_buffer.keys().cloned().collect::<Vec<_>>();
@@ -0,0 +1,54 @@
+use log::info;
+use message::{FileInfo, FileName};
+use std::collections::BTreeMap;
+use wg_2024::network::NodeId;
+
+/// Files uploaded to a media server, kept in memory and sorted by name
+#[derive(Clone, Debug)]
+pub struct MediaStore {
+    files: BTreeMap<FileName, (NodeId, Vec<u8>)>, // name -> owner and content
+    /// Largest file accepted, in bytes
+    pub max_file_size: usize,
+    /// Files stored at most, a new name past it is refused
+    pub max_files: usize,
+}
+
+impl Default for MediaStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MediaStore {
+    pub fn new() -> Self {
+        Self {
+            files: BTreeMap::new(),
+            max_file_size: 1 << 20,
+            max_files: 256,
+        }
+    }
+    /// Store `data` as `name` for `owner`, replacing the file with the same name.
+    /// `None` if the name is empty or the file doesn't fit
+    pub fn store(&mut self, owner: NodeId, name: FileName, data: Vec<u8>) -> Option<FileInfo> {
+        if name.is_empty() || data.len() > self.max_file_size {
+            return None;
+        }
+        if !self.files.contains_key(&name) && self.files.len() >= self.max_files {
+            return None;
+        }
+        info!("File {:?} of {} bytes stored for {:?}", name, data.len(), owner);
+        let info = FileInfo { name: name.clone(), size: data.len() as u64, owner };
+        self.files.insert(name, (owner, data));
+        Some(info)
+    }
+    pub fn get(&self, name: &FileName) -> Option<&[u8]> {
+        self.files.get(name).map(|(_, data)| data.as_slice())
+    }
+    /// Every stored file, sorted by name
+    pub fn list(&self) -> Vec<FileInfo> {
+        self.files
+            .iter()
+            .map(|(name, (owner, data))| FileInfo { name: name.clone(), size: data.len() as u64, owner: *owner })
+            .collect()
+    }
+}
@@ -1,14 +1,56 @@
 use log::{info, warn};
-use message::{ChatRequest, ChatResponse, RecvMessageWrapper, SentMessageWrapper};
-use std::collections::{HashMap, HashSet};
+use crate::media::MediaStore;
+use crate::text::DocumentStore;
+use crate::network::NetworkManager;
+use message::expiry::stale_keys;
+use message::{AbandonedSessions, BufferLimits, Capabilities, ChatRequest, ChatResponse, DroneSend, Features, FragmentationOptions, HistoryEntry, HostedClient, MessageId, Nonce, Presence, Profile, PublicKey, ReassemblyError, ReceiptStatus, RecvMessageWrapper, RoomName, SentMessageWrapper, ServerType};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, SystemTime};
 use wg_2024::network::{NodeId};
 use wg_2024::packet::{Ack, Fragment};
 
+/// Most entries sent in one `History` page
+const MAX_HISTORY_PAGE: usize = 100;
+
 #[derive(Clone, Debug)]
 pub struct ServerMessageManager {
     incoming_fragments: HashMap<(u64, NodeId), RecvMessageWrapper>,
     pub(crate) outgoing_packets: HashMap<u64, SentMessageWrapper>,
     registered_clients: HashSet<NodeId>,
+    known_clients: HashSet<NodeId>, // registered at least once, messages to them are queued while they are away
+    pending: HashMap<NodeId, VecDeque<ChatResponse>>, // recipient -> messages waiting for it
+    registration_missing: HashMap<NodeId, SystemTime>, // registered clients missing from the topology, and since when
+    completed: HashMap<(u64, NodeId), SystemTime>,
+    peers: HashMap<NodeId, Capabilities>, // capabilities agreed with each client after Hello
+    public_keys: HashMap<NodeId, PublicKey>, // published by each client, never used by the server
+    profiles: HashMap<NodeId, Profile>, // set by each client
+    presence: HashMap<NodeId, Presence>, // published by each registered client, Online if not
+    last_seen: HashMap<NodeId, SystemTime>, // last request of each known client
+    presence_watchers: HashSet<NodeId>, // registered clients that get the presence changes
+    rooms: HashMap<RoomName, HashSet<NodeId>>, // room -> members
+    history: HashMap<(NodeId, NodeId), VecDeque<HistoryEntry>>, // conversation -> relayed direct messages
+    last_history_seq: u64,
+    peer_clients: HashMap<NodeId, Vec<HostedClient>>, // other server -> clients it hosts
+    synced_peers: HashSet<NodeId>, // other servers that got the current list of our clients
+    peer_kinds: HashMap<NodeId, ServerType>, // other server -> kind it told us
+    announced: HashSet<NodeId>, // other servers that were told our kind since they were found
+    sync_due: bool, // our clients changed, or a chat server showed up, since the last sync
+    last_sync: SystemTime,
+    pub kind: ServerType,
+    pub media: MediaStore, // used only by media servers
+    pub documents: DocumentStore, // used only by text servers
+    pub capabilities: Capabilities,
+    pub fragmentation: FragmentationOptions,
+    pub limits: BufferLimits,
+    /// Registered clients missing from the topology for this long are unregistered
+    pub registration_grace: Duration,
+    /// Messages queued for each away recipient, the oldest are dropped
+    pub max_pending: usize,
+    /// Direct messages kept for each conversation, the oldest are dropped
+    pub max_history: usize,
+    /// The peers are synced at least this often, to follow the changes of the topology
+    pub sync_interval: Duration,
+    pub abandoned: AbandonedSessions,
 }
 
 impl Default for ServerMessageManager {
@@ -23,9 +65,70 @@ impl ServerMessageManager {
             incoming_fragments: HashMap::new(),
             outgoing_packets: HashMap::new(),
             registered_clients: HashSet::new(),
+            known_clients: HashSet::new(),
+            pending: HashMap::new(),
+            registration_missing: HashMap::new(),
+            completed: HashMap::new(),
+            peers: HashMap::new(),
+            public_keys: HashMap::new(),
+            profiles: HashMap::new(),
+            presence: HashMap::new(),
+            last_seen: HashMap::new(),
+            presence_watchers: HashSet::new(),
+            rooms: HashMap::new(),
+            history: HashMap::new(),
+            last_history_seq: 0,
+            peer_clients: HashMap::new(),
+            synced_peers: HashSet::new(),
+            peer_kinds: HashMap::new(),
+            announced: HashSet::new(),
+            sync_due: false,
+            last_sync: SystemTime::now(),
+            kind: ServerType::ChatServer,
+            media: MediaStore::new(),
+            documents: DocumentStore::new(),
+            capabilities: Capabilities::default(),
+            fragmentation: FragmentationOptions::default(),
+            limits: BufferLimits::default(),
+            registration_grace: Duration::from_secs(60),
+            max_pending: 64,
+            max_history: 1000,
+            sync_interval: Duration::from_secs(5),
+            abandoned: AbandonedSessions::default(),
         }
     }
-    pub fn store_fragment(&mut self, key: &(u64, NodeId), fragment: Fragment) {
+    /// Drop the messages that are stuck for too long or above the `limits` caps,
+    /// the abandoned ones are returned to be reported to the controller
+    pub fn collect_garbage(&mut self) -> (Vec<RecvMessageWrapper>, Vec<SentMessageWrapper>) {
+        let now = SystemTime::now();
+
+        let stale = stale_keys(&self.incoming_fragments, self.limits.recv_timeout, self.limits.max_recv, now);
+        let incoming: Vec<_> = stale
+            .iter()
+            .filter_map(|key| self.incoming_fragments.remove(key))
+            .collect();
+
+        let stale = stale_keys(&self.outgoing_packets, self.limits.sent_timeout, self.limits.max_sent, now);
+        let outgoing: Vec<_> = stale
+            .iter()
+            .filter_map(|session_id| self.outgoing_packets.remove(session_id))
+            .collect();
+
+        for key in stale_keys(&self.completed, self.limits.recv_timeout, usize::MAX, now) {
+            self.completed.remove(&key);
+        }
+
+        self.abandoned.recv += incoming.len() as u64;
+        self.abandoned.sent += outgoing.len() as u64;
+        (incoming, outgoing)
+    }
+    /// Store the fragment and return the data fragments rebuilt from parity, they must be acked too
+    ///
+    /// Late fragments of an already handled message (e.g. parity) are ignored.
+    pub fn store_fragment(&mut self, key: &(u64, NodeId), fragment: Fragment) -> Vec<u64> {
+        if self.completed.contains_key(key) {
+            return Vec::new();
+        }
         if !self.incoming_fragments.contains_key(key) {
             self.incoming_fragments.insert(
                 *key,
@@ -37,6 +140,7 @@ impl ServerMessageManager {
                 .unwrap()
                 .add_fragment(fragment);
         }
+        self.incoming_fragments.get_mut(key).unwrap().take_recovered()
     }
     pub fn is_registered(&self, client: &NodeId) -> bool {
         self.registered_clients.contains(client)
@@ -44,11 +148,26 @@ impl ServerMessageManager {
     pub fn are_all_fragment_arrived(&self, key: &(u64, NodeId)) -> bool {
         self.incoming_fragments
             .get(key)
-            .unwrap()
-            .is_all_fragments_arrived()
+            .is_some_and(|wrapper| wrapper.is_all_fragments_arrived())
+    }
+    /// Check the integrity of a complete message, if it is corrupted its fragments are
+    /// dropped so that the whole message can be received again
+    ///
+    /// The request is decoded here too, so the controller sees it in the `summary` of the wrapper.
+    pub fn verify_message(&mut self, key: &(u64, NodeId)) -> Result<(), ReassemblyError> {
+        let wrapper = self.incoming_fragments.get_mut(key).unwrap();
+        let result = wrapper.deserialize::<ChatRequest>().map(|_| ());
+        if matches!(&result, Err(e) if e.is_corruption()) {
+            wrapper.reset();
+        }
+        result
     }
     pub fn add_to_registered_client(&mut self, client: NodeId) {
+        self.known_clients.insert(client);
+        self.last_seen.insert(client, SystemTime::now());
         self.registered_clients.insert(client);
+        self.synced_peers.clear();
+        self.sync_due = true;
     }
     pub fn get_from_registered_client(&self, client: &NodeId) -> Option<&NodeId> {
         self.registered_clients.get(client)
@@ -56,35 +175,626 @@ impl ServerMessageManager {
     pub fn get_all_registered_clients(&self) -> Vec<NodeId> {
         self.registered_clients.iter().cloned().collect()
     }
+    /// Forget `client` and what it published, `false` if it was not registered
+    ///
+    /// The agreed capabilities are kept for the messages queued while it is away.
+    pub fn unregister(&mut self, client: &NodeId) -> bool {
+        self.registration_missing.remove(client);
+        self.synced_peers.clear();
+        self.sync_due = true;
+        self.public_keys.remove(client);
+        self.profiles.remove(client);
+        self.presence.remove(client);
+        self.presence_watchers.remove(client);
+        self.rooms.retain(|_, members| {
+            members.remove(client);
+            !members.is_empty()
+        });
+        self.registered_clients.remove(client)
+    }
+    /// Unregister the clients missing from `client_list`, the topology known by the server,
+    /// for longer than `registration_grace`. Returns the departed clients
+    pub fn expire_registrations(&mut self, client_list: &[NodeId]) -> Vec<NodeId> {
+        let now = SystemTime::now();
+        self.registration_missing.retain(|client, _| !client_list.contains(client));
+        for client in self.registered_clients.iter() {
+            if !client_list.contains(client) {
+                self.registration_missing.entry(*client).or_insert(now);
+            }
+        }
+
+        let expired = stale_keys(&self.registration_missing, self.registration_grace, usize::MAX, now);
+        for client in expired.iter() {
+            info!("Client {:?} is no longer in the topology, registration expired", client);
+            self.unregister(client);
+        }
+        expired
+    }
+    /// Tell the remaining registered clients that `departed` left
+    pub fn departure_notices(&mut self, departed: &[NodeId], last_session_id: &mut u64) -> Vec<SentMessageWrapper> {
+        let mut notices = Vec::new();
+        for client in self.get_all_registered_clients() {
+            for departed in departed {
+                notices.push(self.prepare_response(last_session_id, client, &ChatResponse::ClientLeft(*departed)));
+            }
+        }
+        for departed in departed {
+            notices.extend(self.presence_notices(*departed, last_session_id));
+        }
+        notices
+    }
+    /// Presence of `client` and its last request, `Offline` when it is not registered
+    fn presence_of(&self, client: NodeId) -> (NodeId, Presence, SystemTime) {
+        let presence = if self.is_registered(&client) {
+            self.presence.get(&client).copied().unwrap_or_default()
+        } else {
+            Presence::Offline
+        };
+        let last_seen = self.last_seen.get(&client).copied().unwrap_or(SystemTime::UNIX_EPOCH);
+        (client, presence, last_seen)
+    }
+    /// Presence of every client registered at least once, sorted by id
+    pub fn get_presences(&self) -> Vec<(NodeId, Presence, SystemTime)> {
+        let mut clients: Vec<_> = self.known_clients.iter().cloned().collect();
+        clients.sort();
+        clients.into_iter().map(|client| self.presence_of(client)).collect()
+    }
+    /// Tell the watchers, but `client` itself, its current presence
+    fn presence_notices(&mut self, client: NodeId, last_session_id: &mut u64) -> Vec<SentMessageWrapper> {
+        let msg = ChatResponse::Presence(vec![self.presence_of(client)]);
+        let mut watchers: Vec<_> = self.presence_watchers.iter().filter(|watcher| **watcher != client).cloned().collect();
+        watchers.sort();
+        watchers
+            .into_iter()
+            .map(|watcher| self.prepare_response(last_session_id, watcher, &msg))
+            .collect()
+    }
     pub fn insert_ack(&mut self, ack: Ack, session_id: &u64) {
-        self.outgoing_packets
-            .get_mut(session_id)
-            .unwrap()
-            .add_acked(ack.fragment_index);
+        // parity fragments can be acked after the message is complete
+        let Some(wrapper) = self.outgoing_packets.get_mut(session_id) else {
+            return;
+        };
+        wrapper.add_acked(ack.fragment_index);
 
-        if self
-            .outgoing_packets
-            .get(session_id)
-            .unwrap()
-            .is_all_fragment_acked()
-        {
+        if wrapper.is_all_fragment_acked() {
             self.outgoing_packets.remove(session_id);
         }
     }
+    /// Record that a fragment was lost, returns `true` if it has to be sent again
+    ///
+    /// Fragments dropped by a drone are not sent again while the destination can rebuild
+    /// them from parity, explicit requests of the destination are always satisfied.
+    pub fn fragment_lost(&mut self, session_id: &u64, index: u64, nack_source: NodeId) -> bool {
+        self.outgoing_packets
+            .get_mut(session_id)
+            .is_none_or(|wrapper| nack_source == wrapper.destination || wrapper.fragment_lost(index))
+    }
     pub fn get_outgoing_packet(&self, session_id: &u64) -> Option<&SentMessageWrapper> {
         self.outgoing_packets.get(session_id)
     }
     pub fn get_incoming_fragments(&self, key: &(u64, NodeId)) -> Option<RecvMessageWrapper> {
         self.incoming_fragments.get(key).cloned()
     }
+    /// Public keys of the registered clients that published one
+    pub fn get_registered_keys(&self) -> Vec<(NodeId, PublicKey)> {
+        self.registered_clients
+            .iter()
+            .filter_map(|client| self.public_keys.get(client).map(|public_key| (*client, *public_key)))
+            .collect()
+    }
+    /// Public keys of the clients registered on another server but not on this one
+    pub fn get_remote_keys(&self) -> Vec<(NodeId, PublicKey)> {
+        let remote = self.get_remote_clients();
+        let mut keys: Vec<_> = self.peer_clients
+            .values()
+            .flatten()
+            .filter(|hosted| remote.contains(&hosted.id))
+            .filter_map(|hosted| hosted.public_key.map(|public_key| (hosted.id, public_key)))
+            .collect();
+        keys.sort_by_key(|(client, _)| *client);
+        keys.dedup_by_key(|(client, _)| *client);
+        keys
+    }
+    /// Profiles of the registered clients that set one, sorted by id
+    pub fn get_directory(&self) -> Vec<(NodeId, Profile)> {
+        let mut directory: Vec<_> = self.registered_clients
+            .iter()
+            .filter_map(|client| self.profiles.get(client).map(|profile| (*client, profile.clone())))
+            .collect();
+        directory.sort_by_key(|(client, _)| *client);
+        directory
+    }
+    /// Rooms with their members, sorted by name
+    pub fn get_rooms(&self) -> Vec<(RoomName, Vec<NodeId>)> {
+        let mut rooms: Vec<_> = self.rooms
+            .iter()
+            .map(|(room, members)| {
+                let mut members: Vec<_> = members.iter().cloned().collect();
+                members.sort();
+                (room.clone(), members)
+            })
+            .collect();
+        rooms.sort();
+        rooms
+    }
+    pub fn get_room_members(&self, room: &RoomName) -> Option<&HashSet<NodeId>> {
+        self.rooms.get(room)
+    }
+    /// Remove `client` from `room`, the room is deleted when it gets empty. `false` if it was not a member
+    pub fn leave_room(&mut self, client: &NodeId, room: &RoomName) -> bool {
+        let Some(members) = self.rooms.get_mut(room) else {
+            return false;
+        };
+        let removed = members.remove(client);
+        if members.is_empty() {
+            self.rooms.remove(room);
+        }
+        removed
+    }
+    /// Updated `RoomList` for `source` and for the members of `room`
+    fn room_notices(&mut self, source: NodeId, room: &RoomName, last_session_id: &mut u64) -> Vec<SentMessageWrapper> {
+        let msg = ChatResponse::RoomList(self.get_rooms());
+        let mut destinations: Vec<_> = self.rooms.get(room).into_iter().flatten().cloned().collect();
+        if !destinations.contains(&source) {
+            destinations.push(source);
+        }
+        destinations
+            .into_iter()
+            .map(|destination| self.prepare_response(last_session_id, destination, &msg))
+            .collect()
+    }
+    /// Error to answer a message from `source` claiming to come from `from`
+    ///
+    /// The sender is the source of the fragments, not what the client claims.
+    fn check_sender(&self, source: NodeId, from: NodeId) -> Option<ChatResponse> {
+        if from != source {
+            warn!("Client {:?} tried to send a message as {:?}", source, from);
+            return Some(ChatResponse::ErrorWrongSender(from));
+        }
+        if !self.is_registered(&source) {
+            info!("Client {:?} not registered", source);
+            return Some(ChatResponse::ErrorWrongClientId(source));
+        }
+        None
+    }
+    fn is_reachable(&self, client: &NodeId, network: &NetworkManager) -> bool {
+        self.is_registered(client) && network.get_route(client).is_some()
+    }
+    /// Messages waiting for `client`
+    pub fn get_pending(&self, client: &NodeId) -> usize {
+        self.pending.get(client).map_or(0, |queue| queue.len())
+    }
+    /// Send `msg` from `source` to `to`, if `to` is known but away the message is queued
+    /// and `source` is told so with the `id` of the message
+    fn forward(&mut self, source: NodeId, to: NodeId, id: MessageId, msg: ChatResponse, network: &NetworkManager, last_session_id: &mut u64) -> Vec<SentMessageWrapper> {
+        if self.is_reachable(&to, network) {
+            return vec![self.prepare_response(last_session_id, to, &msg)];
+        }
+        if !self.known_clients.contains(&to) {
+            info!("Client {:?} not registered", to);
+            return vec![self.prepare_response(last_session_id, source, &ChatResponse::ErrorWrongClientId(to))];
+        }
+
+        info!("Client {:?} is away, message from {:?} queued", to, source);
+        self.queue(to, msg);
+        vec![self.prepare_response(last_session_id, source, &ChatResponse::MessageQueued { to, id })]
+    }
+    fn queue(&mut self, to: NodeId, msg: ChatResponse) {
+        let queue = self.pending.entry(to).or_default();
+        if queue.len() >= self.max_pending {
+            warn!("Too many messages queued for {:?}, the oldest is dropped", to);
+            queue.pop_front();
+        }
+        queue.push_back(msg);
+    }
+    fn conversation(a: NodeId, b: NodeId) -> (NodeId, NodeId) {
+        (a.min(b), a.max(b))
+    }
+    fn record_history(&mut self, from: NodeId, to: NodeId, msg: &ChatResponse) {
+        let id = match msg {
+            ChatResponse::MessageFrom { id, .. } | ChatResponse::EncryptedFrom { id, .. } => *id,
+            _ => return,
+        };
+        self.last_history_seq += 1;
+        let entries = self.history.entry(Self::conversation(from, to)).or_default();
+        if entries.len() >= self.max_history {
+            entries.pop_front();
+        }
+        entries.push_back(HistoryEntry {
+            seq: self.last_history_seq,
+            timestamp: SystemTime::now(),
+            to,
+            id,
+            message: msg.clone(),
+            edited: false,
+            deleted: false,
+        });
+    }
+    /// Apply the edit of the message `id` sent by `from` to `to`, or its deletion when `text` is
+    /// `None`: only a tombstone of a deleted message is kept
+    fn amend_history(&mut self, from: NodeId, to: NodeId, id: MessageId, text: Option<(Option<Nonce>, Vec<u8>)>) {
+        let Some(entry) = self.history
+            .get_mut(&Self::conversation(from, to))
+            .and_then(|entries| entries.iter_mut().find(|entry| entry.to == to && entry.id == id && !entry.deleted))
+        else {
+            info!("Message {:?} from {:?} to {:?} is not in the history", id, from, to);
+            return;
+        };
+        match text {
+            Some((nonce, message)) => {
+                entry.message = match nonce {
+                    Some(nonce) => ChatResponse::EncryptedFrom { from, nonce, message, id },
+                    None => ChatResponse::MessageFrom { from, message, id },
+                };
+                entry.edited = true;
+            }
+            None => {
+                entry.message = ChatResponse::MessageFrom { from, message: Vec::new(), id };
+                entry.deleted = true;
+            }
+        }
+    }
+    /// Up to `limit` entries of the conversation between `client` and `with` older than `before`,
+    /// oldest first, and whether there are older ones
+    pub fn get_history(&self, client: NodeId, with: NodeId, before: Option<u64>, limit: usize) -> (Vec<HistoryEntry>, bool) {
+        let Some(entries) = self.history.get(&Self::conversation(client, with)) else {
+            return (Vec::new(), false);
+        };
+        let older = entries
+            .iter()
+            .take_while(|entry| before.is_none_or(|before| entry.seq < before))
+            .count();
+        let first = older.saturating_sub(limit);
+        (entries.range(first..older).cloned().collect(), first > 0)
+    }
+    /// Clients known by this server, sorted by id, as sent to the other servers
+    pub fn get_hosted_clients(&self) -> Vec<HostedClient> {
+        let mut clients: Vec<_> = self.known_clients
+            .iter()
+            .map(|client| HostedClient {
+                id: *client,
+                registered: self.is_registered(client),
+                public_key: self.public_keys.get(client).copied(),
+            })
+            .collect();
+        clients.sort_by_key(|hosted| hosted.id);
+        clients
+    }
+    /// Clients registered on another server but not on this one, sorted by id
+    pub fn get_remote_clients(&self) -> Vec<NodeId> {
+        let mut clients: Vec<_> = self.peer_clients
+            .values()
+            .flatten()
+            .filter(|hosted| hosted.registered && !self.is_registered(&hosted.id))
+            .map(|hosted| hosted.id)
+            .collect();
+        clients.sort();
+        clients.dedup();
+        clients
+    }
+    /// Other server to forward the messages for `client` to, `None` if they are handled here
+    ///
+    /// A server where `client` is registered is preferred, then the lowest id.
+    pub fn host_of(&self, client: &NodeId) -> Option<NodeId> {
+        if self.is_registered(client) {
+            return None;
+        }
+        let (away, server) = self.peer_clients
+            .iter()
+            .filter_map(|(server, clients)| {
+                clients.iter().find(|hosted| hosted.id == *client).map(|hosted| (!hosted.registered, *server))
+            })
+            .min()?;
+        // queued here rather than on another server where it is away too
+        if away && self.known_clients.contains(client) {
+            return None;
+        }
+        Some(server)
+    }
+    fn is_hosted_by(&self, server: NodeId, client: NodeId) -> bool {
+        self.peer_clients
+            .get(&server)
+            .is_some_and(|clients| clients.iter().any(|hosted| hosted.id == client && hosted.registered))
+    }
+    /// Replace the clients hosted by `server`, `None` when it is gone, and tell the registered
+    /// clients which remote clients left
+    fn set_peer_clients(&mut self, server: NodeId, clients: Option<Vec<HostedClient>>, last_session_id: &mut u64) -> Vec<SentMessageWrapper> {
+        let before = self.get_remote_clients();
+        match clients {
+            Some(clients) => self.peer_clients.insert(server, clients),
+            None => self.peer_clients.remove(&server),
+        };
+        let after = self.get_remote_clients();
+        let departed: Vec<_> = before.into_iter().filter(|client| !after.contains(client)).collect();
+
+        let mut notices = Vec::new();
+        for client in self.get_all_registered_clients() {
+            for departed in departed.iter() {
+                notices.push(self.prepare_response(last_session_id, client, &ChatResponse::ClientLeft(*departed)));
+            }
+        }
+        notices
+    }
+    /// Whether our clients or the chat servers changed, or `sync_interval` passed, since the last sync
+    pub fn should_sync_peers(&self) -> bool {
+        self.kind == ServerType::ChatServer
+            && (self.sync_due || self.last_sync.elapsed().unwrap_or_default() > self.sync_interval)
+    }
+    /// Tell our kind to the `servers` found since the last sync, send our clients to the chat
+    /// servers that don't have the current list and forget the servers that are no longer in the topology
+    pub fn sync_peers(&mut self, servers: &[NodeId], network: &NetworkManager, last_session_id: &mut u64) -> Vec<SentMessageWrapper> {
+        if self.kind != ServerType::ChatServer {
+            return vec![];
+        }
+        self.sync_due = false;
+        self.last_sync = SystemTime::now();
+        let gone: Vec<_> = self.peer_clients.keys().filter(|server| !servers.contains(server)).cloned().collect();
+        let mut wrappers = Vec::new();
+        for server in gone {
+            info!("Server {:?} is no longer in the topology, its clients are forgotten", server);
+            wrappers.extend(self.set_peer_clients(server, None, last_session_id));
+        }
+        self.synced_peers.retain(|server| servers.contains(server));
+        self.peer_kinds.retain(|server, _| servers.contains(server));
+        self.announced.retain(|server| servers.contains(server));
+
+        let announce = ChatRequest::PeerKind(self.kind);
+        for server in servers {
+            if self.announced.contains(server) || network.get_route(server).is_none() {
+                continue;
+            }
+            self.announced.insert(*server);
+            wrappers.push(self.prepare_response(last_session_id, *server, &announce));
+        }
+
+        // media and text servers never tell their kind, they don't get the clients
+        let msg = ChatRequest::ServerClients(self.get_hosted_clients());
+        for server in servers {
+            if self.peer_kinds.get(server) != Some(&ServerType::ChatServer)
+                || self.synced_peers.contains(server)
+                || network.get_route(server).is_none() {
+                continue;
+            }
+            self.synced_peers.insert(*server);
+            wrappers.push(self.prepare_response(last_session_id, *server, &msg));
+        }
+        wrappers
+    }
+    /// Send `msg` to `to` if it is reachable, queue it if `to` is known but away
+    fn deliver(&mut self, to: NodeId, msg: ChatResponse, network: &NetworkManager, last_session_id: &mut u64) -> Vec<SentMessageWrapper> {
+        if self.is_reachable(&to, network) {
+            vec![self.prepare_response(last_session_id, to, &msg)]
+        }
+        else {
+            if self.known_clients.contains(&to) {
+                self.queue(to, msg);
+            }
+            vec![]
+        }
+    }
+    /// `msg` with the receipt `id`, only if `to` agreed on receipts and can read it
+    fn with_receipt(&self, to: NodeId, id: MessageId, msg: ChatResponse) -> ChatResponse {
+        if self.peers.get(&to).is_some_and(|agreed| agreed.supports(Features::RECEIPTS)) {
+            ChatResponse::WithReceipt { id, response: Box::new(msg) }
+        } else {
+            msg
+        }
+    }
+    /// Deliver a request that `server` forwarded for one of its clients, the sender is
+    /// answered by its own server
+    fn deliver_forwarded(&mut self, server: NodeId, request: ChatRequest, network: &NetworkManager, last_session_id: &mut u64) -> Vec<SentMessageWrapper> {
+        let (request, receipt) = match request {
+            ChatRequest::WithReceipt { id, request } => (*request, Some(id)),
+            ChatRequest::Broadcast { from, message } => {
+                return self.deliver_forwarded_broadcast(server, from, message, network, last_session_id);
+            }
+            request => (request, None),
+        };
+        let (from, to, msg) = match request {
+            ChatRequest::SendMessage { from, to, message, id } => {
+                (from, to, ChatResponse::MessageFrom { from, message: message.into_bytes(), id })
+            }
+            ChatRequest::SendEncrypted { from, to, nonce, message, id } => {
+                (from, to, ChatResponse::EncryptedFrom { from, nonce, message, id })
+            }
+            ChatRequest::Receipt { from, to, id, status } => (from, to, ChatResponse::Receipt { from, id, status }),
+            ChatRequest::Typing { from, to } => (from, to, ChatResponse::Typing(from)),
+            ChatRequest::EditMessage { from, to, id, nonce, message } => {
+                (from, to, ChatResponse::MessageEdited { from, id, nonce, message })
+            }
+            ChatRequest::DeleteMessage { from, to, id } => (from, to, ChatResponse::MessageDeleted { from, id }),
+            other => {
+                warn!("Server {:?} forwarded {:?}, it can't be delivered", server, other);
+                return vec![];
+            }
+        };
+        if !self.is_hosted_by(server, from) {
+            warn!("Server {:?} forwarded a message from {:?}, a client it doesn't host", server, from);
+            return vec![];
+        }
+        if !self.known_clients.contains(&to) {
+            info!("Client {:?} not registered, message forwarded by {:?} dropped", to, server);
+            return vec![];
+        }
+
+        match &msg {
+            ChatResponse::MessageEdited { id, nonce, message, .. } => self.amend_history(from, to, *id, Some((*nonce, message.clone()))),
+            ChatResponse::MessageDeleted { id, .. } => self.amend_history(from, to, *id, None),
+            _ => {}
+        }
+        match msg {
+            ChatResponse::Typing(_) if !self.is_reachable(&to, network) => vec![],
+            ChatResponse::Receipt { .. } | ChatResponse::Typing(_) | ChatResponse::MessageEdited { .. } | ChatResponse::MessageDeleted { .. } => {
+                self.deliver(to, msg, network, last_session_id)
+            }
+            msg => {
+                self.record_history(from, to, &msg);
+                let msg = match receipt {
+                    Some(id) => self.with_receipt(to, id, msg),
+                    None => msg,
+                };
+                self.deliver(to, msg, network, last_session_id)
+            }
+        }
+    }
+    /// Deliver a broadcast that `server` forwarded to the clients it would forward their messages
+    /// here: the ones registered on `server`, or on a server with a lower id, get it from there
+    fn deliver_forwarded_broadcast(&mut self, server: NodeId, from: NodeId, message: String, network: &NetworkManager, last_session_id: &mut u64) -> Vec<SentMessageWrapper> {
+        if !self.is_hosted_by(server, from) {
+            warn!("Server {:?} forwarded a broadcast from {:?}, a client it doesn't host", server, from);
+            return vec![];
+        }
+        let id = network.get_server_id();
+        let clients: Vec<_> = self
+            .get_all_registered_clients()
+            .into_iter()
+            .filter(|client| *client != from && !self.is_hosted_by(server, *client))
+            .filter(|client| !self.peer_clients.keys().any(|peer| *peer != server && *peer < id && self.is_hosted_by(*peer, *client)))
+            .filter(|client| network.get_route(client).is_some())
+            .collect();
+        let msg = ChatResponse::BroadcastFrom {
+            from,
+            message: message.into_bytes(),
+        };
+        clients
+            .into_iter()
+            .map(|client| self.prepare_response(last_session_id, client, &msg))
+            .collect()
+    }
+    /// Forward a SendMessage or SendEncrypted from `source`, `receipt` is the id of the message
+    /// when the sender wants receipts
+    fn relay(&mut self, source: NodeId, request: ChatRequest, receipt: Option<MessageId>, network: &NetworkManager, last_session_id: &mut u64) -> Vec<SentMessageWrapper> {
+        let (from, to, message_id, msg) = match &request {
+            ChatRequest::SendMessage { from, to, message, id } => {
+                (*from, *to, *id, ChatResponse::MessageFrom { from: *from, message: message.clone().into_bytes(), id: *id })
+            }
+            // blind relay, the body can be read only by the recipient
+            ChatRequest::SendEncrypted { from, to, nonce, message, id } => {
+                (*from, *to, *id, ChatResponse::EncryptedFrom { from: *from, nonce: *nonce, message: message.clone(), id: *id })
+            }
+            other => {
+                warn!("Client {:?} asked receipts for {:?}", source, other);
+                return vec![];
+            }
+        };
+        if let Some(error) = self.check_sender(source, from) {
+            return vec![self.prepare_response(last_session_id, source, &error)];
+        }
+        if let Some(server) = self.host_of(&to) {
+            return self.forward_to_peer(source, server, to, message_id, msg, request, receipt, last_session_id);
+        }
+        if self.known_clients.contains(&to) {
+            self.record_history(from, to, &msg);
+        }
+        let Some(id) = receipt.filter(|_| self.known_clients.contains(&to)) else {
+            return self.forward(source, to, message_id, msg, network, last_session_id);
+        };
+
+        // only clients that agreed on receipts can read them
+        let msg = self.with_receipt(to, id, msg);
+        let mut wrappers = self.forward(source, to, message_id, msg, network, last_session_id);
+        let accepted = ChatResponse::Receipt { from: to, id, status: ReceiptStatus::Accepted };
+        wrappers.push(self.prepare_response(last_session_id, source, &accepted));
+        wrappers
+    }
+    /// Relay an EditMessage or DeleteMessage from `source`, queued like the message it changes
+    /// if the recipient is away
+    ///
+    /// The history gets the change too, like the server of the recipient if it is another one.
+    fn amend(&mut self, source: NodeId, request: ChatRequest, network: &NetworkManager, last_session_id: &mut u64) -> Vec<SentMessageWrapper> {
+        let (from, to, id, text) = match &request {
+            ChatRequest::EditMessage { from, to, id, nonce, message } => (*from, *to, *id, Some((*nonce, message.clone()))),
+            ChatRequest::DeleteMessage { from, to, id } => (*from, *to, *id, None),
+            other => {
+                warn!("Client {:?} sent {:?} as a change of a message", source, other);
+                return vec![];
+            }
+        };
+        if let Some(error) = self.check_sender(source, from) {
+            return vec![self.prepare_response(last_session_id, source, &error)];
+        }
+        self.amend_history(from, to, id, text.clone());
+        let msg = match text {
+            Some((nonce, message)) => ChatResponse::MessageEdited { from, id, nonce, message },
+            None => ChatResponse::MessageDeleted { from, id },
+        };
+        if let Some(server) = self.host_of(&to) {
+            let request = ChatRequest::Forwarded(Box::new(request));
+            return vec![self.prepare_response(last_session_id, server, &request)];
+        }
+        if !self.known_clients.contains(&to) {
+            info!("Client {:?} not registered", to);
+            return vec![self.prepare_response(last_session_id, source, &ChatResponse::ErrorWrongClientId(to))];
+        }
+        self.deliver(to, msg, network, last_session_id)
+    }
+    /// Hand `request` from `source` to `server`, which hosts `to`, and answer `source` like
+    /// the message was relayed here
+    #[allow(clippy::too_many_arguments)]
+    fn forward_to_peer(&mut self, source: NodeId, server: NodeId, to: NodeId, id: MessageId, msg: ChatResponse, request: ChatRequest, receipt: Option<MessageId>, last_session_id: &mut u64) -> Vec<SentMessageWrapper> {
+        info!("Client {:?} is hosted by server {:?}, message from {:?} forwarded", to, server, source);
+        self.record_history(source, to, &msg);
+        let request = match receipt {
+            Some(id) => ChatRequest::WithReceipt { id, request: Box::new(request) },
+            None => request,
+        };
+        let mut wrappers = vec![self.prepare_response(last_session_id, server, &ChatRequest::Forwarded(Box::new(request)))];
+        if !self.is_hosted_by(server, to) {
+            wrappers.push(self.prepare_response(last_session_id, source, &ChatResponse::MessageQueued { to, id }));
+        }
+        if let Some(id) = receipt {
+            let accepted = ChatResponse::Receipt { from: to, id, status: ReceiptStatus::Accepted };
+            wrappers.push(self.prepare_response(last_session_id, source, &accepted));
+        }
+        wrappers
+    }
+    /// Send the queued messages of the recipients that are registered and have a route again
+    pub fn deliver_pending(&mut self, network: &NetworkManager, last_session_id: &mut u64) -> Vec<SentMessageWrapper> {
+        let ready: Vec<_> = self.pending
+            .keys()
+            .filter(|client| self.is_reachable(client, network))
+            .cloned()
+            .collect();
+
+        let mut wrappers = Vec::new();
+        for client in ready {
+            let queue = self.pending.remove(&client).unwrap_or_default();
+            info!("Client {:?} is back, delivering {} queued messages", client, queue.len());
+            for msg in queue {
+                wrappers.push(self.prepare_response(last_session_id, client, &msg));
+            }
+        }
+        wrappers
+    }
+    /// Capabilities agreed with `client`, `None` if it never sent a valid Hello
+    pub fn peer_capabilities(&self, client: &NodeId) -> Option<&Capabilities> {
+        self.peers.get(client)
+    }
+    /// Whether `client` sent a valid Hello agreeing on `feature`
+    fn agreed(&self, client: &NodeId, feature: Features) -> bool {
+        self.peers.get(client).is_some_and(|agreed| agreed.supports(feature))
+    }
+    /// Fragment `msg` for `destination` with the features agreed with it and keep it until it is acked
+    ///
+    /// `msg` is a ChatRequest only when `destination` is another server.
+    fn prepare_response<T: DroneSend>(&mut self, last_session_id: &mut u64, destination: NodeId, msg: &T) -> SentMessageWrapper {
+        *last_session_id += 1;
+        let session_id = *last_session_id;
+        let options = match self.peers.get(&destination) {
+            Some(capabilities) => capabilities.restrict(&self.fragmentation),
+            None => self.fragmentation,
+        };
+        let sent_msg_wrapper = SentMessageWrapper::from_message_with_options(session_id, destination, msg, &options);
+        self.outgoing_packets
+            .insert(session_id, sent_msg_wrapper.clone());
+        sent_msg_wrapper
+    }
     pub fn message_handling(
         &mut self,
         key: &(u64, NodeId),
-        session_id: u64,
-        client_list: &Vec<NodeId>,
-    ) -> Option<SentMessageWrapper> {
-
-        let sent_msg_wrapper;
+        last_session_id: &mut u64,
+        network: &NetworkManager,
+    ) -> Vec<SentMessageWrapper> {
 
         if let Some(message) = self
             .incoming_fragments
@@ -93,61 +803,411 @@ impl ServerMessageManager {
             .try_deserialize::<ChatRequest>()
         {
             self.incoming_fragments.remove(key);
+            self.completed.insert(*key, SystemTime::now());
 
-            match message {
-                ChatRequest::ClientList => {
-                    if !self.is_registered(&key.1) {
-                        info!("Client {:?} not registered", key.1);
-                        let msg = ChatResponse::ErrorWrongClientId(key.1);
-                        sent_msg_wrapper = SentMessageWrapper::from_message(session_id, key.1, &msg);
-                        self.outgoing_packets
-                            .insert(session_id, sent_msg_wrapper.clone());
-                        return Some(sent_msg_wrapper);
-                    }
-                    let client_list = self.get_all_registered_clients();
-                    let msg = ChatResponse::ClientList(client_list);
-                    sent_msg_wrapper = SentMessageWrapper::from_message(session_id, key.1, &msg);
-                    self.outgoing_packets
-                        .insert(session_id, sent_msg_wrapper.clone());
-                    Some(sent_msg_wrapper)
-                }
-                ChatRequest::Register(node_id) => {
-                    if client_list.contains(&node_id) {
-                        self.add_to_registered_client(node_id);
-                        info!("Client with {:?} id added to client list", node_id,);
-                        None
-                    }
-                    else {
-                        warn!("Client {:?} not discovered", node_id);
-                        None
-                    }
-                }
-                ChatRequest::SendMessage { from, to, message } => {
-                    if !self.is_registered(&key.1) || !self.is_registered(&to) {
-                        info!("Client {:?} not registered", to);
-                        let msg = ChatResponse::ErrorWrongClientId(to);
-                        sent_msg_wrapper = SentMessageWrapper::from_message(session_id, key.1, &msg);
-                        self.outgoing_packets
-                            .insert(session_id, sent_msg_wrapper.clone());
-                        return Some(sent_msg_wrapper);
-                    }
-                    
-                    sent_msg_wrapper = SentMessageWrapper::from_message(session_id, to, &ChatResponse::MessageFrom {
-                        from,
-                        message: message.into_bytes(),
-                    });
-                    
-                    self.outgoing_packets
-                        .insert(session_id, sent_msg_wrapper.clone());
-                    Some(sent_msg_wrapper)
-                }
-            }
+            self.request_handling(key, message, last_session_id, network)
         } else {
             warn!(
                 "Error during deserialization of message from {:?} with session id: {:?}",
                 key.1, key.0
             );
-            None
+            vec![]
+        }
+    }
+    fn request_handling(
+        &mut self,
+        key: &(u64, NodeId),
+        message: ChatRequest,
+        last_session_id: &mut u64,
+        network: &NetworkManager,
+    ) -> Vec<SentMessageWrapper> {
+        match self.kind {
+            ServerType::MediaServer => return self.media_handling(key.1, message, last_session_id),
+            ServerType::TextServer => return self.text_handling(key.1, message, last_session_id),
+            ServerType::ChatServer => {}
+        }
+        if self.is_registered(&key.1) {
+            self.last_seen.insert(key.1, SystemTime::now());
+        }
+        match message {
+            ChatRequest::ClientList => {
+                if !self.is_registered(&key.1) {
+                    info!("Client {:?} not registered", key.1);
+                    let msg = ChatResponse::ErrorWrongClientId(key.1);
+                    return vec![self.prepare_response(last_session_id, key.1, &msg)];
+                }
+                let mut client_list = self.get_all_registered_clients();
+                client_list.extend(self.get_remote_clients());
+                let msg = ChatResponse::ClientList(client_list);
+                vec![self.prepare_response(last_session_id, key.1, &msg)]
+            }
+            ChatRequest::Register(node_id) => {
+                if network.get_client_list().contains(&node_id) {
+                    self.add_to_registered_client(node_id);
+                    info!("Client with {:?} id added to client list", node_id,);
+                    let mut wrappers = self.presence_notices(node_id, last_session_id);
+                    wrappers.extend(self.deliver_pending(network, last_session_id));
+                    wrappers
+                }
+                else {
+                    warn!("Client {:?} not discovered", node_id);
+                    vec![]
+                }
+            }
+            ChatRequest::SendMessage { .. } | ChatRequest::SendEncrypted { .. } => {
+                self.relay(key.1, message, None, network, last_session_id)
+            }
+            ChatRequest::WithReceipt { id, request } => {
+                self.relay(key.1, *request, Some(id), network, last_session_id)
+            }
+            ChatRequest::Receipt { from, to, id, status } => {
+                if let Some(error) = self.check_sender(key.1, from) {
+                    return vec![self.prepare_response(last_session_id, key.1, &error)];
+                }
+
+                if let Some(server) = self.host_of(&to) {
+                    let request = ChatRequest::Forwarded(Box::new(ChatRequest::Receipt { from, to, id, status }));
+                    return vec![self.prepare_response(last_session_id, server, &request)];
+                }
+
+                // queued like a message, without telling the sender
+                self.deliver(to, ChatResponse::Receipt { from, id, status }, network, last_session_id)
+            }
+            ChatRequest::EditMessage { .. } | ChatRequest::DeleteMessage { .. } => {
+                self.amend(key.1, message, network, last_session_id)
+            }
+            ChatRequest::PublishKey(public_key) => {
+                // bound to the source, like the sender of a message
+                info!("Client {:?} published its public key", key.1);
+                self.public_keys.insert(key.1, public_key);
+                self.synced_peers.clear();
+                self.sync_due = true;
+                vec![]
+            }
+            ChatRequest::ClientKeys => {
+                if !self.is_registered(&key.1) {
+                    info!("Client {:?} not registered", key.1);
+                    let msg = ChatResponse::ErrorWrongClientId(key.1);
+                    return vec![self.prepare_response(last_session_id, key.1, &msg)];
+                }
+                let mut keys = self.get_registered_keys();
+                keys.extend(self.get_remote_keys());
+                let msg = ChatResponse::ClientKeys(keys);
+                vec![self.prepare_response(last_session_id, key.1, &msg)]
+            }
+            ChatRequest::SetProfile(profile) => {
+                if !self.is_registered(&key.1) {
+                    info!("Client {:?} not registered", key.1);
+                    let msg = ChatResponse::ErrorWrongClientId(key.1);
+                    return vec![self.prepare_response(last_session_id, key.1, &msg)];
+                }
+                // bound to the source, every registered client sees the new name
+                info!("Client {:?} is now {:?}", key.1, profile.name);
+                self.profiles.insert(key.1, profile);
+                let msg = ChatResponse::Directory(self.get_directory());
+                self.get_all_registered_clients()
+                    .into_iter()
+                    .map(|client| self.prepare_response(last_session_id, client, &msg))
+                    .collect()
+            }
+            ChatRequest::Directory => {
+                if !self.is_registered(&key.1) {
+                    info!("Client {:?} not registered", key.1);
+                    let msg = ChatResponse::ErrorWrongClientId(key.1);
+                    return vec![self.prepare_response(last_session_id, key.1, &msg)];
+                }
+                let msg = ChatResponse::Directory(self.get_directory());
+                vec![self.prepare_response(last_session_id, key.1, &msg)]
+            }
+            ChatRequest::SetPresence(presence) => {
+                if !self.is_registered(&key.1) {
+                    info!("Client {:?} not registered", key.1);
+                    let msg = ChatResponse::ErrorWrongClientId(key.1);
+                    return vec![self.prepare_response(last_session_id, key.1, &msg)];
+                }
+                // Offline is only set by the server
+                let presence = match presence {
+                    Presence::Offline => Presence::Away,
+                    presence => presence,
+                };
+                if self.presence.insert(key.1, presence).unwrap_or_default() == presence {
+                    return vec![];
+                }
+                info!("Client {:?} is now {:?}", key.1, presence);
+                self.presence_notices(key.1, last_session_id)
+            }
+            ChatRequest::WatchPresence => {
+                if !self.is_registered(&key.1) {
+                    info!("Client {:?} not registered", key.1);
+                    let msg = ChatResponse::ErrorWrongClientId(key.1);
+                    return vec![self.prepare_response(last_session_id, key.1, &msg)];
+                }
+                self.presence_watchers.insert(key.1);
+                let msg = ChatResponse::Presence(self.get_presences());
+                vec![self.prepare_response(last_session_id, key.1, &msg)]
+            }
+            ChatRequest::Typing { from, to } => {
+                if let Some(error) = self.check_sender(key.1, from) {
+                    return vec![self.prepare_response(last_session_id, key.1, &error)];
+                }
+                if let Some(server) = self.host_of(&to) {
+                    let request = ChatRequest::Forwarded(Box::new(ChatRequest::Typing { from, to }));
+                    vec![self.prepare_response(last_session_id, server, &request)]
+                }
+                else if self.is_reachable(&to, network) {
+                    vec![self.prepare_response(last_session_id, to, &ChatResponse::Typing(from))]
+                }
+                else {
+                    vec![]
+                }
+            }
+            ChatRequest::Unregister => {
+                if !self.unregister(&key.1) {
+                    info!("Client {:?} not registered", key.1);
+                    return vec![];
+                }
+                info!("Client {:?} left", key.1);
+                self.departure_notices(&[key.1], last_session_id)
+            }
+            ChatRequest::CreateRoom(_) | ChatRequest::JoinRoom(_) | ChatRequest::LeaveRoom(_) | ChatRequest::RoomList | ChatRequest::SendToRoom { .. }
+                if !self.agreed(&key.1, Features::GROUPS) => {
+                info!("Client {:?} asked for rooms without agreeing on them", key.1);
+                vec![self.prepare_response(last_session_id, key.1, &ChatResponse::ErrorIncompatible(self.capabilities))]
+            }
+            ChatRequest::CreateRoom(room) => {
+                if !self.is_registered(&key.1) {
+                    info!("Client {:?} not registered", key.1);
+                    let msg = ChatResponse::ErrorWrongClientId(key.1);
+                    return vec![self.prepare_response(last_session_id, key.1, &msg)];
+                }
+                if self.rooms.contains_key(&room) {
+                    info!("Room {:?} already exists", room);
+                    return vec![self.prepare_response(last_session_id, key.1, &ChatResponse::ErrorWrongRoom(room))];
+                }
+                info!("Client {:?} created room {:?}", key.1, room);
+                self.rooms.insert(room.clone(), HashSet::from([key.1]));
+                self.room_notices(key.1, &room, last_session_id)
+            }
+            ChatRequest::JoinRoom(room) => {
+                if !self.is_registered(&key.1) {
+                    info!("Client {:?} not registered", key.1);
+                    let msg = ChatResponse::ErrorWrongClientId(key.1);
+                    return vec![self.prepare_response(last_session_id, key.1, &msg)];
+                }
+                let Some(members) = self.rooms.get_mut(&room) else {
+                    info!("Room {:?} doesn't exist", room);
+                    return vec![self.prepare_response(last_session_id, key.1, &ChatResponse::ErrorWrongRoom(room))];
+                };
+                info!("Client {:?} joined room {:?}", key.1, room);
+                members.insert(key.1);
+                self.room_notices(key.1, &room, last_session_id)
+            }
+            ChatRequest::LeaveRoom(room) => {
+                if !self.leave_room(&key.1, &room) {
+                    info!("Client {:?} is not a member of room {:?}", key.1, room);
+                    return vec![self.prepare_response(last_session_id, key.1, &ChatResponse::ErrorWrongRoom(room))];
+                }
+                info!("Client {:?} left room {:?}", key.1, room);
+                self.room_notices(key.1, &room, last_session_id)
+            }
+            ChatRequest::RoomList => {
+                if !self.is_registered(&key.1) {
+                    info!("Client {:?} not registered", key.1);
+                    let msg = ChatResponse::ErrorWrongClientId(key.1);
+                    return vec![self.prepare_response(last_session_id, key.1, &msg)];
+                }
+                let msg = ChatResponse::RoomList(self.get_rooms());
+                vec![self.prepare_response(last_session_id, key.1, &msg)]
+            }
+            ChatRequest::SendToRoom { from, room, message } => {
+                if let Some(error) = self.check_sender(key.1, from) {
+                    return vec![self.prepare_response(last_session_id, key.1, &error)];
+                }
+                let members: Vec<_> = match self.rooms.get(&room) {
+                    Some(members) if members.contains(&from) => members
+                        .iter()
+                        .filter(|member| **member != from)
+                        .cloned()
+                        .collect(),
+                    _ => {
+                        info!("Client {:?} is not a member of room {:?}", from, room);
+                        return vec![self.prepare_response(last_session_id, key.1, &ChatResponse::ErrorWrongRoom(room))];
+                    }
+                };
+
+                // one message for each member, the sender excluded
+                let msg = ChatResponse::MessageFromRoom {
+                    room,
+                    from,
+                    message: message.into_bytes(),
+                };
+                members
+                    .into_iter()
+                    .map(|member| self.prepare_response(last_session_id, member, &msg))
+                    .collect()
+            }
+            ChatRequest::Broadcast { from, message } => {
+                if let Some(error) = self.check_sender(key.1, from) {
+                    return vec![self.prepare_response(last_session_id, key.1, &error)];
+                }
+                let (local, mut unreachable): (Vec<_>, Vec<_>) = self
+                    .get_all_registered_clients()
+                    .into_iter()
+                    .filter(|client| *client != from)
+                    .partition(|client| network.get_route(client).is_some());
+
+                // the clients registered only on other servers get it from the server that would
+                // get their messages, one copy each
+                let mut reached = local.clone();
+                let mut servers = HashSet::new();
+                for client in self.get_remote_clients().into_iter().filter(|client| *client != from) {
+                    match self.host_of(&client) {
+                        Some(server) if network.get_route(&server).is_some() => {
+                            servers.insert(server);
+                            reached.push(client);
+                        }
+                        _ => unreachable.push(client),
+                    }
+                }
+                reached.sort();
+                unreachable.sort();
+                info!("Broadcast from {:?} reached {:?}, unreachable {:?}", from, reached, unreachable);
+
+                // one message for each reachable client, then the report for the sender
+                let mut servers: Vec<_> = servers.into_iter().collect();
+                servers.sort();
+                let request = ChatRequest::Forwarded(Box::new(ChatRequest::Broadcast { from, message: message.clone() }));
+                let mut wrappers: Vec<_> = servers
+                    .into_iter()
+                    .map(|server| self.prepare_response(last_session_id, server, &request))
+                    .collect();
+                let msg = ChatResponse::BroadcastFrom {
+                    from,
+                    message: message.into_bytes(),
+                };
+                wrappers.extend(local
+                    .iter()
+                    .map(|client| self.prepare_response(last_session_id, *client, &msg)));
+                let report = ChatResponse::BroadcastReport { reached, unreachable };
+                wrappers.push(self.prepare_response(last_session_id, from, &report));
+                wrappers
+            }
+            ChatRequest::GetHistory { with, before, limit } => {
+                if !self.is_registered(&key.1) {
+                    info!("Client {:?} not registered", key.1);
+                    let msg = ChatResponse::ErrorWrongClientId(key.1);
+                    return vec![self.prepare_response(last_session_id, key.1, &msg)];
+                }
+                let limit = (limit as usize).clamp(1, MAX_HISTORY_PAGE);
+                let (entries, more) = self.get_history(key.1, with, before, limit);
+                let msg = ChatResponse::History { with, entries, more };
+                vec![self.prepare_response(last_session_id, key.1, &msg)]
+            }
+            ChatRequest::ServerClients(clients) => {
+                if !network.get_server_list().contains(&key.1) {
+                    warn!("Node {:?} sent its clients but it is not a server", key.1);
+                    return vec![];
+                }
+                info!("Server {:?} hosts {} clients", key.1, clients.len());
+                self.set_peer_clients(key.1, Some(clients), last_session_id)
+            }
+            ChatRequest::PeerKind(kind) => {
+                if !network.get_server_list().contains(&key.1) {
+                    warn!("Node {:?} told its kind but it is not a server", key.1);
+                    return vec![];
+                }
+                info!("Server {:?} is a {:?}", key.1, kind);
+                if self.peer_kinds.insert(key.1, kind) != Some(kind) {
+                    self.sync_due = true;
+                }
+                vec![]
+            }
+            ChatRequest::Forwarded(request) => {
+                if !network.get_server_list().contains(&key.1) {
+                    warn!("Node {:?} forwarded a message but it is not a server", key.1);
+                    return vec![];
+                }
+                self.deliver_forwarded(key.1, *request, network, last_session_id)
+            }
+            ChatRequest::UploadFile { .. } | ChatRequest::FileList | ChatRequest::DownloadFile(_) => {
+                info!("Client {:?} asked files to a chat server", key.1);
+                vec![]
+            }
+            ChatRequest::DocumentList | ChatRequest::GetDocument(_) => {
+                info!("Client {:?} asked documents to a chat server", key.1);
+                vec![]
+            }
+            ChatRequest::Hello(capabilities) => {
+                let msg = self.hello(key.1, &capabilities);
+                vec![self.prepare_response(last_session_id, key.1, &msg)]
+            }
+            ChatRequest::ServerType => {
+                vec![self.prepare_response(last_session_id, key.1, &ChatResponse::ServerType(self.kind))]
+            }
         }
     }
+    /// Agree on the capabilities of `source`, answered with Welcome or ErrorIncompatible
+    fn hello(&mut self, source: NodeId, capabilities: &Capabilities) -> ChatResponse {
+        match self.capabilities.negotiate(capabilities) {
+            Some(agreed) => {
+                info!("Client {:?} speaks protocol version {}, features {:?}", source, agreed.version, agreed.features);
+                self.peers.insert(source, agreed);
+                ChatResponse::Welcome(agreed)
+            }
+            None => {
+                warn!("Client {:?} is incompatible, its versions are {}..={}", source, capabilities.min_version, capabilities.version);
+                self.peers.remove(&source);
+                ChatResponse::ErrorIncompatible(self.capabilities)
+            }
+        }
+    }
+    /// Requests to a media server, the chat ones are ignored
+    fn media_handling(&mut self, source: NodeId, message: ChatRequest, last_session_id: &mut u64) -> Vec<SentMessageWrapper> {
+        let msg = match message {
+            ChatRequest::Hello(capabilities) => self.hello(source, &capabilities),
+            ChatRequest::ServerType => ChatResponse::ServerType(self.kind),
+            // nothing to register to, answered with the files
+            ChatRequest::Register(_) | ChatRequest::FileList => ChatResponse::FileList(self.media.list()),
+            ChatRequest::UploadFile { name, data } => match self.media.store(source, name.clone(), data) {
+                Some(info) => ChatResponse::FileStored(info),
+                None => {
+                    warn!("File {:?} from {:?} can't be stored", name, source);
+                    ChatResponse::ErrorWrongFile(name)
+                }
+            },
+            ChatRequest::DownloadFile(name) => match self.media.get(&name) {
+                Some(data) => ChatResponse::File { name, data: data.to_vec() },
+                None => {
+                    info!("File {:?} doesn't exist", name);
+                    ChatResponse::ErrorWrongFile(name)
+                }
+            },
+            _ => {
+                info!("Client {:?} sent a chat request to a media server", source);
+                return vec![];
+            }
+        };
+        vec![self.prepare_response(last_session_id, source, &msg)]
+    }
+    /// Requests to a text server, the chat and file ones are ignored
+    fn text_handling(&mut self, source: NodeId, message: ChatRequest, last_session_id: &mut u64) -> Vec<SentMessageWrapper> {
+        let msg = match message {
+            ChatRequest::Hello(capabilities) => self.hello(source, &capabilities),
+            ChatRequest::ServerType => ChatResponse::ServerType(self.kind),
+            // like a media server, answered with the documents
+            ChatRequest::Register(_) | ChatRequest::DocumentList => ChatResponse::DocumentList(self.documents.list()),
+            ChatRequest::GetDocument(name) => match self.documents.get(&name) {
+                Some(text) => ChatResponse::Document { name, text: text.to_string() },
+                None => {
+                    info!("Document {:?} doesn't exist", name);
+                    ChatResponse::ErrorWrongDocument(name)
+                }
+            },
+            _ => {
+                info!("Client {:?} sent a chat request to a text server", source);
+                return vec![];
+            }
+        };
+        vec![self.prepare_response(last_session_id, source, &msg)]
+    }
 }
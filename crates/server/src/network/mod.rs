@@ -1,4 +1,5 @@
 use log::{info, warn};
+use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::time::{Duration, SystemTime};
 use wg_2024::network::{NodeId, SourceRoutingHeader};
@@ -12,6 +13,7 @@ pub struct NetworkManager {
     pub(crate) topology: HashMap<NodeId, (HashSet<NodeId>, TotalSuccessfulPackets, TotalPackets)>,
     pub(crate) routes: HashMap<NodeId, Vec<NodeId>>,
     pub(crate) client_list: HashSet<NodeId>,
+    pub(crate) server_list: HashSet<NodeId>, // other servers, reachable but never crossed
     server_id: NodeId,
     pub(crate) n_errors: i64,
     pub(crate) n_dropped: i64,
@@ -27,6 +29,7 @@ impl NetworkManager {
             topology,
             routes: HashMap::new(),
             client_list: HashSet::new(),
+            server_list: HashSet::new(),
             server_id,
             n_errors: 0,
             n_dropped: 0,
@@ -36,24 +39,30 @@ impl NetworkManager {
     }
     pub fn update_topology(&mut self, flood_response: FloodResponse) {
         for n in 0..flood_response.path_trace.len() {
-            if !self.topology.contains_key(&flood_response.path_trace[n].0) && !(flood_response.path_trace[n].1 == NodeType::Server) {
-                    self.topology
-                        .insert(flood_response.path_trace[n].0, (HashSet::new(), 1.0, 1.0));
+            if let Entry::Vacant(entry) = self.topology.entry(flood_response.path_trace[n].0) {
+                    entry.insert((HashSet::new(), 1.0, 1.0));
                     if flood_response.path_trace[n].1 == NodeType::Client {
                         if !self.client_list.contains(&flood_response.path_trace[n].0) {
                             self.client_list.insert(flood_response.path_trace[n].0);
                         }
-                }
+                    }
+                    if flood_response.path_trace[n].1 == NodeType::Server {
+                        self.server_list.insert(flood_response.path_trace[n].0);
+                    }
+            }
+            // the other servers are leaves: they can be reached but routes don't cross them
+            if self.server_list.contains(&flood_response.path_trace[n].0) {
+                continue;
             }
             if n > 0 {
-                if flood_response.path_trace[n - 1].1 != NodeType::Server {
+                if flood_response.path_trace[n - 1].0 != self.server_id {
                     if let Some(node) = self.topology.get_mut(&flood_response.path_trace[n].0) {
                         node.0.insert(flood_response.path_trace[n - 1].0);
                     }
                 }
             }
             if n < flood_response.path_trace.len() -1 {
-                if flood_response.path_trace[n + 1].1 != NodeType::Server {
+                if flood_response.path_trace[n + 1].0 != self.server_id {
                     if let Some(node) = self.topology.get_mut(&flood_response.path_trace[n].0) {
                         node.0.insert(flood_response.path_trace[n + 1].0);
                     }
@@ -130,9 +139,11 @@ impl NetworkManager {
         if self.client_list.contains(&node) {
             self.client_list.remove(&node);
         }
+        self.server_list.remove(&node);
     }
 
-    fn calculate_path(&self, node_id: NodeId) -> Vec<NodeId> {
+    /// Most reliable path to `node_id`, `None` if it can't be reached
+    fn calculate_path(&self, node_id: NodeId) -> Option<Vec<NodeId>> {
         let mut path = vec![];
         let mut psp = HashMap::new();
         let mut dist = HashMap::new();
@@ -170,24 +181,40 @@ impl NetworkManager {
         current_node = node_id;
         while current_node != self.server_id {
             path.push(current_node);
-            current_node = *prev.get(&current_node).unwrap();
+            current_node = *prev.get(&current_node)?;
         }
 
         path.push(self.server_id);
         path.reverse();
 
-        path
+        Some(path)
+    }
+    fn update_route(&mut self, node_id: NodeId) -> bool {
+        match self.calculate_path(node_id) {
+            Some(path) => {
+                self.routes.insert(node_id, path);
+                true
+            }
+            None => {
+                warn!("No path to {}", node_id);
+                self.routes.remove(&node_id);
+                false
+            }
+        }
     }
 
     pub fn generate_all_routes(&mut self) {
-        for node in self.client_list.iter() {
-            self.routes.insert(*node, self.calculate_path(*node));
+        let nodes: Vec<_> = self.client_list.iter().chain(self.server_list.iter()).cloned().collect();
+        for node in nodes {
+            self.update_route(node);
         }
-        info!("Generated all routes to clients");
+        info!("Generated all routes to clients and servers");
     }
     fn generate_specific_route(&mut self, node_id: &NodeId) -> bool{
-        if self.client_list.contains(node_id) {
-            self.routes.insert(*node_id, self.calculate_path(*node_id));
+        if self.client_list.contains(node_id) || self.server_list.contains(node_id) {
+            if !self.update_route(*node_id) {
+                return false;
+            }
             info!("Generated route to {}", node_id);
             true
         }
@@ -196,19 +223,30 @@ impl NetworkManager {
             false
         }
     }
+    /// Whether `flood_interval` passed since the last flood or too many packets were lost,
+    /// asked on every tick of the server so the counters restart only with a new flood
     pub fn should_flood_request(&mut self) -> bool {
         let elapsed = self.start_time.elapsed().unwrap_or(Duration::from_secs(0));
 
-        let res = elapsed > self.flood_interval || self.n_errors == 7 || self.n_dropped == 5;
-        self.start_time = SystemTime::now();
-        self.n_errors = 0;
-        self.n_dropped = 0;
+        let res = elapsed > self.flood_interval || self.n_errors >= 7 || self.n_dropped >= 5;
+        if res {
+            self.start_time = SystemTime::now();
+            self.n_errors = 0;
+            self.n_dropped = 0;
+        }
 
         res
     }
     pub fn get_client_list(&self) -> Vec<NodeId> {
         self.client_list.iter().cloned().collect()
     }
+    pub fn get_server_id(&self) -> NodeId {
+        self.server_id
+    }
+    /// Other servers found by flooding
+    pub fn get_server_list(&self) -> Vec<NodeId> {
+        self.server_list.iter().cloned().collect()
+    }
     pub fn get_route(&self, dest: &NodeId) -> Option<Vec<NodeId>> {
         self.routes.get(dest).cloned()
     }
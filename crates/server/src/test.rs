@@ -4,8 +4,8 @@ mod tests {
     use std::collections::{HashMap, HashSet};
     use wg_2024::network::{NodeId, SourceRoutingHeader};
     use wg_2024::packet::{Ack, FloodRequest, FloodResponse, Fragment, Nack, NackType, NodeType, Packet, PacketType};
-    use message::{ChatRequest, ChatResponse, NodeCommand, NodeEvent};
-    use crate::ChatServer;
+    use message::{ChatRequest, ChatResponse, FileInfo, HostedClient, NodeCommand, NodeEvent, Presence, Profile, ServerType};
+    use crate::{ChatServer, DocumentStore};
 
     fn create_test_server() -> (ChatServer, Receiver<NodeEvent>, Sender<NodeCommand>, Sender<Packet>) {
         create_test_server_with_drone_topology(vec![])
@@ -55,6 +55,52 @@ mod tests {
 
         (server, controller_recv_events, controller_send_commands, packet_send_to_server)
     }
+    fn send_request(server: &mut ChatServer, source: NodeId, session_id: u64, request: &ChatRequest) {
+        let wrapper = message::SentMessageWrapper::from_message(session_id, server.id, request);
+        for fragment in wrapper.fragments {
+            server.packet_handler(Packet {
+                routing_header: SourceRoutingHeader::new(vec![source, 100, server.id], 2),
+                session_id,
+                pack_type: PacketType::MsgFragment(fragment),
+            });
+        }
+    }
+
+    // Messaggi creati dal server, con la destinazione e la risposta ricostruita
+    fn created_responses(events_recv: &Receiver<NodeEvent>) -> Vec<(NodeId, ChatResponse)> {
+        events_recv.try_iter()
+            .filter_map(|event| match event {
+                NodeEvent::CreateMessage(wrapper) => Some(wrapper),
+                _ => None,
+            })
+            .map(|wrapper| {
+                let mut recv = message::RecvMessageWrapper::new(wrapper.session_id, 1, wrapper.total_n_fragments);
+                wrapper.fragments.iter().for_each(|f| { recv.add_fragment(f.clone()); });
+                (wrapper.destination, recv.deserialize::<ChatResponse>().unwrap())
+            })
+            .collect()
+    }
+
+    // Richieste inviate al server `peer`, le altre destinazioni ricevono risposte
+    fn created_for_peer(events_recv: &Receiver<NodeEvent>, peer: NodeId) -> (Vec<ChatRequest>, Vec<(NodeId, ChatResponse)>) {
+        let mut requests = Vec::new();
+        let mut responses = Vec::new();
+        for event in events_recv.try_iter() {
+            let NodeEvent::CreateMessage(wrapper) = event else {
+                continue;
+            };
+            let mut recv = message::RecvMessageWrapper::new(wrapper.session_id, 1, wrapper.total_n_fragments);
+            wrapper.fragments.iter().for_each(|f| { recv.add_fragment(f.clone()); });
+            if wrapper.destination == peer {
+                requests.push(recv.deserialize::<ChatRequest>().unwrap());
+            }
+            else {
+                responses.push((wrapper.destination, recv.deserialize::<ChatResponse>().unwrap()));
+            }
+        }
+        (requests, responses)
+    }
+
     fn create_fragment(index: u64, total: u64, data: &str) -> Fragment {
         let mut fragment_data = [0u8; 128];
         let bytes = data.as_bytes();
@@ -168,6 +214,7 @@ mod tests {
             from: client_id,
             to: 6,
             message: "A".repeat(150), // Messaggio lungo che richiede più di un frammento
+            id: 0,
         };
         let msg_str = serde_json::to_string(&send_msg).unwrap();
         let msg_bytes = msg_str.as_bytes();
@@ -260,20 +307,22 @@ mod tests {
         server.server_message_manager.outgoing_packets.insert(session_id, wrapper);
 
         // Crea un NACK
-        let nack = Nack {
-            fragment_index: 0,
-            nack_type: NackType::Dropped,
-        };
-        let routing_header = SourceRoutingHeader::new(vec![client_id, drone_id, server.id], 0);
-        let packet = Packet {
-            routing_header,
+        let nack = |hops| Packet {
+            routing_header: SourceRoutingHeader::new(hops, 0),
             session_id,
-            pack_type: PacketType::Nack(nack),
+            pack_type: PacketType::Nack(Nack {
+                fragment_index: 0,
+                nack_type: NackType::Dropped,
+            }),
         };
 
-        server.packet_handler(packet);
+        // Il destinatario chiede di nuovo un messaggio corrotto, nessun drone lo ha perso
+        server.packet_handler(nack(vec![client_id, drone_id, server.id]));
+        assert_eq!(server.network_manager.n_dropped, 0);
+        assert_eq!(server.network_manager.topology[&drone_id].2, 1.0);
 
         // Verifica che il network manager abbia aggiornato le statistiche
+        server.packet_handler(nack(vec![drone_id, server.id]));
         assert!(server.network_manager.n_dropped > 0);
     }
 
@@ -354,6 +403,7 @@ mod tests {
             from: sender_id,
             to: receiver_id,
             message: "Hello World".to_string(),
+            id: 0,
         };
         let msg_str = serde_json::to_string(&send_msg).unwrap();
         let fragment = create_fragment(0, 1, &msg_str);
@@ -476,6 +526,7 @@ mod tests {
             from: sender_id,
             to: unknown_receiver_id,
             message: "Hello Unknown".to_string(),
+            id: 0,
         };
         let msg_str = serde_json::to_string(&send_msg).unwrap();
         let fragment = create_fragment(0, 1, &msg_str);
@@ -613,6 +664,7 @@ mod tests {
             from: sender_id,
             to: receiver_id,
             message: "Hello through drone!".to_string(),
+            id: 0,
         };
         let msg_str = serde_json::to_string(&send_msg).unwrap();
         let fragment = create_fragment(0, 1, &msg_str);
@@ -746,4 +798,761 @@ mod tests {
         // Verifica che le statistiche del drone siano state aggiornate
         assert!(server.network_manager.topology.get(&drone_id).unwrap().2 > 1.0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_corrupted_message_is_asked_again() {
+        let client_id = 5;
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![client_id]);
+        let session_id = 100;
+
+        // Corrompe il corpo del messaggio dopo l'header del frame
+        let wrapper = message::SentMessageWrapper::from_message(session_id, server.id, &ChatRequest::ClientList);
+        let mut fragment = wrapper.fragments[0].clone();
+        fragment.data[message::frame::FRAME_HEADER_LEN] ^= 0xFF;
+
+        let routing_header = SourceRoutingHeader::new(vec![client_id, 100, server.id], 2);
+        server.packet_handler(Packet {
+            routing_header,
+            session_id,
+            pack_type: PacketType::MsgFragment(fragment),
+        });
+
+        assert!(matches!(events_recv.try_recv(), Ok(NodeEvent::MessageCorrupted(_))));
+
+        // Nessun ACK, solo un NACK per ogni frammento
+        let buffered = server.server_buffer.get(&client_id).unwrap();
+        assert_eq!(buffered.len() as u64, wrapper.total_n_fragments);
+        assert!(buffered.iter().all(|p| matches!(p.pack_type, PacketType::Nack(Nack { nack_type: NackType::Dropped, .. }))));
+
+        // Il messaggio integro viene accettato
+        server.packet_handler(Packet {
+            routing_header: SourceRoutingHeader::new(vec![client_id, 100, server.id], 2),
+            session_id,
+            pack_type: PacketType::MsgFragment(wrapper.fragments[0].clone()),
+        });
+        assert!(server.server_message_manager.get_incoming_fragments(&(session_id, client_id)).is_none());
+    }
+
+    #[test]
+    fn test_fragment_rebuilt_from_parity_is_acked() {
+        let client_id = 5;
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![client_id]);
+        let session_id = 100;
+
+        let send_msg = ChatRequest::SendMessage {
+            from: client_id,
+            to: client_id,
+            message: (0..300).map(|i| char::from(b'a' + (i % 26) as u8)).collect(),
+            id: 0,
+        };
+        let options = message::FragmentationOptions { compress: false, parity_group: 4, ..Default::default() };
+        let wrapper = message::SentMessageWrapper::from_message_with_options(session_id, server.id, &send_msg, &options);
+        let n = wrapper.total_n_fragments as usize;
+
+        // Il frammento 0 viene perso, la parità lo ricostruisce
+        for fragment in wrapper.fragments.iter().skip(1) {
+            server.packet_handler(Packet {
+                routing_header: SourceRoutingHeader::new(vec![client_id, 100, server.id], 2),
+                session_id,
+                pack_type: PacketType::MsgFragment(fragment.clone()),
+            });
+        }
+
+        // Gli ACK non raggiungibili passano dal controller
+        let acked: HashSet<u64> = events_recv.try_iter()
+            .filter_map(|event| match event {
+                NodeEvent::ControllerShortcut(Packet { pack_type: PacketType::Ack(ack), .. }) => Some(ack.fragment_index),
+                _ => None,
+            })
+            .collect();
+        assert!((0..n as u64).all(|i| acked.contains(&i)));
+        assert!(server.server_message_manager.get_incoming_fragments(&(session_id, client_id)).is_none());
+    }
+
+    #[test]
+    fn test_dropped_fragment_with_parity_is_not_resent() {
+        let client_id = 5;
+        let (mut server, _, _, _) = create_test_server_with_drone_topology(vec![client_id]);
+        let drone_id = 100;
+        let session_id = 100;
+
+        let options = message::FragmentationOptions { compress: false, parity_group: 2, ..Default::default() };
+        let test_msg = ChatResponse::ClientList((0..=255).collect());
+        let wrapper = message::SentMessageWrapper::from_message_with_options(session_id, client_id, &test_msg, &options);
+        server.server_message_manager.outgoing_packets.insert(session_id, wrapper);
+
+        let server_id = server.id;
+        let nack = |fragment_index| Packet {
+            routing_header: SourceRoutingHeader::new(vec![drone_id, server_id], 0),
+            session_id,
+            pack_type: PacketType::Nack(Nack { fragment_index, nack_type: NackType::Dropped }),
+        };
+
+        server.packet_handler(nack(0));
+        assert!(server.server_buffer.is_empty());
+
+        // Seconda perdita nello stesso gruppo: il frammento viene rinviato
+        server.packet_handler(nack(1));
+        assert_eq!(server.server_buffer.get(&client_id).map(|b| b.len()), Some(1));
+    }
+
+    #[test]
+    fn test_stale_messages_are_abandoned() {
+        let client_id = 5;
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![client_id]);
+
+        // Messaggio incompleto in arrivo e messaggio in uscita mai confermato
+        server.server_message_manager.store_fragment(&(100, client_id), create_fragment(0, 2, "{"));
+        let wrapper = message::SentMessageWrapper::from_message(200, client_id, &ChatResponse::ClientList(vec![]));
+        server.server_message_manager.outgoing_packets.insert(200, wrapper);
+
+        server.collect_garbage();
+        assert!(events_recv.try_recv().is_err());
+
+        server.server_message_manager.limits.recv_timeout = std::time::Duration::ZERO;
+        server.server_message_manager.limits.sent_timeout = std::time::Duration::ZERO;
+        server.collect_garbage();
+
+        assert!(matches!(events_recv.try_recv(), Ok(NodeEvent::MessageRecvAbandoned(_))));
+        assert!(matches!(events_recv.try_recv(), Ok(NodeEvent::MessageSentAbandoned(_))));
+        assert!(server.server_message_manager.get_incoming_fragments(&(100, client_id)).is_none());
+        assert!(server.server_message_manager.get_outgoing_packet(&200).is_none());
+        assert_eq!(server.server_message_manager.abandoned.recv, 1);
+        assert_eq!(server.server_message_manager.abandoned.sent, 1);
+    }
+
+    #[test]
+    fn test_run_abandons_stale_messages_without_traffic() {
+        let client_id = 5;
+        let (mut server, events_recv, controller, _packets) = create_test_server_with_drone_topology(vec![client_id]);
+        server.server_message_manager.store_fragment(&(100, client_id), create_fragment(0, 2, "{"));
+        server.server_message_manager.limits.recv_timeout = std::time::Duration::from_millis(50);
+
+        // Nessun pacchetto arriva, il messaggio viene abbandonato comunque
+        let handle = std::thread::spawn(move || server.run());
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(2);
+        let mut abandoned = false;
+        while let Ok(event) = events_recv.recv_deadline(deadline) {
+            if matches!(event, NodeEvent::MessageRecvAbandoned(_)) {
+                abandoned = true;
+                break;
+            }
+        }
+        assert!(abandoned);
+
+        // Senza controller il server si ferma
+        drop(controller);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_hello_negotiates_capabilities() {
+        let client_id = 5;
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![client_id]);
+
+        // Client senza compressione: la versione è accettata, le funzionalità ridotte
+        let plain = message::Capabilities::new(message::Features::NONE);
+        send_request(&mut server, client_id, 100, &ChatRequest::Hello(plain));
+        match created_responses(&events_recv).as_slice() {
+            [(5, ChatResponse::Welcome(agreed))] => {
+                assert_eq!(agreed.version, message::PROTOCOL_VERSION);
+                assert!(!agreed.supports(message::Features::COMPRESSION));
+            }
+            other => panic!("Unexpected response {:?}", other),
+        }
+        let agreed = *server.server_message_manager.peer_capabilities(&client_id).unwrap();
+        assert_eq!(agreed.features, message::Features::NONE);
+
+        // Client che parla solo versioni future
+        let future = message::Capabilities { version: message::PROTOCOL_VERSION + 2, min_version: message::PROTOCOL_VERSION + 1, ..plain };
+        send_request(&mut server, client_id, 101, &ChatRequest::Hello(future));
+        assert!(matches!(created_responses(&events_recv).as_slice(), [(5, ChatResponse::ErrorIncompatible(_))]));
+        assert!(server.server_message_manager.peer_capabilities(&client_id).is_none());
+    }
+
+    #[test]
+    fn test_spoofed_sender_is_rejected() {
+        let sender_id = 5;
+        let victim_id = 6;
+        let receiver_id = 7;
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![sender_id, victim_id, receiver_id]);
+        let session_id = 100;
+
+        // Il client 5 si spaccia per il client 6
+        let send_msg = ChatRequest::SendMessage {
+            from: victim_id,
+            to: receiver_id,
+            message: "Hello from 6".to_string(),
+            id: 0,
+        };
+        send_request(&mut server, sender_id, session_id, &send_msg);
+
+        // Nessun inoltro al destinatario, l'errore torna al mittente reale
+        assert!(matches!(created_responses(&events_recv).as_slice(), [(5, ChatResponse::ErrorWrongSender(6))]));
+        assert!(server.server_message_manager.outgoing_packets.values().all(|w| w.destination != receiver_id));
+    }
+
+    #[test]
+    fn test_encrypted_message_is_relayed() {
+        let sender_id = 5;
+        let receiver_id = 6;
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![sender_id, receiver_id]);
+
+        // Le chiavi pubblicate vengono restituite ai client registrati
+        send_request(&mut server, sender_id, 100, &ChatRequest::PublishKey([5; 32]));
+        send_request(&mut server, receiver_id, 101, &ChatRequest::PublishKey([6; 32]));
+        send_request(&mut server, sender_id, 102, &ChatRequest::ClientKeys);
+        assert!(created_responses(&events_recv).iter().any(|(to, response)| *to == sender_id && matches!(response, ChatResponse::ClientKeys(_))));
+        let mut keys = server.server_message_manager.get_registered_keys();
+        keys.sort();
+        assert_eq!(keys, vec![(sender_id, [5; 32]), (receiver_id, [6; 32])]);
+
+        // Il corpo cifrato passa invariato
+        let request = ChatRequest::SendEncrypted { from: sender_id, to: receiver_id, nonce: [7; 24], message: vec![1, 2, 3], id: 4 };
+        send_request(&mut server, sender_id, 103, &request);
+        match created_responses(&events_recv).as_slice() {
+            [(6, ChatResponse::EncryptedFrom { from, nonce, message, id: 4 })] => {
+                assert_eq!(*from, sender_id);
+                assert_eq!(*nonce, [7; 24]);
+                assert_eq!(*message, vec![1, 2, 3]);
+            }
+            other => panic!("Unexpected response {:?}", other),
+        }
+
+        // Anche i messaggi cifrati sono legati al mittente reale
+        let spoofed = ChatRequest::SendEncrypted { from: receiver_id, to: sender_id, nonce: [7; 24], message: vec![1], id: 5 };
+        send_request(&mut server, sender_id, 104, &spoofed);
+        assert!(matches!(created_responses(&events_recv).as_slice(), [(5, ChatResponse::ErrorWrongSender(6))]));
+    }
+
+    #[test]
+    fn test_unregister_notifies_other_clients() {
+        let (leaving_id, other_id) = (5, 6);
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![leaving_id, other_id]);
+        let session_id = 100;
+
+        send_request(&mut server, leaving_id, session_id, &ChatRequest::Unregister);
+
+        assert!(!server.server_message_manager.is_registered(&leaving_id));
+        assert!(matches!(created_responses(&events_recv).as_slice(), [(6, ChatResponse::ClientLeft(5))]));
+    }
+
+    #[test]
+    fn test_registration_expires_out_of_topology() {
+        let (gone_id, other_id) = (5, 6);
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![gone_id, other_id]);
+
+        // Il client sparisce dalla topologia, la registrazione scade dopo il periodo di grazia
+        server.network_manager.remove_node(gone_id);
+        server.expire_registrations();
+        assert!(server.server_message_manager.is_registered(&gone_id));
+
+        server.server_message_manager.registration_grace = std::time::Duration::ZERO;
+        server.expire_registrations();
+        assert!(!server.server_message_manager.is_registered(&gone_id));
+        assert!(server.server_message_manager.is_registered(&other_id));
+
+        let destinations: Vec<_> = events_recv.try_iter()
+            .filter_map(|event| match event {
+                NodeEvent::CreateMessage(wrapper) => Some(wrapper.destination),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(destinations, vec![other_id]);
+
+        // Un client ricomparso non viene rimosso
+        server.expire_registrations();
+        assert!(server.server_message_manager.is_registered(&other_id));
+    }
+
+    #[test]
+    fn test_room_message_is_sent_to_every_member() {
+        let (owner_id, member_id, outsider_id) = (5, 6, 7);
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![owner_id, member_id, outsider_id]);
+        let room = "demo".to_string();
+
+        // Senza Hello le stanze non sono state concordate
+        send_request(&mut server, owner_id, 90, &ChatRequest::CreateRoom(room.clone()));
+        assert!(matches!(created_responses(&events_recv)[..], [(5, ChatResponse::ErrorIncompatible(_))]));
+        assert!(server.server_message_manager.get_rooms().is_empty());
+
+        let capabilities = message::Capabilities::default();
+        for (session_id, client_id) in [(91, owner_id), (92, member_id), (93, outsider_id)] {
+            send_request(&mut server, client_id, session_id, &ChatRequest::Hello(capabilities));
+        }
+        created_responses(&events_recv);
+
+        // Chi crea la stanza ne è il primo membro
+        send_request(&mut server, owner_id, 100, &ChatRequest::CreateRoom(room.clone()));
+        let responses = created_responses(&events_recv);
+        assert_eq!(responses.len(), 1);
+        assert!(matches!(&responses[0], (5, ChatResponse::RoomList(rooms)) if rooms == &vec![(room.clone(), vec![owner_id])]));
+
+        send_request(&mut server, owner_id, 101, &ChatRequest::CreateRoom(room.clone()));
+        assert!(matches!(created_responses(&events_recv)[..], [(5, ChatResponse::ErrorWrongRoom(_))]));
+
+        // Chi entra riceve la lista aggiornata insieme agli altri membri
+        send_request(&mut server, member_id, 102, &ChatRequest::JoinRoom(room.clone()));
+        let mut destinations: Vec<_> = created_responses(&events_recv).into_iter().map(|(destination, _)| destination).collect();
+        destinations.sort();
+        assert_eq!(destinations, vec![owner_id, member_id]);
+
+        // Un messaggio alla stanza arriva a ogni membro tranne il mittente
+        let request = ChatRequest::SendToRoom { from: owner_id, room: room.clone(), message: "ciao".to_string() };
+        send_request(&mut server, owner_id, 103, &request);
+        let responses = created_responses(&events_recv);
+        assert_eq!(responses.len(), 1);
+        match &responses[0] {
+            (6, ChatResponse::MessageFromRoom { room: name, from, message }) => {
+                assert_eq!(name, &room);
+                assert_eq!(*from, owner_id);
+                assert_eq!(message, b"ciao");
+            }
+            other => panic!("Unexpected response {:?}", other),
+        }
+
+        // Chi non è membro non può scrivere nella stanza
+        let request = ChatRequest::SendToRoom { from: outsider_id, room: room.clone(), message: "ciao".to_string() };
+        send_request(&mut server, outsider_id, 104, &request);
+        assert!(matches!(created_responses(&events_recv)[..], [(7, ChatResponse::ErrorWrongRoom(_))]));
+
+        // La stanza vuota viene eliminata
+        send_request(&mut server, member_id, 105, &ChatRequest::LeaveRoom(room.clone()));
+        send_request(&mut server, owner_id, 106, &ChatRequest::LeaveRoom(room.clone()));
+        assert!(server.server_message_manager.get_rooms().is_empty());
+    }
+
+    #[test]
+    fn test_broadcast_reports_unreachable_clients() {
+        let (sender_id, other_id, unreachable_id) = (5, 6, 9);
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![sender_id, other_id]);
+        // Registrato ma senza percorso
+        server.server_message_manager.add_to_registered_client(unreachable_id);
+
+        let request = ChatRequest::Broadcast { from: sender_id, message: "a tutti".to_string() };
+        send_request(&mut server, sender_id, 100, &request);
+        let responses = created_responses(&events_recv);
+        assert_eq!(responses.len(), 2);
+        match &responses[0] {
+            (6, ChatResponse::BroadcastFrom { from, message }) => {
+                assert_eq!(*from, sender_id);
+                assert_eq!(message, b"a tutti");
+            }
+            other => panic!("Unexpected response {:?}", other),
+        }
+        match &responses[1] {
+            (5, ChatResponse::BroadcastReport { reached, unreachable }) => {
+                assert_eq!(reached, &vec![other_id]);
+                assert_eq!(unreachable, &vec![unreachable_id]);
+            }
+            other => panic!("Unexpected response {:?}", other),
+        }
+
+        // Il mittente è verificato come per SendMessage
+        let spoofed = ChatRequest::Broadcast { from: other_id, message: "a tutti".to_string() };
+        send_request(&mut server, sender_id, 101, &spoofed);
+        assert!(matches!(created_responses(&events_recv)[..], [(5, ChatResponse::ErrorWrongSender(6))]));
+    }
+
+    #[test]
+    fn test_message_to_away_client_is_queued() {
+        let (sender_id, away_id) = (5, 6);
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![sender_id, away_id]);
+        send_request(&mut server, away_id, 100, &ChatRequest::Unregister);
+        created_responses(&events_recv);
+
+        // Il destinatario è conosciuto ma assente, il mittente viene avvisato
+        let request = ChatRequest::SendMessage { from: sender_id, to: away_id, message: "ci sei?".to_string(), id: 4 };
+        send_request(&mut server, sender_id, 101, &request);
+        assert!(matches!(created_responses(&events_recv)[..], [(5, ChatResponse::MessageQueued { to: 6, id: 4 })]));
+        assert_eq!(server.server_message_manager.get_pending(&away_id), 1);
+
+        // Un destinatario mai registrato resta un errore
+        let request = ChatRequest::SendMessage { from: sender_id, to: 45, message: "ci sei?".to_string(), id: 1 };
+        send_request(&mut server, sender_id, 102, &request);
+        assert!(matches!(created_responses(&events_recv)[..], [(5, ChatResponse::ErrorWrongClientId(45))]));
+
+        // Al ritorno il messaggio viene consegnato
+        send_request(&mut server, away_id, 103, &ChatRequest::Register(away_id));
+        match &created_responses(&events_recv)[..] {
+            [(6, ChatResponse::MessageFrom { from, message, id: 4 })] => {
+                assert_eq!(*from, sender_id);
+                assert_eq!(message, b"ci sei?");
+            }
+            other => panic!("Unexpected responses {:?}", other),
+        }
+        assert_eq!(server.server_message_manager.get_pending(&away_id), 0);
+    }
+
+    #[test]
+    fn test_receipts_are_relayed_to_the_sender() {
+        let (sender_id, receiver_id) = (5, 6);
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![sender_id, receiver_id]);
+        send_request(&mut server, sender_id, 100, &ChatRequest::Hello(message::Capabilities::default()));
+        send_request(&mut server, receiver_id, 100, &ChatRequest::Hello(message::Capabilities::default()));
+        created_responses(&events_recv);
+
+        // Il messaggio arriva con il suo id e il server conferma di averlo accettato
+        let send = ChatRequest::SendMessage { from: sender_id, to: receiver_id, message: "ciao".to_string(), id: 7 };
+        let request = ChatRequest::WithReceipt { id: 7, request: Box::new(send) };
+        send_request(&mut server, sender_id, 101, &request);
+        match &created_responses(&events_recv)[..] {
+            [(6, ChatResponse::WithReceipt { id: 7, response }), (5, ChatResponse::Receipt { from: 6, id: 7, status })] => {
+                assert!(matches!(response.as_ref(), ChatResponse::MessageFrom { from: 5, .. }));
+                assert_eq!(*status, message::ReceiptStatus::Accepted);
+            }
+            other => panic!("Unexpected responses {:?}", other),
+        }
+
+        // Le ricevute del destinatario tornano al mittente
+        let receipt = ChatRequest::Receipt { from: receiver_id, to: sender_id, id: 7, status: message::ReceiptStatus::Read };
+        send_request(&mut server, receiver_id, 102, &receipt);
+        assert!(matches!(
+            created_responses(&events_recv)[..],
+            [(5, ChatResponse::Receipt { from: 6, id: 7, status: message::ReceiptStatus::Read })]
+        ));
+
+        // Nessuna conferma per un destinatario sconosciuto
+        let send = ChatRequest::SendMessage { from: sender_id, to: 45, message: "ciao".to_string(), id: 8 };
+        send_request(&mut server, sender_id, 103, &ChatRequest::WithReceipt { id: 8, request: Box::new(send) });
+        assert!(matches!(created_responses(&events_recv)[..], [(5, ChatResponse::ErrorWrongClientId(45))]));
+    }
+
+    #[test]
+    fn test_history_is_paged() {
+        let (alice_id, bob_id) = (5, 6);
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![alice_id, bob_id]);
+        for (session_id, (from, to)) in [(alice_id, bob_id), (bob_id, alice_id), (alice_id, bob_id)].into_iter().enumerate() {
+            let request = ChatRequest::SendMessage { from, to, message: format!("messaggio {}", session_id), id: session_id as u64 };
+            send_request(&mut server, from, 100 + session_id as u64, &request);
+        }
+        created_responses(&events_recv);
+
+        // Le ultime due, dalla più vecchia, e ce ne sono altre
+        send_request(&mut server, bob_id, 110, &ChatRequest::GetHistory { with: alice_id, before: None, limit: 2 });
+        let oldest = match &created_responses(&events_recv)[..] {
+            [(6, ChatResponse::History { with: 5, entries, more: true })] => {
+                assert_eq!(entries.len(), 2);
+                assert_eq!(entries[0].to, alice_id);
+                assert!(matches!(&entries[1].message, ChatResponse::MessageFrom { message, .. } if message == b"messaggio 2"));
+                entries[0].seq
+            }
+            other => panic!("Unexpected responses {:?}", other),
+        };
+
+        // La pagina precedente contiene la prima
+        send_request(&mut server, alice_id, 111, &ChatRequest::GetHistory { with: bob_id, before: Some(oldest), limit: 2 });
+        match &created_responses(&events_recv)[..] {
+            [(5, ChatResponse::History { entries, more: false, .. })] => {
+                assert_eq!(entries.len(), 1);
+                assert!(matches!(&entries[0].message, ChatResponse::MessageFrom { message, .. } if message == b"messaggio 0"));
+            }
+            other => panic!("Unexpected responses {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_profile_is_sent_to_every_client() {
+        let (alice_id, bob_id, stranger_id) = (5, 6, 9);
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![alice_id, bob_id]);
+        let profile = Profile { name: "Alice".to_string(), status: "in pausa".to_string() };
+
+        // Tutti i client registrati ricevono la nuova directory
+        send_request(&mut server, alice_id, 120, &ChatRequest::SetProfile(profile.clone()));
+        let mut responses = created_responses(&events_recv);
+        responses.sort_by_key(|(destination, _)| *destination);
+        match &responses[..] {
+            [(5, ChatResponse::Directory(first)), (6, ChatResponse::Directory(second))] => {
+                assert_eq!(first, &vec![(alice_id, profile.clone())]);
+                assert_eq!(first, second);
+            }
+            other => panic!("Unexpected responses {:?}", other),
+        }
+
+        send_request(&mut server, bob_id, 121, &ChatRequest::Directory);
+        assert!(matches!(&created_responses(&events_recv)[..], [(6, ChatResponse::Directory(directory))] if directory.len() == 1));
+
+        // Un client non registrato non può impostare un profilo
+        send_request(&mut server, stranger_id, 122, &ChatRequest::SetProfile(profile));
+        assert!(matches!(&created_responses(&events_recv)[..], [(9, ChatResponse::ErrorWrongClientId(9))]));
+
+        // Il profilo sparisce con la registrazione
+        send_request(&mut server, alice_id, 123, &ChatRequest::Unregister);
+        created_responses(&events_recv);
+        send_request(&mut server, bob_id, 124, &ChatRequest::Directory);
+        assert!(matches!(&created_responses(&events_recv)[..], [(6, ChatResponse::Directory(directory))] if directory.is_empty()));
+    }
+
+    #[test]
+    fn test_presence_is_pushed_to_watchers() {
+        let (alice_id, bob_id) = (5, 6);
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![alice_id, bob_id]);
+
+        // Bob riceve lo stato di tutti i client conosciuti
+        send_request(&mut server, bob_id, 130, &ChatRequest::WatchPresence);
+        match &created_responses(&events_recv)[..] {
+            [(6, ChatResponse::Presence(presences))] => {
+                let states: Vec<_> = presences.iter().map(|(client, presence, _)| (*client, *presence)).collect();
+                assert_eq!(states, vec![(alice_id, Presence::Online), (bob_id, Presence::Online)]);
+            }
+            other => panic!("Unexpected responses {:?}", other),
+        }
+
+        // Solo i cambiamenti vengono inviati, e non a chi li ha fatti
+        send_request(&mut server, alice_id, 131, &ChatRequest::SetPresence(Presence::Away));
+        assert!(matches!(&created_responses(&events_recv)[..], [(6, ChatResponse::Presence(p))] if p[..] == [(5, Presence::Away, p[0].2)]));
+        send_request(&mut server, alice_id, 132, &ChatRequest::SetPresence(Presence::Away));
+        assert!(created_responses(&events_recv).is_empty());
+
+        // La notifica di scrittura arriva solo al destinatario
+        send_request(&mut server, alice_id, 133, &ChatRequest::Typing { from: alice_id, to: bob_id });
+        assert!(matches!(&created_responses(&events_recv)[..], [(6, ChatResponse::Typing(5))]));
+        send_request(&mut server, alice_id, 134, &ChatRequest::Typing { from: bob_id, to: alice_id });
+        assert!(matches!(&created_responses(&events_recv)[..], [(5, ChatResponse::ErrorWrongSender(6))]));
+
+        // Chi esce diventa offline, con l'ora dell'ultima richiesta
+        send_request(&mut server, alice_id, 135, &ChatRequest::Unregister);
+        match &created_responses(&events_recv)[..] {
+            [(6, ChatResponse::ClientLeft(5)), (6, ChatResponse::Presence(presences))] => {
+                assert!(matches!(presences[..], [(5, Presence::Offline, last_seen)] if last_seen > std::time::SystemTime::UNIX_EPOCH));
+            }
+            other => panic!("Unexpected responses {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_messages_are_forwarded_to_the_server_of_the_recipient() {
+        let (alice_id, bob_id, peer_id) = (5, 7, 2);
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![alice_id]);
+        // Un altro server dietro al drone, raggiungibile ma mai attraversato
+        server.network_manager.topology.insert(peer_id, (HashSet::new(), 1.0, 1.0));
+        server.network_manager.topology.get_mut(&100).unwrap().0.insert(peer_id);
+        server.network_manager.server_list.insert(peer_id);
+        server.network_manager.routes.insert(peer_id, vec![1, 100, peer_id]);
+
+        // Il server dice prima il suo tipo, i client vanno solo a chi risponde di essere un server chat
+        server.sync_peers();
+        let (requests, _) = created_for_peer(&events_recv, peer_id);
+        assert!(matches!(&requests[..], [ChatRequest::PeerKind(ServerType::ChatServer)]));
+        assert!(!server.server_message_manager.should_sync_peers());
+        send_request(&mut server, peer_id, 139, &ChatRequest::PeerKind(ServerType::ChatServer));
+        assert!(server.server_message_manager.should_sync_peers());
+
+        // I client del server vengono inviati una sola volta, finché non cambiano
+        server.sync_peers();
+        let (requests, _) = created_for_peer(&events_recv, peer_id);
+        assert!(matches!(&requests[..], [ChatRequest::ServerClients(clients)] if clients[..] == [HostedClient { id: alice_id, registered: true, public_key: None }]));
+        server.sync_peers();
+        assert!(created_for_peer(&events_recv, peer_id).0.is_empty());
+
+        // Bob è registrato sull'altro server e compare nella lista dei client
+        let bob = HostedClient { id: bob_id, registered: true, public_key: None };
+        send_request(&mut server, peer_id, 140, &ChatRequest::ServerClients(vec![bob]));
+        send_request(&mut server, alice_id, 141, &ChatRequest::ClientList);
+        assert!(matches!(&created_responses(&events_recv)[..], [(5, ChatResponse::ClientList(list))] if list[..] == [alice_id, bob_id]));
+
+        // Il messaggio per Bob passa dal suo server, Alice riceve la conferma da questo
+        let send = ChatRequest::SendMessage { from: alice_id, to: bob_id, message: "ciao".to_string(), id: 3 };
+        send_request(&mut server, alice_id, 142, &ChatRequest::WithReceipt { id: 3, request: Box::new(send) });
+        let (requests, responses) = created_for_peer(&events_recv, peer_id);
+        match &requests[..] {
+            [ChatRequest::Forwarded(request)] => {
+                assert!(matches!(&**request, ChatRequest::WithReceipt { id: 3, request } if matches!(&**request, ChatRequest::SendMessage { from: 5, to: 7, .. })));
+            }
+            other => panic!("Unexpected requests {:?}", other),
+        }
+        assert!(matches!(responses[..], [(5, ChatResponse::Receipt { from: 7, id: 3, .. })]));
+
+        // La risposta di Bob arriva ad Alice, ma solo se Bob è davvero sull'altro server
+        let reply = ChatRequest::SendMessage { from: bob_id, to: alice_id, message: "ciao!".to_string(), id: 0 };
+        send_request(&mut server, peer_id, 143, &ChatRequest::Forwarded(Box::new(reply)));
+        assert!(matches!(&created_responses(&events_recv)[..], [(5, ChatResponse::MessageFrom { from: 7, .. })]));
+        let forged = ChatRequest::SendMessage { from: 8, to: alice_id, message: "ciao".to_string(), id: 0 };
+        send_request(&mut server, peer_id, 144, &ChatRequest::Forwarded(Box::new(forged.clone())));
+        assert!(created_responses(&events_recv).is_empty());
+
+        // Un client non può fingersi un server
+        send_request(&mut server, alice_id, 145, &ChatRequest::Forwarded(Box::new(forged)));
+        assert!(created_responses(&events_recv).is_empty());
+
+        // Quando Bob esce dall'altro server, Alice viene avvisata
+        send_request(&mut server, peer_id, 146, &ChatRequest::ServerClients(vec![]));
+        assert!(matches!(&created_responses(&events_recv)[..], [(5, ChatResponse::ClientLeft(7))]));
+    }
+
+    #[test]
+    fn test_broadcast_reaches_the_clients_of_other_servers_once() {
+        let (alice_id, carol_id, bob_id, peer_id) = (5, 6, 7, 2);
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![alice_id, carol_id]);
+        server.network_manager.topology.insert(peer_id, (HashSet::new(), 1.0, 1.0));
+        server.network_manager.topology.get_mut(&100).unwrap().0.insert(peer_id);
+        server.network_manager.server_list.insert(peer_id);
+        server.network_manager.routes.insert(peer_id, vec![1, 100, peer_id]);
+
+        // Carol è registrata su entrambi i server, Bob solo sull'altro
+        let hosted = vec![
+            HostedClient { id: carol_id, registered: true, public_key: None },
+            HostedClient { id: bob_id, registered: true, public_key: None },
+        ];
+        send_request(&mut server, peer_id, 150, &ChatRequest::ServerClients(hosted));
+        created_responses(&events_recv);
+
+        // Carol la riceve da qui, Bob dal suo server: una sola richiesta inoltrata
+        let request = ChatRequest::Broadcast { from: alice_id, message: "a tutti".to_string() };
+        send_request(&mut server, alice_id, 151, &request);
+        let (requests, responses) = created_for_peer(&events_recv, peer_id);
+        match &requests[..] {
+            [ChatRequest::Forwarded(request)] => assert!(matches!(&**request, ChatRequest::Broadcast { from: 5, .. })),
+            other => panic!("Unexpected requests {:?}", other),
+        }
+        match &responses[..] {
+            [(6, ChatResponse::BroadcastFrom { from: 5, .. }), (5, ChatResponse::BroadcastReport { reached, unreachable })] => {
+                assert_eq!(reached, &vec![carol_id, bob_id]);
+                assert!(unreachable.is_empty());
+            }
+            other => panic!("Unexpected responses {:?}", other),
+        }
+
+        // Il broadcast di Bob arriva qui solo ad Alice, Carol lo riceve dal server di Bob
+        let request = ChatRequest::Broadcast { from: bob_id, message: "a tutti".to_string() };
+        send_request(&mut server, peer_id, 152, &ChatRequest::Forwarded(Box::new(request)));
+        assert!(matches!(&created_responses(&events_recv)[..], [(5, ChatResponse::BroadcastFrom { from: 7, .. })]));
+    }
+
+    #[test]
+    fn test_edits_and_deletions_are_relayed() {
+        let (alice_id, bob_id, peer_id) = (5, 6, 2);
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![alice_id, bob_id]);
+        // Carol è su un altro server dietro al drone
+        server.network_manager.topology.insert(peer_id, (HashSet::new(), 1.0, 1.0));
+        server.network_manager.topology.get_mut(&100).unwrap().0.insert(peer_id);
+        server.network_manager.server_list.insert(peer_id);
+        server.network_manager.routes.insert(peer_id, vec![1, 100, peer_id]);
+
+        // La modifica arriva al destinatario con l'id del messaggio
+        let edit = ChatRequest::EditMessage { from: alice_id, to: bob_id, id: 3, nonce: None, message: b"ciao!".to_vec() };
+        send_request(&mut server, alice_id, 150, &edit);
+        match &created_responses(&events_recv)[..] {
+            [(6, ChatResponse::MessageEdited { from: 5, id: 3, nonce: None, message })] => assert_eq!(message, b"ciao!"),
+            other => panic!("Unexpected responses {:?}", other),
+        }
+
+        // Solo il mittente può cambiare i suoi messaggi
+        send_request(&mut server, bob_id, 151, &ChatRequest::DeleteMessage { from: alice_id, to: bob_id, id: 3 });
+        assert!(matches!(created_responses(&events_recv)[..], [(6, ChatResponse::ErrorWrongSender(5))]));
+        send_request(&mut server, alice_id, 152, &ChatRequest::DeleteMessage { from: alice_id, to: 45, id: 3 });
+        assert!(matches!(created_responses(&events_recv)[..], [(5, ChatResponse::ErrorWrongClientId(45))]));
+
+        // Per un client di un altro server passa dal suo server
+        let carol = HostedClient { id: 7, registered: true, public_key: None };
+        send_request(&mut server, peer_id, 156, &ChatRequest::ServerClients(vec![carol]));
+        created_for_peer(&events_recv, peer_id);
+        send_request(&mut server, alice_id, 157, &ChatRequest::DeleteMessage { from: alice_id, to: 7, id: 4 });
+        let (requests, _) = created_for_peer(&events_recv, peer_id);
+        assert!(matches!(&requests[..], [ChatRequest::Forwarded(request)] if matches!(**request, ChatRequest::DeleteMessage { from: 5, to: 7, id: 4 })));
+
+        // E le modifiche dei suoi client arrivano qui
+        let edit = ChatRequest::EditMessage { from: 7, to: alice_id, id: 1, nonce: None, message: b"ok".to_vec() };
+        send_request(&mut server, peer_id, 158, &ChatRequest::Forwarded(Box::new(edit)));
+        assert!(matches!(created_responses(&events_recv)[..], [(5, ChatResponse::MessageEdited { from: 7, id: 1, .. })]));
+
+        // Se il destinatario è assente la cancellazione aspetta il suo ritorno
+        send_request(&mut server, bob_id, 153, &ChatRequest::Unregister);
+        created_responses(&events_recv);
+        send_request(&mut server, alice_id, 154, &ChatRequest::DeleteMessage { from: alice_id, to: bob_id, id: 3 });
+        assert!(created_responses(&events_recv).is_empty());
+        send_request(&mut server, bob_id, 155, &ChatRequest::Register(bob_id));
+        assert!(matches!(created_responses(&events_recv)[..], [(6, ChatResponse::MessageDeleted { from: 5, id: 3 })]));
+    }
+
+    #[test]
+    fn test_history_keeps_edits_and_tombstones() {
+        let (alice_id, bob_id) = (5, 6);
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![alice_id, bob_id]);
+        for id in [1, 2] {
+            let request = ChatRequest::SendMessage { from: alice_id, to: bob_id, message: format!("messaggio {}", id), id };
+            send_request(&mut server, alice_id, 160 + id, &request);
+        }
+        let edit = ChatRequest::EditMessage { from: alice_id, to: bob_id, id: 1, nonce: None, message: b"ciao!".to_vec() };
+        send_request(&mut server, alice_id, 163, &edit);
+        send_request(&mut server, alice_id, 164, &ChatRequest::DeleteMessage { from: alice_id, to: bob_id, id: 2 });
+        created_responses(&events_recv);
+
+        // La cronologia mostra il testo modificato e della cancellazione resta solo la traccia
+        send_request(&mut server, bob_id, 165, &ChatRequest::GetHistory { with: alice_id, before: None, limit: 10 });
+        match &created_responses(&events_recv)[..] {
+            [(6, ChatResponse::History { entries, .. })] => {
+                assert_eq!(entries.len(), 2);
+                assert!(entries[0].edited && !entries[0].deleted);
+                assert!(matches!(&entries[0].message, ChatResponse::MessageFrom { from: 5, id: 1, message } if message == b"ciao!"));
+                assert!(entries[1].deleted);
+                assert!(matches!(&entries[1].message, ChatResponse::MessageFrom { id: 2, message, .. } if message.is_empty()));
+                assert_eq!((entries[0].id, entries[1].id), (1, 2));
+            }
+            other => panic!("Unexpected responses {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_media_server_stores_and_serves_files() {
+        let client_id = 5;
+        let (mut server, events_recv, _, _) = create_test_server_with_drone_topology(vec![client_id]);
+        // Ogni server dice che servizio offre, anche a chi non è registrato
+        send_request(&mut server, 9, 149, &ChatRequest::ServerType);
+        assert!(matches!(created_responses(&events_recv)[..], [(9, ChatResponse::ServerType(ServerType::ChatServer))]));
+        let mut server = server.with_kind(ServerType::MediaServer);
+        send_request(&mut server, client_id, 149, &ChatRequest::ServerType);
+        assert!(matches!(created_responses(&events_recv)[..], [(5, ChatResponse::ServerType(ServerType::MediaServer))]));
+
+        // La registrazione risponde con i file, vuota all'inizio
+        send_request(&mut server, client_id, 150, &ChatRequest::Register(client_id));
+        assert!(matches!(&created_responses(&events_recv)[..], [(5, ChatResponse::FileList(files))] if files.is_empty()));
+
+        // Un file di più frammenti viene ricostruito e salvato
+        let data: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        send_request(&mut server, client_id, 151, &ChatRequest::UploadFile { name: "foto.png".to_string(), data: data.clone() });
+        let stored = FileInfo { name: "foto.png".to_string(), size: 1000, owner: client_id };
+        assert!(matches!(&created_responses(&events_recv)[..], [(5, ChatResponse::FileStored(info))] if *info == stored));
+        send_request(&mut server, client_id, 152, &ChatRequest::FileList);
+        assert!(matches!(&created_responses(&events_recv)[..], [(5, ChatResponse::FileList(files))] if files[..] == [stored.clone()]));
+
+        send_request(&mut server, client_id, 153, &ChatRequest::DownloadFile("foto.png".to_string()));
+        assert!(matches!(&created_responses(&events_recv)[..], [(5, ChatResponse::File { name, data: file })] if name == "foto.png" && *file == data));
+
+        // File inesistenti o senza nome vengono rifiutati
+        send_request(&mut server, client_id, 154, &ChatRequest::DownloadFile("altro.png".to_string()));
+        assert!(matches!(&created_responses(&events_recv)[..], [(5, ChatResponse::ErrorWrongFile(name))] if name == "altro.png"));
+        send_request(&mut server, client_id, 155, &ChatRequest::UploadFile { name: String::new(), data });
+        assert!(matches!(&created_responses(&events_recv)[..], [(5, ChatResponse::ErrorWrongFile(_))]));
+
+        // Le richieste di chat vengono ignorate
+        send_request(&mut server, client_id, 156, &ChatRequest::ClientList);
+        assert!(created_responses(&events_recv).is_empty());
+    }
+
+    #[test]
+    fn test_text_server_lists_and_serves_documents() {
+        let client_id = 5;
+        let (server, events_recv, _, _) = create_test_server_with_drone_topology(vec![client_id]);
+        let mut documents = DocumentStore::new();
+        documents.insert("readme.txt".to_string(), "Benvenuti".to_string());
+        documents.insert("guida.txt".to_string(), "Capitolo 1".to_string());
+        let mut server = server.with_kind(ServerType::TextServer).with_documents(documents);
+        send_request(&mut server, client_id, 159, &ChatRequest::ServerType);
+        assert!(matches!(created_responses(&events_recv)[..], [(5, ChatResponse::ServerType(ServerType::TextServer))]));
+
+        // La registrazione risponde con i nomi dei documenti, in ordine
+        send_request(&mut server, client_id, 160, &ChatRequest::Register(client_id));
+        assert!(matches!(&created_responses(&events_recv)[..], [(5, ChatResponse::DocumentList(names))] if names[..] == ["guida.txt", "readme.txt"]));
+
+        send_request(&mut server, client_id, 161, &ChatRequest::GetDocument("readme.txt".to_string()));
+        assert!(matches!(&created_responses(&events_recv)[..], [(5, ChatResponse::Document { name, text })] if name == "readme.txt" && text == "Benvenuti"));
+        send_request(&mut server, client_id, 162, &ChatRequest::GetDocument("altro.txt".to_string()));
+        assert!(matches!(&created_responses(&events_recv)[..], [(5, ChatResponse::ErrorWrongDocument(name))] if name == "altro.txt"));
+
+        // Le richieste di chat e di file vengono ignorate
+        send_request(&mut server, client_id, 163, &ChatRequest::ClientList);
+        send_request(&mut server, client_id, 164, &ChatRequest::FileList);
+        assert!(created_responses(&events_recv).is_empty());
+    }
+}
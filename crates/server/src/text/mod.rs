@@ -0,0 +1,48 @@
+use log::{info, warn};
+use message::FileName;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Text documents served by a text server, sorted by name
+#[derive(Clone, Debug, Default)]
+pub struct DocumentStore {
+    documents: BTreeMap<FileName, String>,
+}
+
+impl DocumentStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Every `.txt` file of `dir`, named after the file. Files that are not UTF-8 are skipped
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let mut store = Self::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|extension| extension != "txt") {
+                continue;
+            }
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            match fs::read_to_string(&path) {
+                Ok(text) => store.insert(name.to_string(), text),
+                Err(e) => warn!("Document {:?} can't be read: {}", path, e),
+            }
+        }
+        info!("{} documents loaded", store.documents.len());
+        Ok(store)
+    }
+    /// Serve `text` as `name`, replacing the document with the same name
+    pub fn insert(&mut self, name: FileName, text: String) {
+        self.documents.insert(name, text);
+    }
+    pub fn get(&self, name: &FileName) -> Option<&str> {
+        self.documents.get(name).map(String::as_str)
+    }
+    /// Names of every document, sorted
+    pub fn list(&self) -> Vec<FileName> {
+        self.documents.keys().cloned().collect()
+    }
+}
@@ -4,6 +4,7 @@ use crossbeam_channel::{Receiver, Sender};
 use eframe::{egui, Frame};
 use initializer::start;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::process;
 use std::sync::{Arc, Mutex};
 use egui::Context;
@@ -25,6 +26,10 @@ fn main() -> eframe::Result {
     } else {
         "./crates/initializer/src/test_data/input11.toml".to_string()
     };
+    // the chats of every client are saved here and restored at the next run
+    let history_dir = args.get(2).cloned().unwrap_or_else(|| "./chat_history".to_string());
+    // when given, the events and commands of clients and servers are appended here as JSON lines
+    let record_path = args.get(3).map(PathBuf::from);
     
     let (to_ui,
         from_ui,
@@ -34,12 +39,12 @@ fn main() -> eframe::Result {
         message_sender,
         client_state_receiver,
         connections,
-        nodes) = start(&config_path).unwrap_or_else(|e| {  // ← PASSA IL PARAMETRO
+        nodes) = start(&config_path, record_path.as_deref()).unwrap_or_else(|e| {  // ← PASSA IL PARAMETRO
         eprintln!("Errore durante l'avvio del sistema con config '{}': {}", config_path, e);
         process::exit(1);
     });
 
-    let client_ui_state = _setup_ui_client_state(to_ui, from_ui);
+    let client_ui_state = _setup_ui_client_state(to_ui, from_ui, &history_dir);
 
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
@@ -135,8 +140,9 @@ impl eframe::App for App {
 fn _setup_ui_client_state(
     to_ui: HashMap<NodeId, (Sender<ToUICommunication>, Receiver<ToUICommunication>)>,
     from_ui: HashMap<NodeId, (Sender<FromUiCommunication>, Receiver<FromUiCommunication>)>,
+    history_dir: &str,
 ) -> UiState {
-    let mut ui_state = UiState::new();
+    let mut ui_state = UiState::new().with_history_dir(history_dir);
 
     for (node_id, (_tx_to_ui, rx_to_ui)) in to_ui {
         if let Some((tx_from_ui, _rx_from_ui_unused)) = from_ui.get(&node_id) {